@@ -2,79 +2,434 @@
 
 // aya-ebpf currently does not compile on Windows.
 #[cfg(target_os = "linux")]
-use aya_ebpf::TASK_COMM_LEN;
+pub use aya_ebpf::TASK_COMM_LEN;
 #[cfg(not(target_os = "linux"))]
-const TASK_COMM_LEN: usize = 16;
+pub const TASK_COMM_LEN: usize = 16;
 
-type Pid = u32;
+pub type Pid = u32;
 
-pub const INTERCEPT_CONF_LEN: u32 = 20;
+/// Maximum length of a process name. Longer process names in intercept specs are rejected.
+pub const NAME_LEN: usize = 64;
+/// Maximum length of an executable path we read on exec.
+pub const PATH_LEN: usize = 256;
 
+pub type Name = [u8; NAME_LEN];
+
+/// Maximum number of PID rules in an intercept spec.
+pub const PID_RULES_LEN: u32 = 4096;
+/// Maximum number of process name rules in an intercept spec.
+pub const NAME_RULES_LEN: u32 = 4096;
+/// Maximum number of process name rules with wildcards in an intercept spec.
+/// These need to be evaluated one by one, so we can only afford a few of them.
+pub const GLOB_RULES_LEN: u32 = 8;
+/// Maximum number of actions with destination patterns in an intercept spec.
+/// Like glob rules, these need to be evaluated one by one.
+pub const FLOW_RULES_LEN: u32 = 8;
+/// Maximum number of user, group, cgroup, network namespace and process tree rules in a spec.
+pub const ID_RULES_LEN: u32 = 1024;
+/// Number of cgroup levels (including the root cgroup) that are checked for cgroup rules.
+pub const CGROUP_LEVELS: usize = 8;
+/// Maximum number of processes for which we remember the executable name.
+pub const PROCESS_NAMES_LEN: u32 = 65536;
+/// Maximum number of processes for which we remember the parent process.
+pub const PARENTS_LEN: u32 = 65536;
+/// Number of ancestors (parent, grandparent, ...) that are checked for process tree rules.
+pub const TREE_DEPTH: usize = 16;
+/// Maximum number of sockets for which we remember the owning process.
+pub const SOCKET_OWNERS_LEN: u32 = 65536;
+
+/// The process that created an intercepted socket, keyed by socket cookie.
 #[derive(Copy, Clone, Debug)]
 #[repr(C)]
-pub enum Pattern {
-    Pid(Pid),
-    Process([u8; TASK_COMM_LEN]),
+pub struct SocketOwner {
+    pub pid: Pid,
+    /// The executable name, which unlike the comm is not truncated to 16 bytes.
+    pub name: Name,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The currently active intercept spec.
+///
+/// Rules are stored with a generation number, so that a new spec can be written
+/// next to the old one and then activated at once by bumping the generation here.
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct InterceptConfHeader {
+    pub generation: u32,
+    /// Non-zero if processes are intercepted when no rule matches.
+    pub default_intercept: u32,
+    /// Number of glob rules in the active half of the glob rule array.
+    pub glob_rules: u32,
+    /// Number of flow rules in the active half of the flow rule array.
+    /// If there are any, decisions are made on connect instead of on socket creation.
+    pub flow_rules: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum RuleAction {
+    Include,
+    Exclude,
+}
+
+/// The last action in an intercept spec for a given PID or name.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct Rule {
+    /// Position of the action in the intercept spec.
+    pub index: u32,
+    pub action: RuleAction,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct PidKey {
+    pub generation: u32,
+    pub pid: Pid,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u32)]
+pub enum IdKind {
+    Uid,
+    Gid,
+    Cgroup,
+    Netns,
+    /// A process and all of its descendants, keyed by the PID of the process.
+    Tree,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct IdKey {
+    pub generation: u32,
+    pub kind: IdKind,
+    pub id: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct NameKey {
+    pub generation: u32,
+    pub name: Name,
+}
+
+/// A process name pattern with wildcards, see [glob_match].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[repr(C)]
-pub enum Action {
-    None,
-    Include(Pattern),
-    Exclude(Pattern),
-}
-
-impl Pattern {
-    pub fn matches(&self, command: Option<&[u8; TASK_COMM_LEN]>, pid: Pid) -> bool {
-        match self {
-            Pattern::Pid(p) => pid == *p,
-            Pattern::Process(process) => {
-                let Some(command) = command else {
-                    return false;
-                };
-                // `command == process` inexplicably causes BPF verifier errors on Ubuntu 22.04,
-                // (it works on 24.04+), so we do a manual strcmp dance.
-                for i in 0..16 {
-                    let curr = command[i];
-                    if curr != process[i] {
-                        return false;
-                    }
-                    if curr == 0 {
-                        break;
-                    }
+pub struct GlobRule {
+    pub rule: Rule,
+    pub len: u32,
+    pub pattern: Name,
+}
+
+impl GlobRule {
+    pub fn pattern(&self) -> &[u8] {
+        self.pattern
+            .get(..self.len as usize)
+            .unwrap_or(&self.pattern)
+    }
+}
+
+/// Glob rules can't be keyed by generation, so the array holds two halves
+/// and each generation uses the one the previous generation did not use.
+pub fn glob_rule_index(generation: u32, i: u32) -> u32 {
+    (generation % 2) * GLOB_RULES_LEN + i
+}
+
+/// Flow rules are stored like glob rules, see [glob_rule_index].
+pub fn flow_rule_index(generation: u32, i: u32) -> u32 {
+    (generation % 2) * FLOW_RULES_LEN + i
+}
+
+/// The destination of a connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Flow {
+    /// IPv4 addresses are represented as IPv4-mapped IPv6 addresses.
+    pub addr: [u8; 16],
+    pub port: u16,
+    /// IPPROTO_TCP or IPPROTO_UDP.
+    pub protocol: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ProcessMatch {
+    Any,
+    Pid,
+    /// Match `id` against the process property of kind `id_kind`.
+    Id,
+    /// Match `name` as a glob pattern.
+    Name,
+}
+
+/// An action that has destination patterns, optionally combined with a single process pattern.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct FlowRule {
+    pub rule: Rule,
+    pub process: ProcessMatch,
+    pub id_kind: IdKind,
+    pub id: u64,
+    pub name_len: u32,
+    pub name: Name,
+    /// Destination network, IPv4 networks are IPv4-mapped. A prefix length of 0 matches everything.
+    pub addr: [u8; 16],
+    pub prefix_len: u8,
+    /// 0 matches all protocols.
+    pub protocol: u8,
+    pub port_min: u16,
+    pub port_max: u16,
+}
+
+impl FlowRule {
+    pub fn matches(&self, process: &Process, flow: &Flow) -> bool {
+        (self.protocol == 0 || self.protocol == flow.protocol)
+            && self.port_min <= flow.port
+            && flow.port <= self.port_max
+            && prefix_match(&self.addr, self.prefix_len, &flow.addr)
+            && self.matches_process(process)
+    }
+
+    fn matches_process(&self, process: &Process) -> bool {
+        match self.process {
+            ProcessMatch::Any => true,
+            ProcessMatch::Pid => process.pid as u64 == self.id,
+            ProcessMatch::Id => match self.id_kind {
+                IdKind::Uid => process.uid as u64 == self.id,
+                IdKind::Gid => process.gid as u64 == self.id,
+                IdKind::Netns => process.netns == self.id,
+                IdKind::Cgroup => process.cgroups.contains(&self.id),
+                IdKind::Tree => {
+                    process.pid as u64 == self.id
+                        || process
+                            .ancestors
+                            .iter()
+                            .any(|&a| a != 0 && a as u64 == self.id)
                 }
-                true
+            },
+            ProcessMatch::Name => {
+                let name = process
+                    .name
+                    .get(..name_len(&process.name))
+                    .unwrap_or(&process.name);
+                let pattern = self
+                    .name
+                    .get(..self.name_len as usize)
+                    .unwrap_or(&self.name);
+                glob_match(pattern, name)
+            }
+        }
+    }
+}
+
+/// Check if the first `prefix_len` bits of two addresses are equal.
+pub fn prefix_match(prefix: &[u8; 16], prefix_len: u8, addr: &[u8; 16]) -> bool {
+    for i in 0..16 {
+        let bits = (prefix_len as usize).saturating_sub(i * 8).min(8);
+        if bits == 0 {
+            break;
+        }
+        let mask = (0xff00u16 >> bits) as u8;
+        if (prefix[i] ^ addr[i]) & mask != 0 {
+            return false;
+        }
+    }
+    true
+}
+
+/// The properties of a process that intercept specs can match on.
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct Process {
+    pub pid: Pid,
+    pub uid: u32,
+    pub gid: u32,
+    /// Network namespace cookie.
+    pub netns: u64,
+    /// Ids of the process' cgroup and its ancestors, starting at the root cgroup.
+    /// Levels below the process' cgroup are zero.
+    pub cgroups: [u64; CGROUP_LEVELS],
+    /// PIDs of the process' parent, grandparent, and so on.
+    /// Entries after the oldest known ancestor are zero.
+    pub ancestors: [Pid; TREE_DEPTH],
+    pub name: Name,
+}
+
+/// Rule lookups for the active intercept spec.
+/// This is implemented on top of BPF maps in the eBPF program and on top of regular
+/// collections in userspace, so that the matching logic can be tested.
+pub trait RuleLookup {
+    fn default_intercept(&self) -> bool;
+    fn pid_rule(&self, pid: Pid) -> Option<Rule>;
+    fn id_rule(&self, kind: IdKind, id: u64) -> Option<Rule>;
+    fn name_rule(&self, name: &Name) -> Option<Rule>;
+    /// The i-th glob rule, ordered by index.
+    fn glob_rule(&self, i: u32) -> Option<&GlobRule>;
+    /// The i-th flow rule, ordered by index.
+    fn flow_rule(&self, i: u32) -> Option<&FlowRule>;
+    /// Match a process name against a glob rule's pattern.
+    /// The eBPF program runs the match in a `bpf_loop`, so that the verifier does not need
+    /// to walk through all [GLOB_STEPS] iterations for each rule.
+    fn glob_match(&self, pattern: &[u8], name: &[u8]) -> bool {
+        glob_match(pattern, name)
+    }
+}
+
+/// Decide whether a process should be intercepted.
+///
+/// Actions in an intercept spec are applied in order, with includes setting and excludes clearing
+/// the intercept flag. This means that only the last matching action matters, so instead of
+/// evaluating every action we look up the last matching action for each kind of pattern.
+///
+/// Flow rules are only evaluated if the destination is known.
+pub fn should_intercept(rules: &impl RuleLookup, process: &Process, flow: Option<&Flow>) -> bool {
+    let mut last = rules.pid_rule(process.pid);
+    last = later(last, rules.id_rule(IdKind::Tree, process.pid as u64));
+    for &ancestor in &process.ancestors {
+        if ancestor == 0 {
+            break;
+        }
+        last = later(last, rules.id_rule(IdKind::Tree, ancestor as u64));
+    }
+    last = later(last, rules.id_rule(IdKind::Uid, process.uid as u64));
+    last = later(last, rules.id_rule(IdKind::Gid, process.gid as u64));
+    last = later(last, rules.id_rule(IdKind::Netns, process.netns));
+    for &cgroup in &process.cgroups {
+        if cgroup == 0 {
+            break;
+        }
+        last = later(last, rules.id_rule(IdKind::Cgroup, cgroup));
+    }
+    last = later(last, rules.name_rule(&process.name));
+    let name = process
+        .name
+        .get(..name_len(&process.name))
+        .unwrap_or(&process.name);
+    for i in 0..GLOB_RULES_LEN {
+        let Some(glob) = rules.glob_rule(i) else {
+            break;
+        };
+        if last.is_none_or(|l| glob.rule.index > l.index) && rules.glob_match(glob.pattern(), name)
+        {
+            last = Some(glob.rule);
+        }
+    }
+    if let Some(flow) = flow {
+        for i in 0..FLOW_RULES_LEN {
+            let Some(flow_rule) = rules.flow_rule(i) else {
+                break;
+            };
+            if last.is_none_or(|l| flow_rule.rule.index > l.index)
+                && flow_rule.matches(process, flow)
+            {
+                last = Some(flow_rule.rule);
             }
         }
     }
+    match last {
+        Some(rule) => rule.action == RuleAction::Include,
+        None => rules.default_intercept(),
+    }
+}
+
+/// The rule that comes later in the intercept spec.
+fn later(a: Option<Rule>, b: Option<Rule>) -> Option<Rule> {
+    match (a, b) {
+        (Some(a), Some(b)) if b.index > a.index => Some(b),
+        (Some(a), _) => Some(a),
+        (None, b) => b,
+    }
+}
+
+/// Check if a process name matches a pattern.
+///
+/// Patterns match the entire name. `*` matches any sequence of bytes (including none),
+/// and `?` matches any single byte. All other bytes only match themselves.
+/// This is shared between all platforms, so that intercept specs have the same meaning everywhere.
+///
+/// The eBPF verifier only accepts loops with a known bound, so this gives up after [GLOB_STEPS]
+/// steps, which is enough for patterns and names of up to [NAME_LEN] bytes.
+/// Longer inputs need to be matched with [glob_match_steps].
+pub fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    glob_match_steps(pattern, name, GLOB_STEPS)
 }
 
-impl From<&str> for Action {
-    fn from(value: &str) -> Self {
-        let value = value.trim();
-        if let Some(value) = value.strip_prefix('!') {
-            Action::Exclude(Pattern::from(value))
-        } else {
-            Action::Include(Pattern::from(value))
+/// Number of steps [glob_match_steps] needs at most: every backtrack moves the last `*` one byte
+/// further into the name, and between two backtracks at most the whole pattern is consumed.
+pub const fn glob_steps(pattern_len: usize, name_len: usize) -> usize {
+    (pattern_len + 1) * (name_len + 2)
+}
+
+pub const GLOB_STEPS: usize = glob_steps(NAME_LEN, NAME_LEN);
+
+/// [glob_match] with a custom step limit, see [glob_steps].
+pub fn glob_match_steps(pattern: &[u8], name: &[u8], max_steps: usize) -> bool {
+    let mut state = GlobState::default();
+    for _ in 0..max_steps {
+        if let Some(matched) = state.step(pattern, name) {
+            return matched;
         }
     }
+    false
+}
+
+/// State of a glob match that is advanced one step at a time,
+/// so that the eBPF program can drive it with `bpf_loop`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GlobState {
+    p: usize,
+    n: usize,
+    /// Position after the last `*` and the position in name it currently matches up to.
+    backtrack: Option<(usize, usize)>,
 }
 
-impl From<&str> for Pattern {
-    fn from(value: &str) -> Self {
-        let value = value.trim();
-        match value.parse::<u32>() {
-            Ok(pid) => Pattern::Pid(pid),
-            Err(_) => {
-                let mut val = [0u8; TASK_COMM_LEN];
-                let src = value.as_bytes();
-                // This silently truncates to TASK_COMM_LEN - 1 bytes,
-                // bpf_get_current_comm always puts a null byte at the end.
-                let len = core::cmp::min(TASK_COMM_LEN - 1, src.len());
-                val[..len].copy_from_slice(&src[..len]);
-                Pattern::Process(val)
+impl GlobState {
+    /// Advance the match by one step. Returns the result once it is known.
+    pub fn step(&mut self, pattern: &[u8], name: &[u8]) -> Option<bool> {
+        match (pattern.get(self.p), name.get(self.n)) {
+            (Some(b'*'), _) => {
+                self.p += 1;
+                self.backtrack = Some((self.p, self.n));
+                return None;
             }
+            (Some(&c), Some(&x)) if c == b'?' || c == x => {
+                self.p += 1;
+                self.n += 1;
+                return None;
+            }
+            (None, None) => return Some(true),
+            _ => {}
+        }
+        // Mismatch: let the last `*` consume one more byte.
+        match self.backtrack {
+            Some((bp, bn)) if bn < name.len() => {
+                self.backtrack = Some((bp, bn + 1));
+                self.p = bp;
+                self.n = bn + 1;
+                None
+            }
+            _ => Some(false),
         }
     }
 }
+
+/// Whether a pattern needs to be evaluated with [glob_match] instead of a plain comparison.
+pub fn is_glob(pattern: &[u8]) -> bool {
+    pattern.iter().any(|&c| c == b'*' || c == b'?')
+}
+
+/// Length of a NUL-padded name.
+pub fn name_len(name: &Name) -> usize {
+    name.iter().position(|&c| c == 0).unwrap_or(NAME_LEN)
+}
+
+/// Convert a process name into its fixed-size representation,
+/// or return `None` if it is too long.
+pub fn name_from_bytes(value: &[u8]) -> Option<Name> {
+    if value.len() > NAME_LEN {
+        return None;
+    }
+    let mut name = [0u8; NAME_LEN];
+    name[..value.len()].copy_from_slice(value);
+    Some(name)
+}
@@ -1,50 +1,386 @@
 #![no_std]
 #![no_main]
 
-use aya_ebpf::macros::{cgroup_sock, map};
-use aya_ebpf::maps::Array;
-use aya_ebpf::programs::SockContext;
+use core::ffi::c_void;
+
+use aya_ebpf::helpers::gen::bpf_loop;
+use aya_ebpf::helpers::{
+    bpf_get_current_ancestor_cgroup_id, bpf_get_netns_cookie, bpf_get_socket_cookie,
+    bpf_probe_read_kernel_str_bytes, bpf_setsockopt,
+};
+use aya_ebpf::macros::{cgroup_sock, cgroup_sock_addr, map, tracepoint};
+use aya_ebpf::maps::{Array, HashMap, LruHashMap, PerCpuArray};
+use aya_ebpf::programs::{SockAddrContext, SockContext, TracePointContext};
 use aya_ebpf::EbpfContext;
 use aya_log_ebpf::debug;
-use mitmproxy_linux_ebpf_common::{Action, INTERCEPT_CONF_LEN};
+use mitmproxy_linux_ebpf_common::{
+    flow_rule_index, glob_rule_index, Flow, FlowRule, GlobRule, GlobState, IdKey, IdKind, InterceptConfHeader,
+    Name, NameKey, Pid, PidKey, Process, Rule, RuleLookup, SocketOwner, FLOW_RULES_LEN,
+    GLOB_RULES_LEN, GLOB_STEPS, ID_RULES_LEN, NAME_LEN, NAME_RULES_LEN, PARENTS_LEN, PATH_LEN, PID_RULES_LEN,
+    PROCESS_NAMES_LEN, SOCKET_OWNERS_LEN, TASK_COMM_LEN,
+};
+
+const AF_INET: u32 = 2;
+const AF_INET6: u32 = 10;
+const SOL_SOCKET: i32 = 1;
+const SO_BINDTODEVICE: i32 = 25;
+const IFNAMSIZ: usize = 16;
 
 #[no_mangle]
 static INTERFACE_ID: u32 = 0;
 
+/// Name of the TUN device, for binding sockets in connect hooks.
+#[no_mangle]
+static INTERFACE_NAME: [u8; IFNAMSIZ] = [0; IFNAMSIZ];
+
+#[map]
+static INTERCEPT_CONF: Array<InterceptConfHeader> = Array::with_max_entries(1, 0);
+
+#[map]
+static PID_RULES: HashMap<PidKey, Rule> = HashMap::with_max_entries(PID_RULES_LEN, 0);
+
+/// Rules for users, groups, cgroups, network namespaces and process trees.
+#[map]
+static ID_RULES: HashMap<IdKey, Rule> = HashMap::with_max_entries(ID_RULES_LEN, 0);
+
+#[map]
+static NAME_RULES: HashMap<NameKey, Rule> = HashMap::with_max_entries(NAME_RULES_LEN, 0);
+
+#[map]
+static GLOB_RULES: Array<GlobRule> = Array::with_max_entries(2 * GLOB_RULES_LEN, 0);
+
+#[map]
+static FLOW_RULES: Array<FlowRule> = Array::with_max_entries(2 * FLOW_RULES_LEN, 0);
+
+/// PID -> executable name. Unlike the comm, this is not truncated to 16 bytes.
+/// Filled on exec, and by the redirector for processes that existed before it started.
+#[map]
+static PROCESS_NAMES: LruHashMap<Pid, Name> = LruHashMap::with_max_entries(PROCESS_NAMES_LEN, 0);
+
+/// PID -> PID of the process that forked it. Filled on fork, and by the redirector for processes
+/// that existed before it started.
+///
+/// New threads show up as forks too. Their entries are harmless: a TID only becomes the PID of a
+/// new process through another fork, which overwrites the entry.
+#[map]
+static PARENTS: LruHashMap<Pid, Pid> = LruHashMap::with_max_entries(PARENTS_LEN, 0);
+
+/// Scratch space for executable paths, which are too large for the stack.
+#[map]
+static PATH_BUF: PerCpuArray<[u8; PATH_LEN]> = PerCpuArray::with_max_entries(1, 0);
+
+/// Scratch space for the process that is matched against the intercept spec.
+/// Together with the rule lookups, it would take up most of the 512 bytes of stack we have.
+#[map]
+static PROCESS_BUF: PerCpuArray<Process> = PerCpuArray::with_max_entries(1, 0);
+
+/// Scratch space for name rule lookup keys.
 #[map]
-static INTERCEPT_CONF: Array<Action> = Array::with_max_entries(INTERCEPT_CONF_LEN, 0);
+static NAME_KEY_BUF: PerCpuArray<NameKey> = PerCpuArray::with_max_entries(1, 0);
+
+/// Socket cookie -> process that created the socket, so that the redirector can attribute packets.
+#[map]
+static SOCKET_OWNERS: LruHashMap<u64, SocketOwner> =
+    LruHashMap::with_max_entries(SOCKET_OWNERS_LEN, 0);
 
 #[cgroup_sock(sock_create)]
 pub fn cgroup_sock_create(ctx: SockContext) -> i32 {
-    if should_intercept(&ctx) {
+    let Some(header) = INTERCEPT_CONF.get(0) else {
+        return 1;
+    };
+    // If the spec has destination patterns, we need to wait until we know the destination.
+    if header.flow_rules != 0 {
+        return 1;
+    }
+    let netns = unsafe { bpf_get_netns_cookie(ctx.sock as *mut _) };
+    if should_intercept(&ctx, header, netns, None) {
         debug!(&ctx, "intercepting in sock_create");
         let interface_id = unsafe { core::ptr::read_volatile(&INTERFACE_ID) };
         unsafe {
             (*ctx.sock).bound_dev_if = interface_id;
         }
+        record_owner(&ctx, unsafe { bpf_get_socket_cookie(ctx.sock as *mut _) });
     }
     1
 }
 
-pub fn should_intercept(ctx: &SockContext) -> bool {
-    let command = ctx.command().ok();
-    let pid = ctx.pid();
+#[cgroup_sock_addr(connect4)]
+pub fn connect4(ctx: SockAddrContext) -> i32 {
+    on_connect(&ctx)
+}
+
+#[cgroup_sock_addr(connect6)]
+pub fn connect6(ctx: SockAddrContext) -> i32 {
+    on_connect(&ctx)
+}
+
+/// Unconnected UDP sockets don't call connect, so we look at the first datagram instead.
+#[cgroup_sock_addr(sendmsg4)]
+pub fn sendmsg4(ctx: SockAddrContext) -> i32 {
+    on_connect(&ctx)
+}
+
+#[cgroup_sock_addr(sendmsg6)]
+pub fn sendmsg6(ctx: SockAddrContext) -> i32 {
+    on_connect(&ctx)
+}
 
-    let mut intercept = matches!(INTERCEPT_CONF.get(0), Some(Action::Exclude(_)));
-    for i in 0..INTERCEPT_CONF_LEN {
-        match INTERCEPT_CONF.get(i) {
-            Some(Action::Include(pattern)) => {
-                intercept = intercept || pattern.matches(command.as_ref(), pid);
-            }
-            Some(Action::Exclude(pattern)) => {
-                intercept = intercept && !pattern.matches(command.as_ref(), pid);
-            }
-            _ => {
-                break;
-            }
+fn on_connect(ctx: &SockAddrContext) -> i32 {
+    let Some(header) = INTERCEPT_CONF.get(0) else {
+        return 1;
+    };
+    if header.flow_rules == 0 {
+        return 1;
+    }
+    let cookie = unsafe { bpf_get_socket_cookie(ctx.sock_addr as *mut _) };
+    // Unbinding a socket requires CAP_NET_RAW, so once a socket is intercepted,
+    // all of its datagrams are, even if they are sent to other destinations.
+    if unsafe { SOCKET_OWNERS.get(&cookie) }.is_some() {
+        return 1;
+    }
+    let Some(flow) = flow(ctx) else {
+        return 1;
+    };
+    let netns = unsafe { bpf_get_netns_cookie(ctx.sock_addr as *mut _) };
+    if should_intercept(ctx, header, netns, Some(&flow)) {
+        debug!(ctx, "intercepting on connect");
+        let mut name = unsafe { core::ptr::read_volatile(&INTERFACE_NAME) };
+        let ret = unsafe {
+            bpf_setsockopt(
+                ctx.sock_addr as *mut _,
+                SOL_SOCKET,
+                SO_BINDTODEVICE,
+                name.as_mut_ptr() as *mut _,
+                IFNAMSIZ as i32,
+            )
+        };
+        if ret == 0 {
+            record_owner(ctx, cookie);
+        } else {
+            debug!(ctx, "failed to bind socket to interface: {}", ret);
         }
     }
-    intercept
+    1
+}
+
+/// The destination of a connect or sendmsg call.
+fn flow(ctx: &SockAddrContext) -> Option<Flow> {
+    let sock_addr = unsafe { &*ctx.sock_addr };
+    let addr = match sock_addr.user_family {
+        AF_INET => {
+            let [a, b, c, d] = sock_addr.user_ip4.to_ne_bytes();
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d]
+        }
+        AF_INET6 => {
+            let words = [
+                sock_addr.user_ip6[0],
+                sock_addr.user_ip6[1],
+                sock_addr.user_ip6[2],
+                sock_addr.user_ip6[3],
+            ];
+            // The address is in network byte order, so its in-memory representation is what we need.
+            unsafe { core::mem::transmute::<[u32; 4], [u8; 16]>(words) }
+        }
+        _ => return None,
+    };
+    Some(Flow {
+        addr,
+        port: u16::from_be(sock_addr.user_port as u16),
+        protocol: sock_addr.protocol as u8,
+    })
+}
+
+#[tracepoint]
+pub fn sched_process_exec(ctx: TracePointContext) -> u32 {
+    if record_exec(&ctx).is_err() {
+        debug!(&ctx, "failed to record executable name");
+    }
+    0
+}
+
+#[tracepoint]
+pub fn sched_process_fork(ctx: TracePointContext) -> u32 {
+    if record_fork(&ctx).is_err() {
+        debug!(&ctx, "failed to record parent process");
+    }
+    0
+}
+
+fn record_fork(ctx: &TracePointContext) -> Result<(), i64> {
+    // The tracepoint runs in the context of the parent, and child_pid is at offset 44.
+    let child: Pid = unsafe { ctx.read_at(44)? };
+    PARENTS.insert(&child, &ctx.tgid(), 0)
+}
+
+fn record_exec(ctx: &TracePointContext) -> Result<(), i64> {
+    // The filename is a __data_loc field at offset 8 of the tracepoint's arguments:
+    // the lower 16 bits are the offset of the string, the upper 16 bits its length.
+    let data_loc: u32 = unsafe { ctx.read_at(8)? };
+    let offset = (data_loc & 0xffff) as usize;
+    let buf = PATH_BUF.get_ptr_mut(0).ok_or(0)?;
+    let path = unsafe {
+        bpf_probe_read_kernel_str_bytes((ctx.as_ptr() as *const u8).add(offset), &mut *buf)?
+    };
+
+    let mut start = 0;
+    for i in 0..PATH_LEN {
+        match path.get(i) {
+            Some(b'/') => start = i + 1,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    let mut name = [0u8; NAME_LEN];
+    for (i, c) in name.iter_mut().enumerate() {
+        match path.get(start + i) {
+            Some(&b) => *c = b,
+            None => break,
+        }
+    }
+    PROCESS_NAMES.insert(&ctx.tgid(), &name, 0)
+}
+
+fn record_owner<C: EbpfContext>(ctx: &C, cookie: u64) {
+    let mut owner = SocketOwner {
+        pid: ctx.tgid(),
+        name: [0u8; NAME_LEN],
+    };
+    process_name(ctx, owner.pid, &mut owner.name);
+    if SOCKET_OWNERS.insert(&cookie, &owner, 0).is_err() {
+        debug!(ctx, "failed to record socket owner");
+    }
+}
+
+/// Rule lookups in the BPF maps for the currently active generation.
+struct ActiveConf(InterceptConfHeader);
+
+impl RuleLookup for ActiveConf {
+    fn default_intercept(&self) -> bool {
+        self.0.default_intercept != 0
+    }
+
+    fn pid_rule(&self, pid: Pid) -> Option<Rule> {
+        let key = PidKey {
+            generation: self.0.generation,
+            pid,
+        };
+        unsafe { PID_RULES.get(&key) }.copied()
+    }
+
+    fn id_rule(&self, kind: IdKind, id: u64) -> Option<Rule> {
+        let key = IdKey {
+            generation: self.0.generation,
+            kind,
+            id,
+        };
+        unsafe { ID_RULES.get(&key) }.copied()
+    }
+
+    fn name_rule(&self, name: &Name) -> Option<Rule> {
+        let key = unsafe { &mut *NAME_KEY_BUF.get_ptr_mut(0)? };
+        key.generation = self.0.generation;
+        key.name = *name;
+        unsafe { NAME_RULES.get(key) }.copied()
+    }
+
+    fn glob_rule(&self, i: u32) -> Option<&GlobRule> {
+        if i >= self.0.glob_rules {
+            return None;
+        }
+        GLOB_RULES.get(glob_rule_index(self.0.generation, i))
+    }
+
+    fn flow_rule(&self, i: u32) -> Option<&FlowRule> {
+        if i >= self.0.flow_rules {
+            return None;
+        }
+        FLOW_RULES.get(flow_rule_index(self.0.generation, i))
+    }
+
+    fn glob_match(&self, pattern: &[u8], name: &[u8]) -> bool {
+        let mut ctx = GlobLoop {
+            pattern,
+            name,
+            state: GlobState::default(),
+            matched: false,
+        };
+        unsafe {
+            bpf_loop(
+                GLOB_STEPS as u32,
+                glob_loop_step as *mut c_void,
+                &mut ctx as *mut GlobLoop as *mut c_void,
+                0,
+            );
+        }
+        ctx.matched
+    }
+}
+
+/// Context for [glob_loop_step].
+struct GlobLoop<'a> {
+    pattern: &'a [u8],
+    name: &'a [u8],
+    state: GlobState,
+    matched: bool,
+}
+
+/// `bpf_loop` callback that advances a glob match by one step.
+/// Returns 1 to stop the loop once the result is known.
+extern "C" fn glob_loop_step(_index: u32, ctx: *mut c_void) -> i64 {
+    let ctx = unsafe { &mut *(ctx as *mut GlobLoop) };
+    match ctx.state.step(ctx.pattern, ctx.name) {
+        Some(matched) => {
+            ctx.matched = matched;
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Write the executable name of the current process, or its comm if we have not seen it exec.
+fn process_name<C: EbpfContext>(ctx: &C, pid: Pid, name: &mut Name) {
+    if let Some(known) = unsafe { PROCESS_NAMES.get(&pid) } {
+        *name = *known;
+        return;
+    }
+    *name = [0u8; NAME_LEN];
+    if let Ok(comm) = ctx.command() {
+        name[..TASK_COMM_LEN].copy_from_slice(&comm);
+    }
+}
+
+fn should_intercept<C: EbpfContext>(
+    ctx: &C,
+    header: &InterceptConfHeader,
+    netns: u64,
+    flow: Option<&Flow>,
+) -> bool {
+    let Some(process) = PROCESS_BUF.get_ptr_mut(0) else {
+        return false;
+    };
+    // The buffer is reused, so every field needs to be overwritten.
+    let process = unsafe { &mut *process };
+    // The PID in intercept specs refers to the process, not the thread creating the socket.
+    let pid = ctx.tgid();
+    process.pid = pid;
+    process.uid = ctx.uid();
+    process.gid = ctx.gid();
+    process.netns = netns;
+    for (level, id) in process.cgroups.iter_mut().enumerate() {
+        // Levels below the process' cgroup are zero.
+        *id = unsafe { bpf_get_current_ancestor_cgroup_id(level as i32) };
+    }
+    let mut current = pid;
+    for ancestor in process.ancestors.iter_mut() {
+        // Entries after the oldest known ancestor are zero.
+        *ancestor = match unsafe { PARENTS.get(&current) } {
+            Some(&parent) if current != 0 && parent != current => parent,
+            _ => 0,
+        };
+        current = *ancestor;
+    }
+    process_name(ctx, pid, &mut process.name);
+    mitmproxy_linux_ebpf_common::should_intercept(&ActiveConf(*header), process, flow)
 }
 
 #[cfg(not(test))]
@@ -1,44 +1,316 @@
-use std::{fs, iter};
+use std::fs;
 use std::fs::Permissions;
 use anyhow::Context;
 use anyhow::anyhow;
 use anyhow::Result;
 use aya::{Ebpf, EbpfLoader};
-use aya::maps::Array;
+use aya::maps::{Array, HashMap, MapData};
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
 use aya::Btf;
-use aya::programs::{links::CgroupAttachMode, CgroupSock};
+use aya::programs::{links::CgroupAttachMode, CgroupSock, CgroupSockAddr, TracePoint};
 use log::{debug, warn, info, error};
 use prost::bytes::{Bytes, BytesMut};
 use tokio::net::UnixDatagram;
 use tokio::select;
 use mitmproxy::packet_sources::tun::create_tun_device;
+use mitmproxy::packet_sources::tun_routing::SysctlSnapshot;
 use tun::AbstractDevice;
 use prost::Message;
 use tokio::io::AsyncReadExt;
 use tokio::signal::unix::{signal, SignalKind};
-use mitmproxy::ipc::{PacketWithMeta, from_proxy};
+use mitmproxy::ipc::{PacketWithMeta, TunnelInfo, from_proxy};
 use mitmproxy::ipc::FromProxy;
 use mitmproxy::packet_sources::IPC_BUF_SIZE;
-use mitmproxy_linux_ebpf_common::{Action, INTERCEPT_CONF_LEN};
+use mitmproxy::intercept_conf::InterceptConf;
+use mitmproxy::intercept_conf::ebpf::{EbpfConf, SystemResolver, CGROUP_ROOT};
+use mitmproxy_linux_ebpf_common::{flow_rule_index, glob_rule_index, name_len, FlowRule, GlobRule, IdKey, InterceptConfHeader, Name, NameKey, PidKey, Rule, SocketOwner, NAME_LEN};
+use lru_time_cache::LruCache;
+use sock_diag::SockDiag;
+
+mod sock_diag;
 
 // We can't implement aya::Pod in mitmproxy-linux-ebpf-common, so we do it on a newtype.
 // (see https://github.com/aya-rs/aya/pull/59)
 #[derive(Copy, Clone)]
 #[repr(transparent)]
-struct ActionWrapper(Action);
+struct InterceptConfHeaderWrapper(InterceptConfHeader);
+
+unsafe impl aya::Pod for InterceptConfHeaderWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct PidKeyWrapper(PidKey);
+
+unsafe impl aya::Pod for PidKeyWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct IdKeyWrapper(IdKey);
+
+unsafe impl aya::Pod for IdKeyWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct NameKeyWrapper(NameKey);
+
+unsafe impl aya::Pod for NameKeyWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct RuleWrapper(Rule);
+
+unsafe impl aya::Pod for RuleWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct GlobRuleWrapper(GlobRule);
+
+unsafe impl aya::Pod for GlobRuleWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct FlowRuleWrapper(FlowRule);
+
+unsafe impl aya::Pod for FlowRuleWrapper {}
+
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+struct SocketOwnerWrapper(SocketOwner);
+
+unsafe impl aya::Pod for SocketOwnerWrapper {}
+
+const BPF_NOEXIST: u64 = 1;
+
+/// The BPF maps that hold the intercept spec.
+///
+/// New rules are written with a new generation and then activated by updating INTERCEPT_CONF,
+/// so that the eBPF program never sees a partially written spec.
+struct InterceptMaps {
+    conf: Array<MapData, InterceptConfHeaderWrapper>,
+    pid_rules: HashMap<MapData, PidKeyWrapper, RuleWrapper>,
+    id_rules: HashMap<MapData, IdKeyWrapper, RuleWrapper>,
+    name_rules: HashMap<MapData, NameKeyWrapper, RuleWrapper>,
+    glob_rules: Array<MapData, GlobRuleWrapper>,
+    flow_rules: Array<MapData, FlowRuleWrapper>,
+    generation: u32,
+    active: Option<EbpfConf>,
+}
+
+impl InterceptMaps {
+    fn new(ebpf: &mut Ebpf) -> Result<Self> {
+        let conf = ebpf.take_map("INTERCEPT_CONF").context("couldn't get INTERCEPT_CONF map")?;
+        let pid_rules = ebpf.take_map("PID_RULES").context("couldn't get PID_RULES map")?;
+        let id_rules = ebpf.take_map("ID_RULES").context("couldn't get ID_RULES map")?;
+        let name_rules = ebpf.take_map("NAME_RULES").context("couldn't get NAME_RULES map")?;
+        let glob_rules = ebpf.take_map("GLOB_RULES").context("couldn't get GLOB_RULES map")?;
+        let flow_rules = ebpf.take_map("FLOW_RULES").context("couldn't get FLOW_RULES map")?;
+        Ok(Self {
+            conf: Array::try_from(conf).context("Cannot cast INTERCEPT_CONF to Array")?,
+            pid_rules: HashMap::try_from(pid_rules).context("Cannot cast PID_RULES to HashMap")?,
+            id_rules: HashMap::try_from(id_rules).context("Cannot cast ID_RULES to HashMap")?,
+            name_rules: HashMap::try_from(name_rules).context("Cannot cast NAME_RULES to HashMap")?,
+            glob_rules: Array::try_from(glob_rules).context("Cannot cast GLOB_RULES to Array")?,
+            flow_rules: Array::try_from(flow_rules).context("Cannot cast FLOW_RULES to Array")?,
+            generation: 0,
+            active: None,
+        })
+    }
+
+    fn update(&mut self, conf: EbpfConf) -> Result<()> {
+        let generation = self.generation.wrapping_add(1);
+        let header = InterceptConfHeader {
+            generation,
+            default_intercept: conf.default as u32,
+            glob_rules: conf.glob_rules.len() as u32,
+            flow_rules: conf.flow_rules.len() as u32,
+        };
+        let result = self.insert_rules(generation, &conf)
+            .and_then(|()| self.conf.set(0, InterceptConfHeaderWrapper(header), 0).context("failed to update INTERCEPT_CONF"));
+        if let Err(e) = result {
+            self.remove_rules(generation, &conf);
+            return Err(e);
+        }
+        if let Some(previous) = self.active.replace(conf) {
+            self.remove_rules(self.generation, &previous);
+        }
+        self.generation = generation;
+        Ok(())
+    }
+
+    fn insert_rules(&mut self, generation: u32, conf: &EbpfConf) -> Result<()> {
+        for (&pid, &rule) in &conf.pid_rules {
+            self.pid_rules.insert(PidKeyWrapper(PidKey { generation, pid }), RuleWrapper(rule), 0)
+                .context("failed to update PID_RULES")?;
+        }
+        for (&(kind, id), &rule) in &conf.id_rules {
+            self.id_rules.insert(IdKeyWrapper(IdKey { generation, kind, id }), RuleWrapper(rule), 0)
+                .context("failed to update ID_RULES")?;
+        }
+        for (&name, &rule) in &conf.name_rules {
+            self.name_rules.insert(NameKeyWrapper(NameKey { generation, name }), RuleWrapper(rule), 0)
+                .context("failed to update NAME_RULES")?;
+        }
+        // The eBPF program only reads the other half of the array until we switch generations.
+        for (i, &glob) in conf.glob_rules.iter().enumerate() {
+            self.glob_rules.set(glob_rule_index(generation, i as u32), GlobRuleWrapper(glob), 0)
+                .context("failed to update GLOB_RULES")?;
+        }
+        for (i, &flow) in conf.flow_rules.iter().enumerate() {
+            self.flow_rules.set(flow_rule_index(generation, i as u32), FlowRuleWrapper(flow), 0)
+                .context("failed to update FLOW_RULES")?;
+        }
+        Ok(())
+    }
+
+    fn remove_rules(&mut self, generation: u32, conf: &EbpfConf) {
+        for &pid in conf.pid_rules.keys() {
+            self.pid_rules.remove(&PidKeyWrapper(PidKey { generation, pid })).ok();
+        }
+        for &(kind, id) in conf.id_rules.keys() {
+            self.id_rules.remove(&IdKeyWrapper(IdKey { generation, kind, id })).ok();
+        }
+        for &name in conf.name_rules.keys() {
+            self.name_rules.remove(&NameKeyWrapper(NameKey { generation, name })).ok();
+        }
+    }
+}
+
+/// The eBPF program records executable names on exec,
+/// so we need to fill in all processes that were started before it was loaded.
+fn record_running_processes(
+    process_names: &mut HashMap<MapData, u32, Name>,
+    parents: &mut HashMap<MapData, u32, u32>,
+) -> Result<()> {
+    for entry in fs::read_dir("/proc").context("failed to read /proc")? {
+        let Ok(entry) = entry else { continue };
+        let Some(pid) = entry.file_name().to_str().and_then(|pid| pid.parse::<u32>().ok()) else {
+            continue;
+        };
+        if let Some(ppid) = parent_pid(&entry.path()) {
+            // Don't overwrite parents of PIDs that have been reused in the meantime.
+            parents.insert(pid, ppid, BPF_NOEXIST).ok();
+        }
+        // Kernel threads don't have an executable.
+        let Ok(exe) = fs::read_link(entry.path().join("exe")) else { continue };
+        let Some(file_name) = exe.file_name() else { continue };
+        let file_name = file_name.as_bytes();
+        let mut name = [0u8; NAME_LEN];
+        let len = file_name.len().min(NAME_LEN);
+        name[..len].copy_from_slice(&file_name[..len]);
+        // Don't overwrite names of processes that have exec'd in the meantime.
+        process_names.insert(pid, name, BPF_NOEXIST).ok();
+    }
+    Ok(())
+}
+
+/// Read the parent PID from `/proc/<pid>/stat`.
+fn parent_pid(proc_dir: &Path) -> Option<u32> {
+    let stat = fs::read_to_string(proc_dir.join("stat")).ok()?;
+    // The format is `pid (comm) state ppid ...`, where comm may contain spaces and parentheses.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Attributes packets read from the TUN device to the process that created their socket.
+struct ProcessLookup {
+    diag: SockDiag,
+    owners: HashMap<MapData, u64, SocketOwnerWrapper>,
+    /// UDP has no handshake we could look at, so we cache the result for each flow instead.
+    udp_flows: LruCache<(SocketAddr, SocketAddr), Option<TunnelInfo>>,
+}
 
-unsafe impl aya::Pod for ActionWrapper {}
+impl ProcessLookup {
+    fn new(owners: HashMap<MapData, u64, SocketOwnerWrapper>) -> Result<Self> {
+        Ok(Self {
+            diag: SockDiag::new().context("failed to open sock_diag socket")?,
+            owners,
+            udp_flows: LruCache::with_expiry_duration_and_capacity(Duration::from_secs(60), 4096),
+        })
+    }
+
+    /// The proxy only looks at the first packet of each connection,
+    /// so we only need to look up TCP SYNs and new UDP flows.
+    fn tunnel_info(&mut self, packet: &[u8]) -> Option<TunnelInfo> {
+        let (protocol, src, dst, syn) = parse_flow(packet)?;
+        match protocol as i32 {
+            libc::IPPROTO_TCP if syn => self.lookup(protocol, src, dst),
+            libc::IPPROTO_UDP => {
+                if let Some(info) = self.udp_flows.get(&(src, dst)) {
+                    return info.clone();
+                }
+                let info = self.lookup(protocol, src, dst);
+                self.udp_flows.insert((src, dst), info.clone());
+                info
+            }
+            _ => None,
+        }
+    }
+
+    fn lookup(&mut self, protocol: u8, src: SocketAddr, dst: SocketAddr) -> Option<TunnelInfo> {
+        let cookie = match self.diag.socket_cookie(protocol, src, dst) {
+            Ok(cookie) => cookie?,
+            Err(e) => {
+                debug!("Failed to look up socket for {src} -> {dst}: {e}");
+                return None;
+            }
+        };
+        let SocketOwnerWrapper(owner) = self.owners.get(&cookie, 0).ok()?;
+        Some(TunnelInfo {
+            pid: Some(owner.pid),
+            process_name: Some(String::from_utf8_lossy(&owner.name[..name_len(&owner.name)]).into_owned()),
+        })
+    }
+}
+
+/// Extract protocol, source and destination address from an IP packet,
+/// and whether it is a TCP SYN (without ACK).
+fn parse_flow(packet: &[u8]) -> Option<(u8, SocketAddr, SocketAddr, bool)> {
+    let (protocol, src, dst, transport) = match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            // Only the first fragment has the transport header.
+            if (packet[6] & 0x1f) != 0 || packet[7] != 0 {
+                return None;
+            }
+            let header_len = (packet[0] & 0x0f) as usize * 4;
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).unwrap());
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&packet[16..20]).unwrap());
+            (packet[9], IpAddr::V4(src), IpAddr::V4(dst), packet.get(header_len..)?)
+        }
+        6 if packet.len() >= 40 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&packet[24..40]).unwrap());
+            (packet[6], IpAddr::V6(src), IpAddr::V6(dst), &packet[40..])
+        }
+        _ => return None,
+    };
+    if transport.len() < 4 {
+        return None;
+    }
+    let src_port = u16::from_be_bytes([transport[0], transport[1]]);
+    let dst_port = u16::from_be_bytes([transport[2], transport[3]]);
+    // SYN set, ACK not set.
+    let syn = protocol == libc::IPPROTO_TCP as u8
+        && transport.get(13).is_some_and(|flags| flags & 0x12 == 0x02);
+    Some((protocol, SocketAddr::new(src, src_port), SocketAddr::new(dst, dst_port), syn))
+}
 
 const BPF_PROG: &[u8] = aya::include_bytes_aligned!(concat!(env!("OUT_DIR"), "/mitmproxy-linux"));
 const BPF_HASH: [u8; 20] = const_sha1::sha1(BPF_PROG).as_bytes();
 
-fn load_bpf(device_index: u32) -> Result<Ebpf> {
+fn load_bpf(device_index: u32, device_name: &str, cgroup: &Path) -> Result<Ebpf> {
     debug!("Loading BPF program ({:x})...", Bytes::from_static(&BPF_HASH));
+    // IFNAMSIZ, including the trailing NUL byte.
+    let mut interface_name = [0u8; 16];
+    interface_name.get_mut(..device_name.len()).context("device name too long")?.copy_from_slice(device_name.as_bytes());
     let mut ebpf = EbpfLoader::new()
         .btf(Btf::from_sys_fs().ok().as_ref())
         .set_global("INTERFACE_ID", &device_index, true)
+        .set_global("INTERFACE_NAME", &interface_name, true)
         .load(BPF_PROG)
         .context("failed to load eBPF program")?;
     if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
@@ -48,10 +320,28 @@ fn load_bpf(device_index: u32) -> Result<Ebpf> {
 
     debug!("Attaching BPF_CGROUP_INET_SOCK_CREATE program...");
     let prog: &mut CgroupSock = ebpf.program_mut("cgroup_sock_create").context("failed to get cgroup_sock_create")?.try_into()?;
-    // root cgroup to get all events.
-    let cgroup = fs::File::open("/sys/fs/cgroup/").context("failed to open \"/sys/fs/cgroup/\"")?;
+    // The program only sees sockets created in this cgroup and its descendants.
+    let cgroup = fs::File::open(cgroup).with_context(|| format!("failed to open {}", cgroup.display()))?;
     prog.load().context("failed to load cgroup_sock_create program")?;
     prog.attach(&cgroup, CgroupAttachMode::Single).context("failed to attach cgroup_sock_create program")?;
+
+    // If the intercept spec has destination patterns, we decide on connect instead.
+    for name in ["connect4", "connect6", "sendmsg4", "sendmsg6"] {
+        debug!("Attaching {name} program...");
+        let prog: &mut CgroupSockAddr = ebpf.program_mut(name).with_context(|| format!("failed to get {name}"))?.try_into()?;
+        prog.load().with_context(|| format!("failed to load {name} program"))?;
+        prog.attach(&cgroup, CgroupAttachMode::Single).with_context(|| format!("failed to attach {name} program"))?;
+    }
+
+    debug!("Attaching sched_process_exec tracepoint...");
+    let prog: &mut TracePoint = ebpf.program_mut("sched_process_exec").context("failed to get sched_process_exec")?.try_into()?;
+    prog.load().context("failed to load sched_process_exec program")?;
+    prog.attach("sched", "sched_process_exec").context("failed to attach sched_process_exec program")?;
+
+    debug!("Attaching sched_process_fork tracepoint...");
+    let prog: &mut TracePoint = ebpf.program_mut("sched_process_fork").context("failed to get sched_process_fork")?.try_into()?;
+    prog.load().context("failed to load sched_process_fork program")?;
+    prog.attach("sched", "sched_process_fork").context("failed to attach sched_process_fork program")?;
     Ok(ebpf)
 }
 
@@ -68,26 +358,49 @@ async fn main() -> anyhow::Result<()> {
     let pipe_dir = args
         .get(1)
         .map(PathBuf::from)
-        .with_context(|| format!("usage: {} <pipe-dir>", args[0]))?;
+        .with_context(|| format!("usage: {} <pipe-dir> [cgroup]", args[0]))?;
+    // By default, attach to the root cgroup to get all events.
+    let cgroup = Path::new(CGROUP_ROOT).join(args.get(2).map_or("", |c| c.trim_start_matches('/')));
     let mitmproxy_addr = pipe_dir.join("mitmproxy");
     let redirector_addr = pipe_dir.join("redirector");
 
     bump_memlock_rlimit();
 
     debug!("Creating tun device...");
-    let (mut device, name) = create_tun_device(None)?;
+    // Sysctls we change are restored on exit.
+    let sysctls = Arc::new(Mutex::new(SysctlSnapshot::default()));
+    let (mut device, name) = create_tun_device(None, &mut sysctls.lock().unwrap())?;
     let device_index = device.tun_index().context("failed to get tun device index")? as u32;
     debug!("Tun device created: {name} (id={device_index})");
 
-    let mut ebpf = load_bpf(device_index).context("eBPF initialization failed")?;
+    let mut ebpf = load_bpf(device_index, &name, &cgroup).context("eBPF initialization failed")?;
 
-    debug!("Getting INTERCEPT_CONF map...");
-    let mut intercept_conf = {
-        let map = ebpf.map_mut("INTERCEPT_CONF")
-            .context("couldn't get INTERCEPT_CONF map")?;
-        Array::<_, ActionWrapper>::try_from(map)
-            .context("Cannot cast INTERCEPT_CONF to Array")?
+    debug!("Getting SOCKET_OWNERS map...");
+    let socket_owners = {
+        let map = ebpf.take_map("SOCKET_OWNERS")
+            .context("couldn't get SOCKET_OWNERS map")?;
+        HashMap::<_, u64, SocketOwnerWrapper>::try_from(map)
+            .context("Cannot cast SOCKET_OWNERS to HashMap")?
+    };
+    let mut process_lookup = ProcessLookup::new(socket_owners)?;
+
+    debug!("Recording running processes...");
+    let mut process_names = {
+        let map = ebpf.take_map("PROCESS_NAMES")
+            .context("couldn't get PROCESS_NAMES map")?;
+        HashMap::<_, u32, Name>::try_from(map)
+            .context("Cannot cast PROCESS_NAMES to HashMap")?
+    };
+    let mut parents = {
+        let map = ebpf.take_map("PARENTS")
+            .context("couldn't get PARENTS map")?;
+        HashMap::<_, u32, u32>::try_from(map)
+            .context("Cannot cast PARENTS to HashMap")?
     };
+    record_running_processes(&mut process_names, &mut parents)?;
+
+    debug!("Getting intercept conf maps...");
+    let mut intercept_maps = InterceptMaps::new(&mut ebpf)?;
 
     debug!("Connecting to {}...", mitmproxy_addr.display());
     let ipc = UnixDatagram::bind(&redirector_addr)
@@ -99,13 +412,15 @@ async fn main() -> anyhow::Result<()> {
     println!("{}", redirector_addr.to_string_lossy());
 
     // Exit cleanly on SIGINT/SIGTERM
-    tokio::spawn(async {
+    let sysctls_on_signal = sysctls.clone();
+    tokio::spawn(async move {
         let mut sigint = signal(SignalKind::interrupt()).context("failed to register SIGINT listener").unwrap();
         let mut sigterm = signal(SignalKind::terminate()).context("failed to register SIGTERM listener").unwrap();
         select! {
             _ = sigint.recv() => (),
             _ = sigterm.recv() => (),
         }
+        sysctls_on_signal.lock().unwrap().restore();
         std::process::exit(0);
     });
 
@@ -130,23 +445,19 @@ async fn main() -> anyhow::Result<()> {
                             }
                             from_proxy::Message::InterceptConf(conf) => {
                                 debug!("Updating ebpf intercept conf: {conf:?}");
-                                if conf.actions.len() > INTERCEPT_CONF_LEN as usize {
-                                    error!("Truncating intercept conf to {INTERCEPT_CONF_LEN} elements.");
-                                }
-                                let actions = conf.actions
-                                    .iter()
-                                    .map(|s| Action::from(s.as_str()))
-                                    .chain(iter::once(Action::None))
-                                    .take(INTERCEPT_CONF_LEN as usize);
-                                for (i, action) in actions.enumerate() {
-                                    intercept_conf.set(i as u32, ActionWrapper(action), 0)
-                                        .context("failed to update INTERCEPT_CONF")?;
+                                // Specs are validated by the proxy already, so this should not fail.
+                                let conf = InterceptConf::try_from(conf.actions)
+                                    .and_then(|conf| EbpfConf::new(&conf, &SystemResolver));
+                                match conf {
+                                    Ok(conf) => intercept_maps.update(conf)?,
+                                    Err(e) => error!("Keeping previous intercept conf, new spec is not supported: {e}"),
                                 }
                             }
                         }
                     }
                     _ => {
                         info!("IPC read failed. Exiting.");
+                        sysctls.lock().unwrap().restore();
                         std::process::exit(0);
                     }
                 }
@@ -155,9 +466,10 @@ async fn main() -> anyhow::Result<()> {
             r = device.read_buf(&mut dev_buf) => {
                 r.context("TUN read() failed")?;
 
+                let tunnel_info = process_lookup.tunnel_info(&dev_buf);
                 let packet = PacketWithMeta {
                     data: dev_buf.split().freeze(),
-                    tunnel_info: None,
+                    tunnel_info,
                 };
 
                 packet.encode(&mut ipc_buf)?;
@@ -193,7 +505,50 @@ mod tests {
     #[cfg_attr(not(feature = "root-tests"), ignore)]
     #[tokio::test]
     async fn bpf_load() {
-        load_bpf(0).unwrap();
+        load_bpf(0, "lo", Path::new(CGROUP_ROOT)).unwrap();
+    }
+
+    #[test]
+    fn parse_flows() {
+        // IPv4 TCP SYN 192.168.0.1:54321 -> 10.0.0.1:80
+        let mut packet = hex::decode(
+            "450000280000400040060000c0a800010a000001\
+             d431005000000000000000005002ffff00000000",
+        )
+        .unwrap();
+        assert_eq!(
+            parse_flow(&packet),
+            Some((
+                libc::IPPROTO_TCP as u8,
+                "192.168.0.1:54321".parse().unwrap(),
+                "10.0.0.1:80".parse().unwrap(),
+                true
+            ))
+        );
+        // SYN-ACK
+        packet[20 + 13] = 0x12;
+        assert!(!parse_flow(&packet).unwrap().3);
+        // non-first fragment
+        packet[7] = 1;
+        assert_eq!(parse_flow(&packet), None);
+
+        // IPv6 UDP [::1]:1234 -> [::2]:53
+        let packet = hex::decode(
+            "6000000000081140\
+             00000000000000000000000000000001\
+             00000000000000000000000000000002\
+             04d2003500080000",
+        )
+        .unwrap();
+        assert_eq!(
+            parse_flow(&packet),
+            Some((
+                libc::IPPROTO_UDP as u8,
+                "[::1]:1234".parse().unwrap(),
+                "[::2]:53".parse().unwrap(),
+                false
+            ))
+        );
     }
 
 }
\ No newline at end of file
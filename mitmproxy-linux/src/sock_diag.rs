@@ -0,0 +1,256 @@
+//! Look up socket cookies for packets via `NETLINK_SOCK_DIAG`.
+//!
+//! The eBPF program records the owning process for each socket cookie on socket creation.
+//! Packets read from the TUN device don't carry the cookie, so we ask the kernel which socket
+//! a packet belongs to.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_HDR_LEN: usize = 16;
+const INET_DIAG_REQ_V2_LEN: usize = 56;
+/// Offset of `idiag_cookie` in `struct inet_diag_msg`.
+const INET_DIAG_MSG_COOKIE_OFFSET: usize = 4 + 40;
+const INET_DIAG_NOCOOKIE: u32 = !0;
+
+pub struct SockDiag {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl SockDiag {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_SOCK_DIAG,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Don't block forever if the kernel does not answer for whatever reason.
+        let timeout = libc::timeval {
+            tv_sec: 0,
+            tv_usec: 100_000,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const _ as *const libc::c_void,
+                size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        // SAFETY: fd is a freshly opened socket that we own.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { fd, seq: 0 })
+    }
+
+    /// Find the cookie of the TCP or UDP socket that sent a packet from `local` to `remote`.
+    pub fn socket_cookie(
+        &mut self,
+        protocol: u8,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> io::Result<Option<u64>> {
+        if let Some(cookie) = self.query(protocol, local, remote)? {
+            return Ok(Some(cookie));
+        }
+        // IPv4 traffic may also originate from dual-stack IPv6 sockets.
+        if let (IpAddr::V4(l), IpAddr::V4(r)) = (local.ip(), remote.ip()) {
+            let local = SocketAddr::new(IpAddr::V6(l.to_ipv6_mapped()), local.port());
+            let remote = SocketAddr::new(IpAddr::V6(r.to_ipv6_mapped()), remote.port());
+            return self.query(protocol, local, remote);
+        }
+        Ok(None)
+    }
+
+    fn query(
+        &mut self,
+        protocol: u8,
+        local: SocketAddr,
+        remote: SocketAddr,
+    ) -> io::Result<Option<u64>> {
+        // For UDP, the kernel swaps source and destination for historical reasons.
+        let (src, dst) = if protocol == libc::IPPROTO_UDP as u8 {
+            (remote, local)
+        } else {
+            (local, remote)
+        };
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut req = Vec::with_capacity(NLMSG_HDR_LEN + INET_DIAG_REQ_V2_LEN);
+        // struct nlmsghdr
+        req.extend(((NLMSG_HDR_LEN + INET_DIAG_REQ_V2_LEN) as u32).to_ne_bytes());
+        req.extend(SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+        req.extend((libc::NLM_F_REQUEST as u16).to_ne_bytes());
+        req.extend(self.seq.to_ne_bytes());
+        req.extend(0u32.to_ne_bytes());
+        // struct inet_diag_req_v2
+        req.push(match local {
+            SocketAddr::V4(_) => libc::AF_INET as u8,
+            SocketAddr::V6(_) => libc::AF_INET6 as u8,
+        });
+        req.push(protocol);
+        req.push(0); // idiag_ext
+        req.push(0); // pad
+        req.extend(u32::MAX.to_ne_bytes()); // idiag_states: all
+        // struct inet_diag_sockid
+        req.extend(src.port().to_be_bytes());
+        req.extend(dst.port().to_be_bytes());
+        req.extend(addr_bytes(src.ip()));
+        req.extend(addr_bytes(dst.ip()));
+        req.extend(0u32.to_ne_bytes()); // idiag_if
+        req.extend(INET_DIAG_NOCOOKIE.to_ne_bytes());
+        req.extend(INET_DIAG_NOCOOKIE.to_ne_bytes());
+        debug_assert_eq!(req.len(), NLMSG_HDR_LEN + INET_DIAG_REQ_V2_LEN);
+
+        let ret = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                req.as_ptr() as *const libc::c_void,
+                req.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut buf = [0u8; 512];
+        loop {
+            let len = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let msg = &buf[..len as usize];
+            if msg.len() < NLMSG_HDR_LEN {
+                return Err(io::Error::other("truncated netlink message"));
+            }
+            let msg_type = u16::from_ne_bytes([msg[4], msg[5]]);
+            let seq = u32::from_ne_bytes(msg[8..12].try_into().unwrap());
+            if seq != self.seq {
+                // Stale response to a previous query that timed out.
+                continue;
+            }
+            let payload = &msg[NLMSG_HDR_LEN..];
+            return match msg_type {
+                NLMSG_ERROR => {
+                    let errno = payload
+                        .get(..4)
+                        .map(|e| -i32::from_ne_bytes(e.try_into().unwrap()))
+                        .unwrap_or(0);
+                    if errno == libc::ENOENT {
+                        Ok(None)
+                    } else {
+                        Err(io::Error::from_raw_os_error(errno))
+                    }
+                }
+                SOCK_DIAG_BY_FAMILY => {
+                    let Some(cookie) =
+                        payload.get(INET_DIAG_MSG_COOKIE_OFFSET..INET_DIAG_MSG_COOKIE_OFFSET + 8)
+                    else {
+                        return Err(io::Error::other("truncated inet_diag_msg"));
+                    };
+                    let lo = u32::from_ne_bytes(cookie[..4].try_into().unwrap()) as u64;
+                    let hi = u32::from_ne_bytes(cookie[4..].try_into().unwrap()) as u64;
+                    Ok(Some(hi << 32 | lo))
+                }
+                other => Err(io::Error::other(format!(
+                    "unexpected netlink message type: {other}"
+                ))),
+            };
+        }
+    }
+}
+
+fn addr_bytes(addr: IpAddr) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    match addr {
+        IpAddr::V4(a) => bytes[..4].copy_from_slice(&a.octets()),
+        IpAddr::V6(a) => bytes.copy_from_slice(&a.octets()),
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream, UdpSocket};
+    use std::os::fd::AsFd;
+
+    fn cookie_of(sock: impl AsFd) -> u64 {
+        let mut cookie = 0u64;
+        let mut len = size_of::<u64>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                sock.as_fd().as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_COOKIE,
+                &mut cookie as *mut u64 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0);
+        cookie
+    }
+
+    #[test]
+    fn tcp_cookie() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut diag = SockDiag::new().unwrap();
+        let cookie = diag
+            .socket_cookie(
+                libc::IPPROTO_TCP as u8,
+                stream.local_addr().unwrap(),
+                stream.peer_addr().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(cookie, Some(cookie_of(&stream)));
+    }
+
+    #[test]
+    fn udp_cookie() {
+        // unconnected socket, as is common for UDP.
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut diag = SockDiag::new().unwrap();
+        let cookie = diag
+            .socket_cookie(
+                libc::IPPROTO_UDP as u8,
+                sock.local_addr().unwrap(),
+                "127.0.0.1:53".parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(cookie, Some(cookie_of(&sock)));
+    }
+
+    #[test]
+    fn no_socket() {
+        let mut diag = SockDiag::new().unwrap();
+        let cookie = diag
+            .socket_cookie(
+                libc::IPPROTO_TCP as u8,
+                "127.0.0.1:1".parse().unwrap(),
+                "127.0.0.1:2".parse().unwrap(),
+            )
+            .unwrap();
+        assert_eq!(cookie, None);
+    }
+}
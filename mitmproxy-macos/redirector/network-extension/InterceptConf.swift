@@ -1,40 +1,374 @@
 import Foundation
+import Network
+
+/// The remote end of a flow.
+/// The address is unknown for TCP flows opened by hostname, and address and port are unknown
+/// for UDP flows, where they are only known per datagram.
+struct Destination {
+    var address: [UInt8]?  // IPv4-mapped for IPv4 addresses
+    var port: UInt16?
+    var proto: Proto
+
+    init(host: String? = nil, port: String? = nil, proto: Proto) {
+        self.address = host.flatMap(mappedAddress)
+        self.port = port.flatMap { UInt16($0) }
+        self.proto = proto
+    }
+}
+
+enum Proto: String {
+    case tcp
+    case udp
+}
+
+enum InterceptConfError: Error {
+    case invalidSpec(String)
+    case invalidPattern(String)
+}
 
 enum Action {
-    case include(Pattern)
-    case exclude(Pattern)
+    case include(Expr)
+    case exclude(Expr)
 
     init(from string: String) throws {
-        if string.hasPrefix("!") {
-            self = .exclude(Pattern(from: String(string.dropFirst())))
-        } else {
-            self = .include(Pattern(from: string))
+        var parser = SpecParser(string)
+        self = try parser.action()
+        try parser.end()
+    }
+
+    func matches(_ processInfo: ProcessInfo, _ destination: Destination?) -> Bool {
+        switch self {
+        case .include(let expr), .exclude(let expr):
+            return expr.matches(processInfo, destination)
         }
     }
 }
 
+indirect enum Expr {
+    case pattern(Pattern)
+    /// All expressions must match.
+    case and([Expr])
+    /// At least one expression must match.
+    case or([Expr])
+
+    func matches(_ processInfo: ProcessInfo, _ destination: Destination?) -> Bool {
+        switch self {
+        case .pattern(let pattern):
+            return pattern.matches(processInfo, destination)
+        case .and(let terms):
+            return terms.allSatisfy { $0.matches(processInfo, destination) }
+        case .or(let alternatives):
+            return alternatives.contains { $0.matches(processInfo, destination) }
+        }
+    }
+}
+
+/// Mirrored after the parser in the Rust implementation, which also validates specs before they
+/// are sent to us. Errors are therefore not reported in detail.
+struct SpecParser {
+    private static let prefixes: Set<String> = [
+        "pid", "tree", "name", "path", "uid", "gid", "cgroup", "netns", "dst", "port", "proto",
+    ]
+
+    private let chars: [Character]
+    private var pos = 0
+
+    init(_ string: String) {
+        chars = Array(string)
+    }
+
+    /// The next non-whitespace character.
+    private mutating func peek() -> Character? {
+        while pos < chars.count && chars[pos].isWhitespace {
+            pos += 1
+        }
+        return pos < chars.count ? chars[pos] : nil
+    }
+
+    private mutating func eat(_ c: Character) -> Bool {
+        if peek() == c {
+            pos += 1
+            return true
+        }
+        return false
+    }
+
+    private func error() -> InterceptConfError {
+        InterceptConfError.invalidSpec(String(chars))
+    }
+
+    mutating func end() throws {
+        if peek() != nil {
+            throw error()
+        }
+    }
+
+    mutating func action() throws -> Action {
+        if eat("!") {
+            return .exclude(try expr())
+        }
+        return .include(try expr())
+    }
+
+    private mutating func expr() throws -> Expr {
+        var alternatives = [try and()]
+        while eat("|") {
+            alternatives.append(try and())
+        }
+        return alternatives.count == 1 ? alternatives[0] : .or(alternatives)
+    }
+
+    private mutating func and() throws -> Expr {
+        var terms = [try term()]
+        while eat("&") {
+            terms.append(try term())
+        }
+        return terms.count == 1 ? terms[0] : .and(terms)
+    }
+
+    private mutating func term() throws -> Expr {
+        switch peek() {
+        case "(":
+            pos += 1
+            let expr = try expr()
+            guard eat(")") else {
+                throw error()
+            }
+            return expr
+        case nil, "!", ",", "&", "|", ")":
+            throw error()
+        default:
+            return .pattern(try pattern())
+        }
+    }
+
+    private mutating func pattern() throws -> Pattern {
+        var prefix: String? = nil
+        if let colon = chars[pos...].firstIndex(of: ":"),
+            SpecParser.prefixes.contains(String(chars[pos..<colon]))
+        {
+            prefix = String(chars[pos..<colon])
+            pos = colon + 1
+        }
+        let regex = (prefix == "name" || prefix == "path") && eat("~")
+        _ = peek()
+        let (value, quoted) = try self.value()
+        return try Pattern(prefix: prefix, regex: regex, value: value, quoted: quoted)
+    }
+
+    /// Read a quoted value, or a bare value up to the next `,`, `&`, `|` or unbalanced `)`.
+    private mutating func value() throws -> (String, Bool) {
+        if pos < chars.count && chars[pos] == "\"" {
+            pos += 1
+            var value = ""
+            while pos < chars.count {
+                let c = chars[pos]
+                pos += 1
+                if c == "\"" {
+                    return (value, true)
+                }
+                if c == "\\" && pos < chars.count && (chars[pos] == "\"" || chars[pos] == "\\") {
+                    value.append(chars[pos])
+                    pos += 1
+                } else {
+                    value.append(c)
+                }
+            }
+            throw error()
+        }
+        let start = pos
+        var parens = 0
+        scan: while pos < chars.count {
+            switch chars[pos] {
+            case ",", "&", "|":
+                break scan
+            case "(":
+                parens += 1
+            case ")":
+                if parens == 0 {
+                    break scan
+                }
+                parens -= 1
+            default:
+                break
+            }
+            pos += 1
+        }
+        let value = String(chars[start..<pos]).trimmingCharacters(in: .whitespacesAndNewlines)
+        return (value, false)
+    }
+}
+
 enum Pattern {
     case pid(UInt32)
+    case tree(UInt32)
     case process(String)
+    case path(String)
+    case processRegex(NSRegularExpression)
+    case pathRegex(NSRegularExpression)
+    case dst([UInt8], Int)  // IPv4-mapped network and prefix length
+    case port(UInt16, UInt16)
+    case proto(Proto)
 
-    init(from string: String) {
-        if let pid = UInt32(string) {
+    init(prefix: String?, regex: Bool, value: String, quoted: Bool) throws {
+        guard !value.isEmpty else {
+            throw InterceptConfError.invalidPattern(value)
+        }
+        switch prefix {
+        case nil:
+            if !quoted, let pid = UInt32(value) {
+                self = .pid(pid)
+            } else {
+                self = .process(value)
+            }
+        case "pid":
+            guard let pid = UInt32(value) else {
+                throw InterceptConfError.invalidPattern(value)
+            }
             self = .pid(pid)
-        } else {
-            self = .process(string)
+        case "tree":
+            guard let pid = UInt32(value) else {
+                throw InterceptConfError.invalidPattern(value)
+            }
+            self = .tree(pid)
+        case "name" where regex:
+            self = .processRegex(try NSRegularExpression(pattern: value))
+        case "name":
+            self = .process(value)
+        case "path" where regex:
+            self = .pathRegex(try NSRegularExpression(pattern: value))
+        case "path":
+            self = .path(value)
+        case "dst":
+            let parts = value.split(separator: "/", maxSplits: 1)
+            guard let host = parts.first.map(String.init), let address = mappedAddress(host) else {
+                throw InterceptConfError.invalidPattern(value)
+            }
+            let isIPv4 = IPv4Address(host) != nil
+            var prefixLen = isIPv4 ? 32 : 128
+            if parts.count == 2 {
+                guard let len = Int(parts[1]), len <= prefixLen else {
+                    throw InterceptConfError.invalidPattern(value)
+                }
+                prefixLen = len
+            }
+            self = .dst(address, isIPv4 ? prefixLen + 96 : prefixLen)
+        case "port":
+            let parts = value.split(separator: "-", maxSplits: 1)
+            guard let min = UInt16(parts.first ?? ""), let max = UInt16(parts.last ?? ""),
+                min <= max
+            else {
+                throw InterceptConfError.invalidPattern(value)
+            }
+            self = .port(min, max)
+        case "proto":
+            guard let proto = Proto(rawValue: value) else {
+                throw InterceptConfError.invalidPattern(value)
+            }
+            self = .proto(proto)
+        default:
+            // Linux-only patterns are rejected before specs are sent to us.
+            throw InterceptConfError.invalidPattern(value)
         }
     }
 
-    func matches(_ processInfo: ProcessInfo) -> Bool {
+    func matches(_ processInfo: ProcessInfo, _ destination: Destination?) -> Bool {
         switch self {
         case .pid(let pid):
             return processInfo.pid == pid
+        case .tree(let pid):
+            return processInfo.pid == pid || processInfo.ancestors.contains(pid)
         case .process(let name):
-            if let processName = processInfo.path {
-                return processName.contains(name)
+            if let path = processInfo.path {
+                let fileName = (path as NSString).lastPathComponent
+                return globMatch(Array(name.utf8), Array(fileName.utf8))
             } else {
-                return false 
+                return false
             }
+        case .path(let pattern):
+            guard let path = processInfo.path else { return false }
+            return globMatch(Array(pattern.utf8), Array(path.utf8))
+        case .processRegex(let regex):
+            guard let path = processInfo.path else { return false }
+            return regexMatch(regex, (path as NSString).lastPathComponent)
+        case .pathRegex(let regex):
+            guard let path = processInfo.path else { return false }
+            return regexMatch(regex, path)
+        // Destination patterns don't match if the destination is not known.
+        case .dst(let network, let prefixLen):
+            guard let address = destination?.address else { return false }
+            return prefixMatch(network, prefixLen, address)
+        case .port(let min, let max):
+            guard let port = destination?.port else { return false }
+            return min <= port && port <= max
+        case .proto(let proto):
+            return destination?.proto == proto
+        }
+    }
+}
+
+/// A 16-byte address, with IPv4 addresses as IPv4-mapped IPv6 addresses.
+func mappedAddress(_ host: String) -> [UInt8]? {
+    if let ipv4 = IPv4Address(host) {
+        return [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff] + Array(ipv4.rawValue)
+    }
+    if let ipv6 = IPv6Address(host) {
+        return Array(ipv6.rawValue)
+    }
+    return nil
+}
+
+/// Mirrored after `prefix_match` in mitmproxy-linux-ebpf-common.
+func prefixMatch(_ prefix: [UInt8], _ prefixLen: Int, _ address: [UInt8]) -> Bool {
+    for i in 0..<16 {
+        let bits = min(max(prefixLen - i * 8, 0), 8)
+        if bits == 0 {
+            break
+        }
+        let mask = UInt8(truncatingIfNeeded: 0xff00 >> bits)
+        if (prefix[i] ^ address[i]) & mask != 0 {
+            return false
+        }
+    }
+    return true
+}
+
+/// Regexes are unanchored, as in the Rust implementation.
+func regexMatch(_ regex: NSRegularExpression, _ string: String) -> Bool {
+    let range = NSRange(string.startIndex..., in: string)
+    return regex.firstMatch(in: string, range: range) != nil
+}
+
+/// Mirrored after `glob_match` in mitmproxy-linux-ebpf-common:
+/// `*` matches any sequence of bytes, `?` matches a single byte.
+func globMatch(_ pattern: [UInt8], _ name: [UInt8]) -> Bool {
+    var p = 0
+    var n = 0
+    // Position after the last `*` and the position in name it currently matches up to.
+    var backtrack: (Int, Int)? = nil
+    while true {
+        if p < pattern.count && pattern[p] == UInt8(ascii: "*") {
+            p += 1
+            backtrack = (p, n)
+            continue
+        }
+        if p < pattern.count && n < name.count
+            && (pattern[p] == UInt8(ascii: "?") || pattern[p] == name[n])
+        {
+            p += 1
+            n += 1
+            continue
+        }
+        if p == pattern.count && n == name.count {
+            return true
+        }
+        // Mismatch: let the last `*` consume one more byte.
+        if case let (bp, bn)? = backtrack, bn < name.count {
+            backtrack = (bp, bn + 1)
+            p = bp
+            n = bn + 1
+        } else {
+            return false
         }
     }
 }
@@ -58,15 +392,15 @@ class InterceptConf {
     }
     
     /// Mirrored after the Rust implementation
-    func shouldIntercept(_ processInfo: ProcessInfo) -> Bool {
+    func shouldIntercept(_ processInfo: ProcessInfo, _ destination: Destination?) -> Bool {
         var intercept = self.defaultAction
         
         for action in actions {
             switch action {
-            case .include(let pattern):
-                intercept = intercept || pattern.matches(processInfo)
-            case .exclude(let pattern):
-                intercept = intercept && !pattern.matches(processInfo)
+            case .include:
+                intercept = intercept || action.matches(processInfo, destination)
+            case .exclude:
+                intercept = intercept && !action.matches(processInfo, destination)
             }
         }
         
@@ -3,6 +3,8 @@ import Foundation
 struct ProcessInfo {
     var pid: UInt32
     var path: String?
+    /// PIDs of the process' parent, grandparent, and so on.
+    var ancestors: [UInt32]
 }
 
 let PROC_PIDPATHINFO_MAXSIZE = UInt32(MAXPATHLEN * 4)
@@ -41,8 +43,26 @@ class ProcessInfoCache {
             path = nil
         }
 
-        let procInfo = ProcessInfo(pid: UInt32(pid), path: path)
+        let procInfo = ProcessInfo(pid: UInt32(pid), path: path, ancestors: ancestors(of: pid))
         cache[tokenData] = procInfo
         return procInfo
     }
 }
+
+/// Walk up the process tree using the parent PIDs reported by proc_pidinfo.
+func ancestors(of pid: pid_t) -> [UInt32] {
+    var ancestors: [UInt32] = []
+    var current = pid
+    var info = proc_bsdinfo()
+    let size = Int32(MemoryLayout<proc_bsdinfo>.size)
+    while proc_pidinfo(current, PROC_PIDTBSDINFO, 0, &info, size) == size {
+        let parent = info.pbi_ppid
+        // The kernel (PID 0) is the parent of launchd. PIDs get reused, so guard against cycles.
+        if parent == 0 || parent == UInt32(pid) || ancestors.contains(parent) {
+            break
+        }
+        ancestors.append(parent)
+        current = pid_t(parent)
+    }
+    return ancestors
+}
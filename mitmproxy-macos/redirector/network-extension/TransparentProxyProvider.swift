@@ -96,7 +96,17 @@ class TransparentProxyProvider: NETransparentProxyProvider {
             log.debug("Skipping flow, no intercept spec provided.")
             return false
         }
-        guard spec.shouldIntercept(processInfo) else {
+        // The destination of UDP flows is only known per datagram, and we cannot hand individual
+        // datagrams back to the system. So only `proto:` patterns can match UDP flows.
+        var destination: Destination? = nil
+        if let tcp_flow = flow as? NEAppProxyTCPFlow {
+            let remoteEndpoint = tcp_flow.remoteEndpoint as? NWHostEndpoint
+            destination = Destination(
+                host: remoteEndpoint?.hostname, port: remoteEndpoint?.port, proto: .tcp)
+        } else if flow is NEAppProxyUDPFlow {
+            destination = Destination(proto: .udp)
+        }
+        guard spec.shouldIntercept(processInfo, destination) else {
             log.debug("Flow not in scope, leaving it to the system.")
             return false
         }
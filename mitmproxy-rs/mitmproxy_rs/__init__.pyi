@@ -1,6 +1,6 @@
 from __future__ import annotations
 
-from typing import Any, Literal
+from typing import Any, Literal, TypedDict
 from typing import final, overload, TypeVar
 from . import certs, contentviews, dns, local, process_info, tun, udp, wireguard, syntax_highlight
 
@@ -8,6 +8,20 @@ T = TypeVar("T")
 
 # TCP / UDP
 
+class TcpSynInfo(TypedDict):
+    ttl: int
+    window: int
+    options: list[int]
+    mss: int | None
+    window_scale: int | None
+    sack_permitted: bool
+    timestamp: tuple[int, int] | None
+
+class WireGuardPeerTunnelInfo(TypedDict):
+    public_key: str
+    name: str
+    endpoints: list[tuple[tuple[str, int], float | None]]
+
 @final
 class Stream:
     async def read(self, n: int) -> bytes: ...
@@ -52,6 +66,30 @@ class Stream:
     @overload
     def get_extra_info(self, name: Literal["process_name"], default: T) -> str | T: ...
     @overload
+    def get_extra_info(
+        self, name: Literal["tcp_syn"], default: None = None
+    ) -> TcpSynInfo: ...
+    @overload
+    def get_extra_info(
+        self, name: Literal["tcp_syn"], default: T
+    ) -> TcpSynInfo | T: ...
+    @overload
+    def get_extra_info(
+        self, name: Literal["wireguard_peer"], default: None = None
+    ) -> WireGuardPeerTunnelInfo: ...
+    @overload
+    def get_extra_info(
+        self, name: Literal["wireguard_peer"], default: T
+    ) -> WireGuardPeerTunnelInfo | T: ...
+    @overload
+    def get_extra_info(
+        self, name: Literal["close_reason"], default: None = None
+    ) -> Literal["fin", "reset", "timeout", "keepalive_expired", "shutdown"]: ...
+    @overload
+    def get_extra_info(
+        self, name: Literal["close_reason"], default: T
+    ) -> Literal["fin", "reset", "timeout", "keepalive_expired", "shutdown"] | T: ...
+    @overload
     def get_extra_info(self, name: str, default: Any) -> Any: ...
     def __repr__(self) -> str: ...
 
@@ -7,13 +7,21 @@ from . import Stream
 async def start_local_redirector(
     handle_tcp_stream: Callable[[Stream], Awaitable[None]],
     handle_udp_stream: Callable[[Stream], Awaitable[None]],
+    cgroup: str | None = None,
+    tcp_fast_open: bool = False,
 ) -> LocalRedirector: ...
 @final
 class LocalRedirector:
     @staticmethod
     def describe_spec(spec: str) -> None: ...
-    def set_intercept(self, spec: str) -> None: ...
-    def close(self) -> None: ...
+    def set_intercept(self, spec: str) -> None:
+        """
+        Set a new intercept spec.
+
+        Process names match the file name of the executable exactly, with `*` and `?` as wildcards.
+        Names are no longer matched as substrings: use `*mitm*` instead of `mitm` to match `mitmproxy`.
+        """
+    def close(self, timeout: float | None = None) -> None: ...
     async def wait_closed(self) -> None: ...
     @staticmethod
     def unavailable_reason() -> str | None: ...
@@ -8,11 +8,24 @@ async def create_tun_interface(
     handle_tcp_stream: Callable[[Stream], Awaitable[None]],
     handle_udp_stream: Callable[[Stream], Awaitable[None]],
     tun_name: str | None = None,
+    ipv6_address: str | None = None,
+    queues: int = 1,
+    routes: list[str] = [],
+    fwmark: int | None = None,
+    uids: list[tuple[int, int]] = [],
+    tcp_fast_open: bool = False,
+) -> TunInterface: ...
+async def create_tap_interface(
+    handle_tcp_stream: Callable[[Stream], Awaitable[None]],
+    handle_udp_stream: Callable[[Stream], Awaitable[None]],
+    tap_name: str | None = None,
+    bridge: str | None = None,
+    gateways: list[str] = [],
 ) -> TunInterface: ...
 @final
 class TunInterface:
     def tun_name(self) -> str: ...
-    def close(self) -> None: ...
+    def close(self, timeout: float | None = None) -> None: ...
     async def wait_closed(self) -> None: ...
     def __repr__(self) -> str: ...
     @staticmethod
@@ -20,5 +33,6 @@ class TunInterface:
 
 __all__ = [
     "create_tun_interface",
+    "create_tap_interface",
     "TunInterface",
 ]
@@ -12,7 +12,7 @@ async def start_udp_server(
 @final
 class UdpServer:
     def getsockname(self) -> tuple[str, int]: ...
-    def close(self) -> None: ...
+    def close(self, timeout: float | None = None) -> None: ...
     async def wait_closed(self) -> None: ...
     def __repr__(self) -> str: ...
 
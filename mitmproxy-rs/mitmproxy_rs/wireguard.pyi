@@ -1,9 +1,27 @@
 from __future__ import annotations
 
 from collections.abc import Awaitable, Callable
-from typing import final
+from typing import final, NotRequired, TypedDict
 from . import Stream
 
+class WireGuardPeer(TypedDict):
+    public_key: str
+    name: NotRequired[str | None]
+    allowed_ips: NotRequired[list[str]]
+    preshared_key: NotRequired[str]
+    persistent_keepalive: NotRequired[int | None]
+    endpoint: NotRequired[tuple[str, int]]
+
+class WireGuardPeerInfo(TypedDict):
+    public_key: str
+    name: str
+    allowed_ips: list[str]
+    endpoint: tuple[str, int] | None
+    endpoint_history: list[tuple[tuple[str, int], float | None]]
+    last_handshake: float | None
+    rx_bytes: int
+    tx_bytes: int
+
 def genkey() -> str: ...
 def pubkey(private_key: str) -> str: ...
 async def start_wireguard_server(
@@ -13,11 +31,46 @@ async def start_wireguard_server(
     peer_public_keys: list[str],
     handle_tcp_stream: Callable[[Stream], Awaitable[None]],
     handle_udp_stream: Callable[[Stream], Awaitable[None]],
+    *,
+    peers: list[WireGuardPeer] | None = None,
+    additional_listen_addrs: list[tuple[str, int]] | None = None,
+    tcp_fast_open: bool = False,
 ) -> WireGuardServer: ...
+async def start_wireguard_server_from_config(
+    host: str,
+    config: str,
+    handle_tcp_stream: Callable[[Stream], Awaitable[None]],
+    handle_udp_stream: Callable[[Stream], Awaitable[None]],
+    *,
+    tcp_fast_open: bool = False,
+) -> WireGuardServer: ...
+def client_config(server_config: str, client_private_key: str, endpoint: str) -> str: ...
+async def start_wireguard_client(
+    config: str,
+    handle_tcp_stream: Callable[[Stream], Awaitable[None]],
+    handle_udp_stream: Callable[[Stream], Awaitable[None]],
+) -> WireGuardClient: ...
 @final
 class WireGuardServer:
+    def getsockname(self) -> tuple[str, int] | list[tuple[str, int]]:
+        """
+        Get the addresses the server listens on.
+
+        This is a single `(host, port)` tuple unless `additional_listen_addrs` are configured,
+        in which case it is a list of all addresses, starting with `host` and `port`.
+        """
+    def close(self, timeout: float | None = None) -> None: ...
+    async def wait_closed(self) -> None: ...
+    def add_peer(self, peer: WireGuardPeer) -> None: ...
+    def remove_peer(self, public_key: str) -> None: ...
+    async def peers(self) -> list[WireGuardPeerInfo]: ...
+    async def dropped_packets(self) -> int: ...
+    def __repr__(self) -> str: ...
+
+@final
+class WireGuardClient:
     def getsockname(self) -> tuple[str, int]: ...
-    def close(self) -> None: ...
+    def close(self, timeout: float | None = None) -> None: ...
     async def wait_closed(self) -> None: ...
     def __repr__(self) -> str: ...
 
@@ -25,5 +78,9 @@ __all__ = [
     "genkey",
     "pubkey",
     "start_wireguard_server",
+    "start_wireguard_server_from_config",
+    "client_config",
     "WireGuardServer",
+    "start_wireguard_client",
+    "WireGuardClient",
 ]
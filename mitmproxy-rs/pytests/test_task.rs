@@ -4,7 +4,9 @@ mod tests {
     use std::ffi::CString;
     use std::future::Future;
 
-    use mitmproxy::messages::{ConnectionId, TransportEvent, TunnelInfo};
+    use mitmproxy::messages::{
+        CloseReason, ConnectionId, ConnectionIdGenerator, TransportEvent, TunnelInfo,
+    };
     use mitmproxy_rs::task::PyInteropTask;
     use pyo3::prelude::*;
     use pyo3::types::PyDict;
@@ -55,6 +57,64 @@ mod tests {
         Ok(())
     }
 
+    #[pyo3_async_runtimes::tokio::test]
+    async fn test_handler_wait_closed() -> PyResult<()> {
+        let logger = setup_logger().await;
+        let (command_tx, _command_rx) = mpsc::unbounded_channel();
+        let (event_tx, event_rx) = mpsc::channel(1);
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+
+        let handler = Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            let code = CString::new(
+                "async def handler(stream):\n    await stream.wait_closed()\n    raise RuntimeError(stream.get_extra_info('close_reason'))",
+            )
+            .unwrap();
+            py.run(&code, None, Some(&locals)).unwrap();
+            locals.get_item("handler").unwrap().unwrap().unbind()
+        });
+        let udp_handler = Python::with_gil(|py| handler.clone_ref(py));
+
+        let task = PyInteropTask::new(command_tx, event_rx, handler, udp_handler, shutdown_rx)?;
+        let task = tokio::spawn(task.run());
+
+        // connections with a dedicated command channel report their close reason the same way.
+        let (dedicated_tx, _dedicated_rx) = mpsc::unbounded_channel();
+        let mut ids = ConnectionIdGenerator::udp();
+        for (dedicated_tx, reason) in [
+            (None, CloseReason::Timeout),
+            (Some(dedicated_tx), CloseReason::Reset),
+        ] {
+            let connection_id = ids.next_id();
+            event_tx
+                .send(TransportEvent::ConnectionEstablished {
+                    connection_id,
+                    src_addr: "127.0.0.1:51232".parse()?,
+                    dst_addr: "127.0.0.1:53".parse()?,
+                    tunnel_info: TunnelInfo::None,
+                    tcp_syn: None,
+                    command_tx: dedicated_tx,
+                })
+                .await
+                .unwrap();
+            event_tx
+                .send(TransportEvent::ConnectionClosed {
+                    connection_id,
+                    reason,
+                })
+                .await
+                .unwrap();
+
+            logger
+                .wait_for(&format!("RuntimeError: {}", reason.as_str()))
+                .await;
+        }
+
+        shutdown_tx.send().unwrap();
+        task.await.unwrap()?;
+        Ok(())
+    }
+
     async fn _test_task_error_handling<F>(code: &str, verify: F) -> PyResult<()>
     where
         F: Future<Output = ()>,
@@ -80,6 +140,7 @@ mod tests {
                 src_addr: "127.0.0.1:51232".parse()?,
                 dst_addr: "127.0.0.1:53".parse()?,
                 tunnel_info: TunnelInfo::None,
+                tcp_syn: None,
                 command_tx: None,
             })
             .await
@@ -89,7 +150,7 @@ mod tests {
 
         verify.await;
 
-        shutdown_tx.send(()).unwrap();
+        shutdown_tx.send().unwrap();
         task.await.unwrap()?;
         Ok(())
     }
@@ -65,7 +65,7 @@ mod mitmproxy_rs {
     #[pymodule]
     mod tun {
         #[pymodule_export]
-        use crate::server::{create_tun_interface, TunInterface};
+        use crate::server::{create_tap_interface, create_tun_interface, TunInterface};
     }
 
     #[pymodule]
@@ -79,7 +79,12 @@ mod mitmproxy_rs {
     #[pymodule]
     mod wireguard {
         #[pymodule_export]
-        use crate::server::{start_wireguard_server, WireGuardServer};
+        use crate::server::{
+            client_config, start_wireguard_server, start_wireguard_server_from_config,
+            WireGuardServer,
+        };
+        #[pymodule_export]
+        use crate::server::{start_wireguard_client, WireGuardClient};
         #[pymodule_export]
         use crate::util::{genkey, pubkey};
     }
@@ -1,28 +1,38 @@
 use crate::task::PyInteropTask;
 
+use std::time::Duration;
+
 use anyhow::Result;
 
 use mitmproxy::packet_sources::{PacketSourceConf, PacketSourceTask};
 use mitmproxy::shutdown::shutdown_task;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
 use mitmproxy::shutdown;
 use tokio::sync::mpsc;
-use tokio::sync::watch;
 use tokio::task::JoinSet;
 
 #[derive(Debug)]
 pub struct Server {
     shutdown_done: shutdown::Receiver,
-    start_shutdown: Option<watch::Sender<()>>,
+    start_shutdown: Option<shutdown::Sender>,
 }
 
 impl Server {
-    pub fn close(&mut self) {
+    /// Trigger a shutdown. If `timeout` (in seconds) is given, pending data of existing
+    /// connections is drained for at most this long before all tasks exit.
+    pub fn close(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        let drain_timeout = match timeout {
+            Some(t) => Duration::try_from_secs_f64(t)
+                .map_err(|_| PyValueError::new_err(format!("Invalid timeout: {t}")))?,
+            None => Duration::ZERO,
+        };
         if let Some(trigger) = self.start_shutdown.take() {
             log::debug!("Shutting down.");
-            trigger.send(()).ok();
+            trigger.send_with_drain_timeout(drain_timeout).ok();
         }
+        Ok(())
     }
 
     pub fn wait_closed<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
@@ -94,6 +104,6 @@ impl Server {
 
 impl Drop for Server {
     fn drop(&mut self) {
-        self.close()
+        self.close(None).ok();
     }
 }
@@ -31,20 +31,44 @@ impl LocalRedirector {
     }
 }
 
+/// Parse an intercept spec and make sure that it can be enforced on this platform.
+fn parse_spec(spec: &str) -> anyhow::Result<InterceptConf> {
+    let conf = InterceptConf::try_from(spec)?;
+    #[cfg(target_os = "linux")]
+    {
+        use mitmproxy::intercept_conf::ebpf::{EbpfConf, SystemResolver};
+        EbpfConf::new(&conf, &SystemResolver)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    anyhow::ensure!(
+        !conf.requires_linux(),
+        "user, group, cgroup and network namespace patterns are only supported on Linux"
+    );
+    Ok(conf)
+}
+
 #[pymethods]
 impl LocalRedirector {
     /// Return a textual description of the given spec,
     /// or raise a ValueError if the spec is invalid.
     #[staticmethod]
     fn describe_spec(spec: &str) -> PyResult<String> {
-        InterceptConf::try_from(spec)
-            .map(|conf| conf.description())
-            .map_err(|e| PyValueError::new_err(format!("{e:?}")))
+        let conf = parse_spec(spec).map_err(|e| PyValueError::new_err(format!("{e:?}")))?;
+        #[allow(unused_mut)]
+        let mut description = conf.description();
+        #[cfg(target_os = "macos")]
+        if conf.matches_addresses() {
+            description.push_str(
+                " On macOS, dst: and port: patterns do not match UDP, \
+                 and dst: patterns do not match TCP connections to hostnames.",
+            );
+        }
+        Ok(description)
     }
 
     /// Set a new intercept spec.
     pub fn set_intercept(&mut self, spec: String) -> PyResult<()> {
-        let conf = InterceptConf::try_from(spec.as_str())?;
+        let conf = parse_spec(&spec)?;
         self.spec = spec;
         self.conf_tx
             .send(conf)
@@ -53,8 +77,12 @@ impl LocalRedirector {
     }
 
     /// Close the OS proxy server.
-    pub fn close(&mut self) {
-        self.server.close()
+    ///
+    /// If `timeout` is given, existing TCP connections are drained and closed
+    /// for up to `timeout` seconds before the server exits.
+    #[pyo3(signature = (timeout=None))]
+    pub fn close(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        self.server.close(timeout)
     }
 
     pub fn wait_closed<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyAny>> {
@@ -92,14 +120,22 @@ impl LocalRedirector {
 ///
 /// - `handle_tcp_stream`: An async function that will be called for each new TCP `Stream`.
 /// - `handle_udp_stream`: An async function that will be called for each new UDP `Stream`.
+/// - `cgroup`: Only intercept processes in this cgroup (e.g. `/system.slice/docker-<id>.scope`)
+///   and its descendants. By default, all processes are considered. *Linux only.*
+/// - `tcp_fast_open`: Advertise TCP Fast Open support by handing out cookies to intercepted
+///   processes. If enabled, data sent along with a SYN is only accepted with a valid cookie.
+///   *Windows and Linux only.*
 ///
 /// *Availability: Windows, Linux, and macOS*
 #[pyfunction]
 #[allow(unused_variables)]
+#[pyo3(signature = (handle_tcp_stream, handle_udp_stream, cgroup=None, tcp_fast_open=false))]
 pub fn start_local_redirector(
     py: Python<'_>,
     handle_tcp_stream: PyObject,
     handle_udp_stream: PyObject,
+    cgroup: Option<String>,
+    tcp_fast_open: bool,
 ) -> PyResult<Bound<PyAny>> {
     #[cfg(windows)]
     {
@@ -110,7 +146,10 @@ pub fn start_local_redirector(
         if !executable_path.exists() {
             return Err(anyhow::anyhow!("{} does not exist", executable_path.display()).into());
         }
-        let conf = WindowsConf { executable_path };
+        let conf = WindowsConf {
+            executable_path,
+            tcp_fast_open,
+        };
         pyo3_async_runtimes::tokio::future_into_py(py, async move {
             let (server, conf_tx) =
                 Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
@@ -127,7 +166,11 @@ pub fn start_local_redirector(
         if !executable_path.exists() {
             return Err(anyhow::anyhow!("{} does not exist", executable_path.display()).into());
         }
-        let conf = LinuxConf { executable_path };
+        let conf = LinuxConf {
+            executable_path,
+            cgroup,
+            tcp_fast_open,
+        };
         pyo3_async_runtimes::tokio::future_into_py(py, async move {
             let (server, conf_tx) =
                 Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
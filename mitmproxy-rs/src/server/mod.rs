@@ -3,8 +3,12 @@ mod local_redirector;
 mod tun;
 mod udp;
 mod wireguard;
+mod wireguard_client;
 
 pub use local_redirector::{start_local_redirector, LocalRedirector};
-pub use tun::{create_tun_interface, TunInterface};
+pub use tun::{create_tap_interface, create_tun_interface, TunInterface};
 pub use udp::{start_udp_server, UdpServer};
-pub use wireguard::{start_wireguard_server, WireGuardServer};
+pub use wireguard::{
+    client_config, start_wireguard_server, start_wireguard_server_from_config, WireGuardServer,
+};
+pub use wireguard_client::{start_wireguard_client, WireGuardClient};
@@ -22,8 +22,12 @@ impl TunInterface {
     }
 
     /// Request the interface to be closed.
-    pub fn close(&mut self) {
-        self.server.close()
+    ///
+    /// If `timeout` is given, existing TCP connections are drained and closed
+    /// for up to `timeout` seconds before the interface is shut down.
+    #[pyo3(signature = (timeout=None))]
+    pub fn close(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        self.server.close(timeout)
     }
 
     /// Wait until the interface has shut down.
@@ -57,20 +61,124 @@ impl TunInterface {
 /// - `handle_tcp_stream`: An async function that will be called for each new TCP `Stream`.
 /// - `handle_udp_stream`: An async function that will be called for each new UDP `Stream`.
 /// - `tun_name`: An optional string to specify the tunnel name. By default, tun0, ... will be used.
+/// - `ipv6_address`: The IPv6 address (with prefix length) to assign to the interface.
+///   By default, `fe80::1/64` is used.
+/// - `queues`: The number of queues. If larger than one, a multi-queue device is created
+///   and packets are read from all queues in parallel.
+/// - `routes`: A list of CIDRs (e.g. `"10.0.0.0/8"`) that are routed through the interface.
+/// - `fwmark`: If set, only packets with this firewall mark are routed through the interface.
+/// - `uids`: If set, only packets from sockets owned by these `(start, end)` user id ranges are
+///   routed through the interface.
+/// - `tcp_fast_open`: Advertise TCP Fast Open support by handing out cookies to clients.
+///   If enabled, data sent along with a SYN is only accepted with a valid cookie.
+///
+/// Routes, policy rules and all changed sysctls are restored when the interface is closed.
 ///
 /// *Availability: Linux*
 #[pyfunction]
 #[allow(unused_variables)]
-#[pyo3(signature = (handle_tcp_stream, handle_udp_stream, tun_name=None))]
+#[pyo3(signature = (handle_tcp_stream, handle_udp_stream, tun_name=None, ipv6_address=None, queues=1, routes=Vec::new(), fwmark=None, uids=Vec::new(), tcp_fast_open=false))]
+#[allow(clippy::too_many_arguments)]
 pub fn create_tun_interface(
     py: Python<'_>,
     handle_tcp_stream: PyObject,
     handle_udp_stream: PyObject,
     tun_name: Option<String>,
+    ipv6_address: Option<String>,
+    queues: usize,
+    routes: Vec<String>,
+    fwmark: Option<u32>,
+    uids: Vec<(u32, u32)>,
+    tcp_fast_open: bool,
+) -> PyResult<Bound<PyAny>> {
+    #[cfg(target_os = "linux")]
+    {
+        use mitmproxy::packet_sources::tun::DEFAULT_IPV6_ADDRESS;
+        use mitmproxy::packet_sources::tun_routing::{RoutingConf, DEFAULT_ROUTING_TABLE};
+        let ipv6_address = match ipv6_address {
+            Some(addr) => addr.parse().map_err(|_| {
+                pyo3::exceptions::PyValueError::new_err(format!("invalid IPv6 CIDR: {addr}"))
+            })?,
+            None => DEFAULT_IPV6_ADDRESS,
+        };
+        let routes = routes
+            .iter()
+            .map(|r| {
+                r.parse().map_err(|_| {
+                    pyo3::exceptions::PyValueError::new_err(format!("invalid CIDR: {r}"))
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let conf = mitmproxy::packet_sources::tun::TunConf {
+            tun_name,
+            ipv6_address,
+            queues,
+            routing: RoutingConf {
+                routes,
+                fwmark,
+                uid_ranges: uids,
+                table: DEFAULT_ROUTING_TABLE,
+            },
+            tcp_fast_open,
+        };
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let (server, tun_name) =
+                Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
+            Ok(TunInterface { server, tun_name })
+        })
+    }
+    #[cfg(not(target_os = "linux"))]
+    Err(pyo3::exceptions::PyNotImplementedError::new_err(
+        TunInterface::unavailable_reason(),
+    ))
+}
+
+/// Create a TAP (layer-2) interface that is configured with the given parameters:
+///
+/// - `handle_tcp_stream`: An async function that will be called for each new TCP `Stream`.
+/// - `handle_udp_stream`: An async function that will be called for each new UDP `Stream`.
+/// - `tap_name`: An optional string to specify the interface name. By default, tap0, ... will be used.
+/// - `bridge`: An optional bridge to attach the interface to, e.g. one that VMs or containers are
+///   connected to. Requires `gateways`.
+/// - `gateways`: The addresses that ARP requests and IPv6 neighbor solicitations are answered for,
+///   usually the default gateway of the hosts on the bridge, so that they send their traffic to
+///   mitmproxy. If empty, all addresses that no host on the link uses are answered, which is
+///   only allowed without a `bridge`.
+///
+/// *Availability: Linux*
+#[pyfunction]
+#[allow(unused_variables)]
+#[pyo3(signature = (handle_tcp_stream, handle_udp_stream, tap_name=None, bridge=None, gateways=Vec::new()))]
+pub fn create_tap_interface(
+    py: Python<'_>,
+    handle_tcp_stream: PyObject,
+    handle_udp_stream: PyObject,
+    tap_name: Option<String>,
+    bridge: Option<String>,
+    gateways: Vec<String>,
 ) -> PyResult<Bound<PyAny>> {
     #[cfg(target_os = "linux")]
     {
-        let conf = mitmproxy::packet_sources::tun::TunConf { tun_name };
+        use mitmproxy::packet_sources::tap::{TapConf, DEFAULT_MAC_ADDRESS};
+        let gateways = gateways
+            .iter()
+            .map(|g| {
+                g.parse().map_err(|_| {
+                    pyo3::exceptions::PyValueError::new_err(format!("invalid IP address: {g}"))
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        if bridge.is_some() && gateways.is_empty() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "gateways must be specified when attaching the TAP interface to a bridge",
+            ));
+        }
+        let conf = TapConf {
+            tap_name,
+            bridge,
+            mac_address: DEFAULT_MAC_ADDRESS,
+            gateways,
+        };
         pyo3_async_runtimes::tokio::future_into_py(py, async move {
             let (server, tun_name) =
                 Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
@@ -24,9 +24,11 @@ impl UdpServer {
     /// Request the server to gracefully shut down.
     ///
     /// The server will stop accepting new connections on its UDP socket, but will flush pending
-    /// outgoing data before shutting down.
-    pub fn close(&mut self) {
-        self.server.close()
+    /// outgoing data before shutting down. If `timeout` is given, pending outgoing packets
+    /// are sent for up to `timeout` seconds before the server exits.
+    #[pyo3(signature = (timeout=None))]
+    pub fn close(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        self.server.close(timeout)
     }
 
     /// Wait until the server has shut down.
@@ -1,10 +1,20 @@
 use std::net::{IpAddr, SocketAddr};
+use std::time::UNIX_EPOCH;
 
-use crate::util::string_to_key;
+use crate::util::{
+    endpoint_history_to_py, event_queue_unavailable, key_to_string, socketaddr_to_py, string_to_key,
+};
 
-use mitmproxy::packet_sources::wireguard::WireGuardConf;
+use mitmproxy::packet_sources::wireguard::{
+    parse_allowed_ip, WireGuardCommand, WireGuardConf, WireGuardPeerConf, WireGuardPeerInfo,
+};
 
+use mitmproxy::wireguard_config::WgQuickConfig;
+use pyo3::exceptions::{PyKeyError, PyOSError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::IntoPyObjectExt;
+use tokio::sync::{mpsc, oneshot};
 
 use boringtun::x25519::PublicKey;
 
@@ -19,9 +29,11 @@ use crate::server::base::Server;
 #[pyclass(module = "mitmproxy_rs.wireguard")]
 #[derive(Debug)]
 pub struct WireGuardServer {
-    /// local address of the WireGuard UDP socket
-    local_addr: SocketAddr,
+    /// local addresses of the WireGuard UDP sockets
+    local_addrs: Vec<SocketAddr>,
     server: Server,
+    /// channel to add, remove and list peers
+    peers_tx: mpsc::UnboundedSender<WireGuardCommand>,
 }
 
 #[pymethods]
@@ -29,9 +41,11 @@ impl WireGuardServer {
     /// Request the WireGuard server to gracefully shut down.
     ///
     /// The server will stop accepting new connections on its UDP socket, but will flush pending
-    /// outgoing data before shutting down.
-    pub fn close(&mut self) {
-        self.server.close()
+    /// outgoing data before shutting down. If `timeout` is given, existing TCP connections are
+    /// drained and closed for up to `timeout` seconds before the server exits.
+    #[pyo3(signature = (timeout=None))]
+    pub fn close(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        self.server.close(timeout)
     }
 
     /// Wait until the WireGuard server has shut down.
@@ -42,13 +56,81 @@ impl WireGuardServer {
         self.server.wait_closed(py)
     }
 
-    /// Get the local socket address that the WireGuard server is listening on.
-    pub fn getsockname(&self) -> (String, u16) {
-        (self.local_addr.ip().to_string(), self.local_addr.port())
+    /// Get the local socket addresses that the WireGuard server is listening on.
+    ///
+    /// This is a single `(host, port)` tuple if the server listens on one address, as before
+    /// `additional_listen_addrs` existed, and a list of such tuples otherwise.
+    pub fn getsockname(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let mut addrs: Vec<(String, u16)> = self
+            .local_addrs
+            .iter()
+            .map(|addr| (addr.ip().to_string(), addr.port()))
+            .collect();
+        if addrs.len() == 1 {
+            addrs.remove(0).into_py_any(py)
+        } else {
+            addrs.into_py_any(py)
+        }
+    }
+
+    /// Add a WireGuard peer. An existing peer with the same public key is replaced.
+    ///
+    /// `peer` is a dict with the same keys as the `peers` argument of `start_wireguard_server`.
+    pub fn add_peer(&self, peer: Bound<PyDict>) -> PyResult<()> {
+        let conf = peer_conf_from_dict(&peer)?;
+        self.peers_tx
+            .send(WireGuardCommand::AddPeer(conf))
+            .map_err(event_queue_unavailable)
+    }
+
+    /// Remove the WireGuard peer with the given base64-encoded public key.
+    pub fn remove_peer(&self, public_key: String) -> PyResult<()> {
+        let public_key = string_to_key(public_key)?;
+        self.peers_tx
+            .send(WireGuardCommand::RemovePeer(public_key))
+            .map_err(event_queue_unavailable)
+    }
+
+    /// Get the current state of all WireGuard peers.
+    ///
+    /// Each peer is described by a dict with its `public_key`, `name`, `allowed_ips`, `endpoint`,
+    /// the `endpoint_history` of `((host, port), timestamp)` tuples the peer has roamed to,
+    /// the time of the `last_handshake` (as a UNIX timestamp, or `None`), and the number of
+    /// `rx_bytes` and `tx_bytes` transferred.
+    pub fn peers<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyAny>> {
+        let (tx, rx) = oneshot::channel();
+        self.peers_tx
+            .send(WireGuardCommand::ListPeers(tx))
+            .map_err(event_queue_unavailable)?;
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            let peers = rx
+                .await
+                .map_err(|_| PyOSError::new_err("Server has been shut down."))?;
+            Python::with_gil(|py| {
+                peers
+                    .iter()
+                    .map(|peer| peer_info_to_py(py, peer))
+                    .collect::<PyResult<Vec<_>>>()
+            })
+        })
+    }
+
+    /// Get the number of outgoing packets that were dropped because they could not be routed
+    /// to a peer, e.g. because no peer's allowed IPs match or the peer's endpoint is unknown.
+    pub fn dropped_packets<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyAny>> {
+        let (tx, rx) = oneshot::channel();
+        self.peers_tx
+            .send(WireGuardCommand::DroppedPackets(tx))
+            .map_err(event_queue_unavailable)?;
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            rx.await
+                .map_err(|_| PyOSError::new_err("Server has been shut down."))
+        })
     }
 
     pub fn __repr__(&self) -> String {
-        format!("WireGuardServer({})", self.local_addr)
+        let addrs: Vec<String> = self.local_addrs.iter().map(|a| a.to_string()).collect();
+        format!("WireGuardServer({})", addrs.join(", "))
     }
 }
 
@@ -60,7 +142,14 @@ impl WireGuardServer {
 /// - `peer_public_keys`: List of public X25519 keys for WireGuard peers as base64-encoded strings.
 /// - `handle_tcp_stream`: An async function that will be called for each new TCP `Stream`.
 /// - `handle_udp_stream`: An async function that will be called for each new UDP `Stream`.
+/// - `peers`: Additional WireGuard peers with per-peer configuration, see `peer_conf_from_dict`.
+/// - `additional_listen_addrs`: Further `(host, port)` addresses to listen on, e.g. to serve
+///   IPv4 and IPv6 clients with the same server. All addresses share the same peers.
+/// - `tcp_fast_open`: Advertise TCP Fast Open support by handing out cookies to clients.
+///   If enabled, data sent along with a SYN is only accepted with a valid cookie.
 #[pyfunction]
+#[pyo3(signature = (host, port, private_key, peer_public_keys, handle_tcp_stream, handle_udp_stream, *, peers = None, additional_listen_addrs = None, tcp_fast_open = false))]
+#[allow(clippy::too_many_arguments)]
 pub fn start_wireguard_server(
     py: Python<'_>,
     host: IpAddr,
@@ -69,19 +158,165 @@ pub fn start_wireguard_server(
     peer_public_keys: Vec<String>,
     handle_tcp_stream: PyObject,
     handle_udp_stream: PyObject,
+    peers: Option<Vec<Bound<PyDict>>>,
+    additional_listen_addrs: Option<Vec<(IpAddr, u16)>>,
+    tcp_fast_open: bool,
 ) -> PyResult<Bound<PyAny>> {
     let private_key = string_to_key(private_key)?;
-    let peer_public_keys = peer_public_keys
+    let mut peer_confs = peer_public_keys
         .into_iter()
-        .map(string_to_key)
-        .collect::<PyResult<Vec<PublicKey>>>()?;
+        .map(|key| string_to_key::<PublicKey>(key).map(WireGuardPeerConf::new))
+        .collect::<PyResult<Vec<_>>>()?;
+    for peer in peers.unwrap_or_default() {
+        peer_confs.push(peer_conf_from_dict(&peer)?);
+    }
+    let mut listen_addrs = vec![SocketAddr::from((host, port))];
+    listen_addrs.extend(
+        additional_listen_addrs
+            .unwrap_or_default()
+            .into_iter()
+            .map(SocketAddr::from),
+    );
     let conf = WireGuardConf {
-        listen_addr: SocketAddr::from((host, port)),
+        listen_addrs,
         private_key,
-        peer_public_keys,
+        peers: peer_confs,
+        tcp_fast_open,
     };
+    start(py, conf, handle_tcp_stream, handle_udp_stream)
+}
+
+/// Start a WireGuard server from a `wg-quick` configuration file:
+///
+/// - `host`: The host address for the WireGuard UDP socket.
+/// - `config`: The contents of the configuration file. The server listens on the `ListenPort`
+///   of its `[Interface]`, or on `51820` by default. Peer endpoints must be IP addresses.
+/// - `handle_tcp_stream`: An async function that will be called for each new TCP `Stream`.
+/// - `handle_udp_stream`: An async function that will be called for each new UDP `Stream`.
+/// - `tcp_fast_open`: Advertise TCP Fast Open support, see `start_wireguard_server`.
+#[pyfunction]
+#[pyo3(signature = (host, config, handle_tcp_stream, handle_udp_stream, *, tcp_fast_open = false))]
+pub fn start_wireguard_server_from_config(
+    py: Python<'_>,
+    host: IpAddr,
+    config: String,
+    handle_tcp_stream: PyObject,
+    handle_udp_stream: PyObject,
+    tcp_fast_open: bool,
+) -> PyResult<Bound<PyAny>> {
+    let mut conf = WgQuickConfig::parse(&config)
+        .and_then(|c| c.to_wireguard_conf(host))
+        .map_err(|e| PyValueError::new_err(format!("{e:#}")))?;
+    conf.tcp_fast_open = tcp_fast_open;
+    start(py, conf, handle_tcp_stream, handle_udp_stream)
+}
+
+/// Render the `wg-quick` configuration for a client of the server with the given configuration.
+///
+/// - `server_config`: The server's `wg-quick` configuration file.
+/// - `client_private_key`: The client's private key as a base64-encoded string.
+///   Its public key must match one of the server's peers, which needs `AllowedIPs` to
+///   assign as the client's addresses.
+/// - `endpoint`: The server address as seen from the client, as `host:port`.
+#[pyfunction]
+pub fn client_config(
+    server_config: String,
+    client_private_key: String,
+    endpoint: String,
+) -> PyResult<String> {
+    let client_private_key: [u8; 32] = string_to_key(client_private_key)?;
+    WgQuickConfig::parse(&server_config)
+        .and_then(|c| c.client_config(client_private_key, &endpoint))
+        .map(|c| c.to_string())
+        .map_err(|e| PyValueError::new_err(format!("{e:#}")))
+}
+
+fn start(
+    py: Python<'_>,
+    conf: WireGuardConf,
+    handle_tcp_stream: PyObject,
+    handle_udp_stream: PyObject,
+) -> PyResult<Bound<PyAny>> {
     pyo3_async_runtimes::tokio::future_into_py(py, async move {
-        let (server, local_addr) = Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
-        Ok(WireGuardServer { server, local_addr })
+        let (server, (local_addrs, peers_tx)) =
+            Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
+        Ok(WireGuardServer {
+            server,
+            local_addrs,
+            peers_tx,
+        })
     })
 }
+
+/// Parse a WireGuard peer configuration from a Python dict with the following keys:
+///
+/// - `public_key`: The peer's public X25519 key as a base64-encoded string.
+/// - `name` (optional): A name for the peer, e.g. the device name. Connections can be attributed
+///   to peers with `Stream.get_extra_info("wireguard_peer")`. Defaults to a prefix of the public key.
+/// - `allowed_ips` (optional): List of IP ranges in CIDR notation that the peer may use as source
+///   addresses, and to which packets are routed. By default, the peer may use any address.
+/// - `preshared_key` (optional): A base64-encoded preshared key.
+/// - `persistent_keepalive` (optional): Keepalive interval in seconds, or `None` to disable.
+///   Defaults to 25 seconds.
+/// - `endpoint` (optional): The peer's initial `(host, port)` address.
+pub(crate) fn peer_conf_from_dict(peer: &Bound<PyDict>) -> PyResult<WireGuardPeerConf> {
+    let public_key: String = peer
+        .get_item("public_key")?
+        .ok_or_else(|| PyKeyError::new_err("public_key"))?
+        .extract()?;
+    let mut conf = WireGuardPeerConf::new(string_to_key(public_key)?);
+    if let Some(name) = peer.get_item("name")? {
+        conf.name = name.extract()?;
+    }
+    if let Some(allowed_ips) = peer.get_item("allowed_ips")? {
+        conf.allowed_ips = allowed_ips
+            .extract::<Vec<String>>()?
+            .iter()
+            .map(|s| parse_allowed_ip(s))
+            .collect::<anyhow::Result<_>>()
+            .map_err(|e| PyValueError::new_err(format!("{e}")))?;
+    }
+    if let Some(preshared_key) = peer.get_item("preshared_key")? {
+        conf.preshared_key = Some(string_to_key(preshared_key.extract()?)?);
+    }
+    if let Some(keepalive) = peer.get_item("persistent_keepalive")? {
+        conf.persistent_keepalive = keepalive.extract()?;
+    }
+    if let Some(endpoint) = peer.get_item("endpoint")? {
+        let (host, port): (IpAddr, u16) = endpoint.extract()?;
+        conf.endpoint = Some(SocketAddr::from((host, port)));
+    }
+    Ok(conf)
+}
+
+fn peer_info_to_py(py: Python, peer: &WireGuardPeerInfo) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("public_key", key_to_string(peer.public_key.as_bytes()))?;
+    dict.set_item("name", &peer.name)?;
+    dict.set_item(
+        "allowed_ips",
+        peer.allowed_ips
+            .iter()
+            .map(|cidr| cidr.to_string())
+            .collect::<Vec<_>>(),
+    )?;
+    dict.set_item(
+        "endpoint",
+        peer.endpoint
+            .map(|addr| socketaddr_to_py(py, addr))
+            .transpose()?,
+    )?;
+    dict.set_item(
+        "endpoint_history",
+        endpoint_history_to_py(py, &peer.endpoint_history)?,
+    )?;
+    dict.set_item(
+        "last_handshake",
+        peer.last_handshake
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64()),
+    )?;
+    dict.set_item("rx_bytes", peer.rx_bytes)?;
+    dict.set_item("tx_bytes", peer.tx_bytes)?;
+    dict.into_py_any(py)
+}
@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+
+use mitmproxy::wireguard_config::WgQuickConfig;
+
+use crate::server::base::Server;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// A running WireGuard client.
+///
+/// A new client can be started by calling `start_wireguard_client`.
+/// The public API is intended to be similar to the API provided by
+/// [`asyncio.Server`](https://docs.python.org/3/library/asyncio-eventloop.html#asyncio.Server)
+/// from the Python standard library.
+#[pyclass(module = "mitmproxy_rs.wireguard")]
+#[derive(Debug)]
+pub struct WireGuardClient {
+    /// local address of the WireGuard UDP socket
+    local_addr: SocketAddr,
+    /// the peer's address as specified in the configuration
+    endpoint: String,
+    server: Server,
+}
+
+#[pymethods]
+impl WireGuardClient {
+    /// Request the WireGuard client to gracefully shut down.
+    ///
+    /// If `timeout` is given, existing TCP connections are drained and closed
+    /// for up to `timeout` seconds before the client exits.
+    #[pyo3(signature = (timeout=None))]
+    pub fn close(&mut self, timeout: Option<f64>) -> PyResult<()> {
+        self.server.close(timeout)
+    }
+
+    /// Wait until the WireGuard client has shut down.
+    ///
+    /// This coroutine will yield once pending data has been flushed and all tasks have
+    /// successfully terminated after calling the `close` method.
+    pub fn wait_closed<'p>(&self, py: Python<'p>) -> PyResult<Bound<'p, PyAny>> {
+        self.server.wait_closed(py)
+    }
+
+    /// Get the local address of the UDP socket that the WireGuard client uses.
+    pub fn getsockname(&self) -> (String, u16) {
+        (self.local_addr.ip().to_string(), self.local_addr.port())
+    }
+
+    pub fn __repr__(&self) -> String {
+        format!("WireGuardClient({} -> {})", self.local_addr, self.endpoint)
+    }
+}
+
+/// Start a WireGuard client that connects to an existing WireGuard network and intercepts
+/// all traffic that is routed to it through the tunnel.
+///
+/// - `config`: The client's `wg-quick` configuration file, which must have exactly one
+///   `[Peer]` with an `Endpoint`. The `Address` of the `[Interface]` is ignored:
+///   traffic to any destination that the peer routes to us is intercepted.
+/// - `handle_tcp_stream`: An async function that will be called for each new TCP `Stream`.
+/// - `handle_udp_stream`: An async function that will be called for each new UDP `Stream`.
+#[pyfunction]
+pub fn start_wireguard_client(
+    py: Python<'_>,
+    config: String,
+    handle_tcp_stream: PyObject,
+    handle_udp_stream: PyObject,
+) -> PyResult<Bound<PyAny>> {
+    let conf = WgQuickConfig::parse(&config)
+        .and_then(|c| c.to_wireguard_client_conf())
+        .map_err(|e| PyValueError::new_err(format!("{e:#}")))?;
+    let endpoint = conf.endpoint.clone();
+    pyo3_async_runtimes::tokio::future_into_py(py, async move {
+        let (server, local_addr) = Server::init(conf, handle_tcp_stream, handle_udp_stream).await?;
+        Ok(WireGuardClient {
+            local_addr,
+            endpoint,
+            server,
+        })
+    })
+}
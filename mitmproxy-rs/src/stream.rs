@@ -6,11 +6,12 @@ use pyo3::{exceptions::PyOSError, intern, prelude::*, IntoPyObjectExt};
 use tokio::sync::{
     mpsc::{self},
     oneshot::{self},
+    watch,
 };
 
-use mitmproxy::messages::{ConnectionId, TransportCommand, TunnelInfo};
+use mitmproxy::messages::{CloseReason, ConnectionId, TcpSynInfo, TransportCommand, TunnelInfo};
 
-use crate::util::{event_queue_unavailable, socketaddr_to_py};
+use crate::util::{event_queue_unavailable, socketaddr_to_py, tcp_syn_to_py, wireguard_peer_to_py};
 
 #[derive(Debug)]
 pub enum StreamState {
@@ -31,6 +32,9 @@ pub struct Stream {
     pub peername: SocketAddr,
     pub sockname: SocketAddr,
     pub tunnel_info: TunnelInfo,
+    pub tcp_syn: Option<TcpSynInfo>,
+    /// Receives the reason once the underlying connection has been closed.
+    pub closed: watch::Receiver<Option<CloseReason>>,
 }
 
 #[pymethods]
@@ -135,16 +139,35 @@ impl Stream {
         }
     }
 
-    /// Wait until the stream is closed (currently a no-op).
+    /// Wait until the underlying connection is closed.
+    ///
+    /// For TCP, this waits until both sides have sent a FIN and ours has been acknowledged,
+    /// or until the connection has been reset or timed out. It does not wait for TIME-WAIT to expire.
+    /// UDP streams that have been closed locally return immediately.
+    /// The reason is then available via `get_extra_info("close_reason")`.
     fn wait_closed<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        pyo3_async_runtimes::tokio::future_into_py(py, std::future::ready(Ok(())))
+        let mut closed = self.closed.clone();
+        let closed_locally = matches!(self.state, StreamState::Closed);
+        let is_udp = !self.connection_id.is_tcp();
+        pyo3_async_runtimes::tokio::future_into_py(py, async move {
+            if !(is_udp && closed_locally) {
+                // An error means that the server has been shut down, so we're done as well.
+                closed.wait_for(Option::is_some).await.ok();
+            }
+            Ok(())
+        })
     }
 
     /// Query the stream for details of the underlying network connection.
     ///
     /// Supported values:
     ///   - Always available: `transport_protocol`, `peername`, `sockname`
-    ///   - WireGuard mode: `original_dst`, `original_src`
+    ///   - TCP connections intercepted at the packet level: `tcp_syn`
+    ///     (TTL, window size, and options of the client's SYN packet)
+    ///   - Once the connection is closed: `close_reason`
+    ///     (`"fin"`, `"reset"`, `"timeout"`, `"keepalive_expired"`, or `"shutdown"`)
+    ///   - WireGuard mode: `original_dst`, `original_src`, `wireguard_peer`
+    ///     (public key, name, and endpoint history of the peer)
     ///   - Local redirector mode: `pid`, `process_name`, `remote_endpoint`
     #[pyo3(signature = (name, default=None))]
     fn get_extra_info(
@@ -164,12 +187,27 @@ impl Stream {
             }
             "peername" => return socketaddr_to_py(py, self.peername),
             "sockname" => return socketaddr_to_py(py, self.sockname),
+            "tcp_syn" => {
+                if let Some(info) = &self.tcp_syn {
+                    return tcp_syn_to_py(py, info);
+                }
+            }
+            "close_reason" => {
+                if let Some(reason) = *self.closed.borrow() {
+                    return reason.as_str().into_py_any(py);
+                }
+            }
             _ => (),
         }
         match &self.tunnel_info {
-            TunnelInfo::WireGuard { src_addr, dst_addr } => match name.as_str() {
+            TunnelInfo::WireGuard {
+                src_addr,
+                dst_addr,
+                peer,
+            } => match name.as_str() {
                 "original_src" => return socketaddr_to_py(py, *src_addr),
                 "original_dst" => return socketaddr_to_py(py, *dst_addr),
+                "wireguard_peer" => return wireguard_peer_to_py(py, peer),
                 _ => (),
             },
             TunnelInfo::LocalRedirector {
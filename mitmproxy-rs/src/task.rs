@@ -5,11 +5,11 @@ use anyhow::{Context, Result};
 use pyo3::exceptions::asyncio::CancelledError;
 use pyo3::prelude::*;
 use pyo3_async_runtimes::TaskLocals;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 
 use crate::stream::Stream;
 use crate::stream::StreamState;
-use mitmproxy::messages::{TransportCommand, TransportEvent};
+use mitmproxy::messages::{CloseReason, TransportCommand, TransportEvent};
 use mitmproxy::shutdown;
 
 pub struct PyInteropTask {
@@ -49,6 +49,7 @@ impl PyInteropTask {
 
     pub async fn run(mut self) -> Result<()> {
         let active_streams = Arc::new(Mutex::new(HashMap::new()));
+        let mut close_notifiers = HashMap::new();
 
         loop {
             tokio::select! {
@@ -66,8 +67,13 @@ impl PyInteropTask {
                             src_addr,
                             dst_addr,
                             tunnel_info,
+                            tcp_syn,
                             command_tx,
                         } => {
+                            // All connections report why they were closed with a ConnectionClosed event,
+                            // including those with a dedicated command channel.
+                            let (closed_tx, closed_rx) = watch::channel(None);
+                            close_notifiers.insert(connection_id, closed_tx);
                             let command_tx = command_tx.unwrap_or_else(|| self.transport_commands.clone());
                             // initialize new stream
                             let stream = Stream {
@@ -77,6 +83,8 @@ impl PyInteropTask {
                                 peername: src_addr,
                                 sockname: dst_addr,
                                 tunnel_info,
+                                tcp_syn,
+                                closed: closed_rx,
                             };
 
                             let mut conns = active_streams.lock().await;
@@ -115,6 +123,14 @@ impl PyInteropTask {
                                 log::error!("Failed to spawn connection handler:\n{err}");
                             };
                         },
+                        TransportEvent::ConnectionClosed {
+                            connection_id,
+                            reason,
+                        } => {
+                            if let Some(closed_tx) = close_notifiers.remove(&connection_id) {
+                                closed_tx.send(Some(reason)).ok();
+                            }
+                        },
                     }
                 }
             };
@@ -122,6 +138,10 @@ impl PyInteropTask {
 
         log::debug!("Python interoperability task shutting down.");
 
+        for (_, closed_tx) in close_notifiers.drain() {
+            closed_tx.send(Some(CloseReason::Shutdown)).ok();
+        }
+
         while let Some((_, handle)) = active_streams.lock().await.drain().next() {
             if handle.is_finished() {
                 // Future is already finished: just await;
@@ -5,10 +5,10 @@ use anyhow::Result;
 use pyo3::prelude::*;
 use tokio::net::{lookup_host, UdpSocket};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 
 use crate::stream::{Stream, StreamState};
-use mitmproxy::messages::{ConnectionId, TransportCommand, TunnelInfo};
+use mitmproxy::messages::{CloseReason, ConnectionId, TransportCommand, TunnelInfo};
 use mitmproxy::MAX_PACKET_SIZE;
 
 use mitmproxy::packet_sources::udp::remote_host_closed_conn;
@@ -33,15 +33,21 @@ pub fn open_udp_connection(
         let sockname = socket.local_addr()?;
 
         let (command_tx, command_rx) = unbounded_channel();
+        let (closed_tx, closed_rx) = watch::channel(None);
 
-        tokio::spawn(async {
+        tokio::spawn(async move {
             let task = UdpClientTask {
                 socket,
                 transport_commands_rx: command_rx,
             };
-            if let Err(e) = task.run().await {
-                log::error!("UDP client errored: {e}");
-            }
+            let reason = match task.run().await {
+                Ok(()) => CloseReason::Fin,
+                Err(e) => {
+                    log::error!("UDP client errored: {e}");
+                    CloseReason::Reset
+                }
+            };
+            closed_tx.send(Some(reason)).ok();
         });
 
         let stream = Stream {
@@ -51,6 +57,8 @@ pub fn open_udp_connection(
             peername,
             sockname,
             tunnel_info: TunnelInfo::None,
+            tcp_syn: None,
+            closed: closed_rx,
         };
 
         Ok(stream)
@@ -5,12 +5,15 @@ use data_encoding::BASE64;
 use mitmproxy::certificates;
 
 use pyo3::exceptions::PyOSError;
+use pyo3::types::PyDict;
 use pyo3::{exceptions::PyValueError, prelude::*, IntoPyObjectExt};
 use rand_core::OsRng;
 
 use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use boringtun::x25519::{PublicKey, StaticSecret};
+use mitmproxy::messages::{TcpSynInfo, WireGuardPeerTunnelInfo};
 use tokio::sync::mpsc;
 
 pub fn string_to_key<T>(data: String) -> PyResult<T>
@@ -25,10 +28,52 @@ where
         .ok_or_else(|| PyValueError::new_err("Invalid key."))
 }
 
+pub fn key_to_string(key: &[u8; 32]) -> String {
+    BASE64.encode(key)
+}
+
 pub fn socketaddr_to_py(py: Python, s: SocketAddr) -> PyResult<PyObject> {
     (s.ip().to_string(), s.port()).into_py_any(py)
 }
 
+pub fn tcp_syn_to_py(py: Python, info: &TcpSynInfo) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("ttl", info.ttl)?;
+    dict.set_item("window", info.window)?;
+    dict.set_item("options", info.options.clone())?;
+    dict.set_item("mss", info.mss)?;
+    dict.set_item("window_scale", info.window_scale)?;
+    dict.set_item("sack_permitted", info.sack_permitted)?;
+    dict.set_item("timestamp", info.timestamp)?;
+    dict.into_py_any(py)
+}
+
+pub fn wireguard_peer_to_py(py: Python, peer: &WireGuardPeerTunnelInfo) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("public_key", &peer.public_key)?;
+    dict.set_item("name", &peer.name)?;
+    dict.set_item("endpoints", endpoint_history_to_py(py, &peer.endpoints)?)?;
+    dict.into_py_any(py)
+}
+
+/// Convert an endpoint history to a list of `((host, port), timestamp)` tuples.
+pub fn endpoint_history_to_py(
+    py: Python,
+    endpoints: &[(SocketAddr, SystemTime)],
+) -> PyResult<Vec<(PyObject, Option<f64>)>> {
+    endpoints
+        .iter()
+        .map(|(addr, time)| {
+            Ok((
+                socketaddr_to_py(py, *addr)?,
+                time.duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_secs_f64()),
+            ))
+        })
+        .collect()
+}
+
 pub fn event_queue_unavailable<T>(_: mpsc::error::SendError<T>) -> PyErr {
     PyOSError::new_err("Server has been shut down.")
 }
@@ -8,12 +8,12 @@ use anyhow::{anyhow, Context, Result};
 use internet_packet::{ConnectionId, InternetPacket, TransportProtocol};
 use log::{debug, error, info, warn};
 use lru_time_cache::LruCache;
-use mitmproxy::intercept_conf::{InterceptConf, ProcessInfo};
+use mitmproxy::intercept_conf::{Destination, InterceptConf, ProcessInfo, Protocol, PID};
 use mitmproxy::ipc;
 use mitmproxy::ipc::FromProxy;
 use mitmproxy::packet_sources::IPC_BUF_SIZE;
 use mitmproxy::windows::network::network_table;
-use mitmproxy::processes::get_process_name;
+use mitmproxy::processes::{parent_pids, process_info};
 use mitmproxy::MAX_PACKET_SIZE;
 use prost::Message;
 use std::io::Cursor;
@@ -203,7 +203,9 @@ async fn main() -> Result<()> {
                                         "Inbound packet for known application: {:?} ({})",
                                         &proc_info.process_name, &proc_info.pid
                                     );
-                                    if state.should_intercept(proc_info) {
+                                    let destination =
+                                        destination(&packet.connection_id().reverse());
+                                    if state.should_intercept(proc_info, Some(&destination)) {
                                         ConnectionAction::Intercept(proc_info.clone())
                                     } else {
                                         ConnectionAction::None
@@ -268,17 +270,14 @@ async fn main() -> Result<()> {
                             continue;
                         }
 
-                        let proc_info = {
-                            let pid = address.process_id();
-                            ProcessInfo {
-                                pid,
-                                process_name: get_process_name(pid)
-                                    .map(|x| x.to_string_lossy().into_owned())
-                                    .ok(),
-                            }
-                        };
+                        let proc_info = process_info(
+                            address.process_id(),
+                            parent_pids_if_needed(&state).as_ref(),
+                        );
 
-                        let action = if state.should_intercept(&proc_info) {
+                        let action = if state
+                            .should_intercept(&proc_info, Some(&destination(&connection_id)))
+                        {
                             ConnectionAction::Intercept(proc_info)
                         } else {
                             ConnectionAction::None
@@ -295,16 +294,15 @@ async fn main() -> Result<()> {
                         .await?;
                     }
                     WinDivertEvent::SocketListen => {
-                        let pid = address.process_id();
-                        let process_name = get_process_name(pid)
-                            .map(|x| x.to_string_lossy().into_owned())
-                            .ok();
-                        debug!("Registering {:?} on {}.", process_name, connection_id.src);
-                        active_listeners.insert(
-                            connection_id.src,
-                            proto,
-                            ProcessInfo { pid, process_name },
+                        let proc_info = process_info(
+                            address.process_id(),
+                            parent_pids_if_needed(&state).as_ref(),
+                        );
+                        debug!(
+                            "Registering {:?} on {}.",
+                            proc_info.process_name, connection_id.src
                         );
+                        active_listeners.insert(connection_id.src, proto, proc_info);
                     }
                     WinDivertEvent::SocketClose => {
                         // We cannot clean up here because there are still final packets on connections after this event,
@@ -360,13 +358,9 @@ async fn main() -> Result<()> {
                 // Handle preexisting connections.
                 connections.clear();
                 active_listeners.clear();
+                let parents = parent_pids_if_needed(&state);
                 for e in network_table()? {
-                    let proc_info = ProcessInfo {
-                        pid: e.pid,
-                        process_name: get_process_name(e.pid)
-                            .map(|x| x.to_string_lossy().into_owned())
-                            .ok(),
-                    };
+                    let proc_info = process_info(e.pid, parents.as_ref());
                     let proto = TransportProtocol::try_from(e.protocol)?;
                     if e.remote_addr.ip().is_unspecified() {
                         active_listeners.insert(e.local_addr, proto, proc_info);
@@ -376,7 +370,9 @@ async fn main() -> Result<()> {
                             src: e.local_addr,
                             dst: e.remote_addr,
                         };
-                        let action = if state.should_intercept(&proc_info) {
+                        let action = if state
+                            .should_intercept(&proc_info, Some(&destination(&connection_id)))
+                        {
                             ConnectionAction::Intercept(proc_info)
                         } else {
                             ConnectionAction::None
@@ -478,6 +474,27 @@ fn relay_network_events(handle: WinDivert<NetworkLayer>, tx: UnboundedSender<Eve
     }
 }
 
+/// Taking a process snapshot is expensive, so we only do it if the intercept spec needs it.
+fn parent_pids_if_needed(conf: &InterceptConf) -> Option<HashMap<PID, PID>> {
+    if !conf.uses_process_trees() {
+        return None;
+    }
+    parent_pids()
+        .map_err(|e| warn!("Failed to list parent processes: {e}"))
+        .ok()
+}
+
+/// The remote end of a connection, with the local end in `src`.
+fn destination(connection_id: &ConnectionId) -> Destination {
+    Destination {
+        addr: connection_id.dst,
+        protocol: match connection_id.proto {
+            TransportProtocol::Tcp => Protocol::Tcp,
+            TransportProtocol::Udp => Protocol::Udp,
+        },
+    }
+}
+
 async fn insert_into_connections(
     connection_id: ConnectionId,
     action: &ConnectionAction,
@@ -531,7 +548,9 @@ async fn process_packet(
                 })
                 .context("failed to re-inject packet")?;
         }
-        ConnectionAction::Intercept(ProcessInfo { pid, process_name }) => {
+        ConnectionAction::Intercept(ProcessInfo {
+            pid, process_name, ..
+        }) => {
             info!(
                 "Intercepting: {} {} outbound={} loopback={}",
                 packet.connection_id(),
@@ -0,0 +1,613 @@
+//! Representation of intercept specs for the Linux eBPF redirector.
+//!
+//! The eBPF program cannot loop over an arbitrary number of actions, so we compile specs into
+//! hash maps that contain the last matching action for each PID, process name, user, group,
+//! cgroup and network namespace. Only process name patterns with wildcards and actions with
+//! destination patterns are evaluated one by one. Alternatives (`|`) are expanded into separate
+//! entries that share the index of their action.
+//! Specs that cannot be represented are rejected instead of silently being changed.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, ensure, Context, Result};
+use mitmproxy_linux_ebpf_common::{
+    is_glob, name_from_bytes, FlowRule, GlobRule, IdKind, Name, Pid, ProcessMatch, Rule,
+    RuleAction, RuleLookup, CGROUP_LEVELS, FLOW_RULES_LEN, GLOB_RULES_LEN, ID_RULES_LEN, NAME_LEN,
+    NAME_RULES_LEN, PID_RULES_LEN,
+};
+
+use super::{Action, Expr, InterceptConf, Pattern, Protocol};
+
+/// Where the cgroup2 hierarchy is mounted.
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Named network namespaces, as created by `ip netns add`.
+const NETNS_DIR: &str = "/run/netns";
+/// `SO_NETNS_COOKIE` from asm-generic/socket.h, which is not exposed by the libc crate yet.
+const SO_NETNS_COOKIE: libc::c_int = 71;
+
+/// Translates cgroup paths and network namespaces in intercept specs into the ids the eBPF
+/// program sees.
+pub trait Resolver {
+    fn cgroup_id(&self, cgroup: &str) -> Result<u64>;
+    fn netns_cookie(&self, netns: &str) -> Result<u64>;
+}
+
+/// Resolves cgroups and network namespaces on the local system.
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn cgroup_id(&self, cgroup: &str) -> Result<u64> {
+        if let Ok(id) = cgroup.parse() {
+            return Ok(id);
+        }
+        let relative = cgroup.trim_start_matches('/');
+        let depth = relative.split('/').filter(|c| !c.is_empty()).count();
+        ensure!(
+            depth < CGROUP_LEVELS,
+            "cgroup is nested more than {} levels deep: {cgroup}",
+            CGROUP_LEVELS - 1
+        );
+        let path = Path::new(CGROUP_ROOT).join(relative);
+        // On cgroup2, the id of a cgroup is the inode number of its directory.
+        let metadata = fs::metadata(&path)
+            .with_context(|| format!("failed to find cgroup {}", path.display()))?;
+        Ok(metadata.ino())
+    }
+
+    fn netns_cookie(&self, netns: &str) -> Result<u64> {
+        let path = if netns.contains('/') {
+            PathBuf::from(netns)
+        } else {
+            Path::new(NETNS_DIR).join(netns)
+        };
+        let file = File::open(&path)
+            .with_context(|| format!("failed to open network namespace {}", path.display()))?;
+        // setns() only affects the calling thread, so we use a short-lived one.
+        std::thread::scope(|s| s.spawn(|| socket_netns_cookie(&file)).join())
+            .expect("netns lookup thread panicked")
+            .with_context(|| format!("failed to look up network namespace {}", path.display()))
+    }
+}
+
+fn socket_netns_cookie(netns: &File) -> io::Result<u64> {
+    if unsafe { libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd is a freshly opened socket that we own.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut cookie = 0u64;
+    let mut len = size_of::<u64>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_NETNS_COOKIE,
+            &mut cookie as *mut u64 as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cookie)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EbpfConf {
+    pub default: bool,
+    pub pid_rules: HashMap<Pid, Rule>,
+    pub id_rules: HashMap<(IdKind, u64), Rule>,
+    pub name_rules: HashMap<Name, Rule>,
+    /// Ordered by index.
+    pub glob_rules: Vec<GlobRule>,
+    /// Actions with destination patterns, ordered by index.
+    pub flow_rules: Vec<FlowRule>,
+}
+
+impl EbpfConf {
+    pub fn new(conf: &InterceptConf, resolver: &impl Resolver) -> Result<Self> {
+        let mut pid_rules = HashMap::new();
+        let mut id_rules = HashMap::new();
+        let mut name_rules = HashMap::new();
+        let mut glob_rules = Vec::new();
+        let mut flow_rules = Vec::new();
+        for (index, action) in conf.actions.iter().enumerate() {
+            let (expr, action) = match action {
+                Action::Include(expr) => (expr, RuleAction::Include),
+                Action::Exclude(expr) => (expr, RuleAction::Exclude),
+            };
+            let rule = Rule {
+                index: index as u32,
+                action,
+            };
+            // All conjunctions share the action's index, so it does not matter which one matches.
+            for patterns in conjunctions(expr)? {
+                let [pattern] = patterns[..] else {
+                    flow_rules.push(flow_rule(rule, &patterns, resolver)?);
+                    continue;
+                };
+                // Later actions overwrite earlier ones, which is what we want.
+                match pattern {
+                    Pattern::Pid(pid) => {
+                        pid_rules.insert(*pid, rule);
+                    }
+                    Pattern::Tree(pid) => {
+                        id_rules.insert((IdKind::Tree, *pid as u64), rule);
+                    }
+                    Pattern::Process(name) => {
+                        let pattern = name_pattern(name)?;
+                        if is_glob(name.as_bytes()) {
+                            glob_rules.push(GlobRule {
+                                rule,
+                                len: name.len() as u32,
+                                pattern,
+                            });
+                        } else {
+                            name_rules.insert(pattern, rule);
+                        }
+                    }
+                    Pattern::Uid(uid) => {
+                        id_rules.insert((IdKind::Uid, *uid as u64), rule);
+                    }
+                    Pattern::Gid(gid) => {
+                        id_rules.insert((IdKind::Gid, *gid as u64), rule);
+                    }
+                    Pattern::Cgroup(cgroup) => {
+                        id_rules.insert((IdKind::Cgroup, resolver.cgroup_id(cgroup)?), rule);
+                    }
+                    Pattern::Netns(netns) => {
+                        id_rules.insert((IdKind::Netns, resolver.netns_cookie(netns)?), rule);
+                    }
+                    Pattern::Path(_) => bail!("path patterns are not supported on Linux"),
+                    Pattern::ProcessRegex(_) | Pattern::PathRegex(_) => {
+                        bail!("regular expressions are not supported on Linux")
+                    }
+                    Pattern::Dst(_) | Pattern::Port(_, _) | Pattern::Protocol(_) => {
+                        flow_rules.push(flow_rule(rule, &patterns, resolver)?);
+                    }
+                }
+            }
+        }
+        ensure!(
+            pid_rules.len() <= PID_RULES_LEN as usize,
+            "intercept spec contains more than {PID_RULES_LEN} PIDs"
+        );
+        ensure!(
+            id_rules.len() <= ID_RULES_LEN as usize,
+            "intercept spec contains more than {ID_RULES_LEN} users, groups, cgroups, network namespaces and process trees"
+        );
+        ensure!(
+            name_rules.len() <= NAME_RULES_LEN as usize,
+            "intercept spec contains more than {NAME_RULES_LEN} process names"
+        );
+        ensure!(
+            glob_rules.len() <= GLOB_RULES_LEN as usize,
+            "intercept spec contains more than {GLOB_RULES_LEN} process names with wildcards"
+        );
+        ensure!(
+            flow_rules.len() <= FLOW_RULES_LEN as usize,
+            "intercept spec contains more than {FLOW_RULES_LEN} actions with destinations or multiple patterns"
+        );
+        Ok(Self {
+            default: conf.default,
+            pid_rules,
+            id_rules,
+            name_rules,
+            glob_rules,
+            flow_rules,
+        })
+    }
+}
+
+fn name_pattern(name: &str) -> Result<Name> {
+    match name_from_bytes(name.as_bytes()) {
+        Some(pattern) => Ok(pattern),
+        None => bail!("process name is longer than {NAME_LEN} bytes: {name}"),
+    }
+}
+
+/// Expand an expression into alternative conjunctions of patterns, as the eBPF program has no `|`.
+fn conjunctions(expr: &Expr) -> Result<Vec<Vec<&Pattern>>> {
+    Ok(match expr {
+        Expr::Pattern(pattern) => vec![vec![pattern]],
+        Expr::Or(alternatives) => {
+            let mut expanded = Vec::new();
+            for alternative in alternatives {
+                expanded.extend(conjunctions(alternative)?);
+            }
+            expanded
+        }
+        Expr::And(terms) => {
+            let mut expanded = vec![vec![]];
+            for term in terms {
+                let alternatives = conjunctions(term)?;
+                // Each of these becomes a flow rule, so we can stop early.
+                ensure!(
+                    expanded.len() * alternatives.len() <= FLOW_RULES_LEN as usize,
+                    "{expr} expands to more than {FLOW_RULES_LEN} combinations of patterns"
+                );
+                expanded = expanded
+                    .iter()
+                    .flat_map(|c| alternatives.iter().map(move |a| [&c[..], &a[..]].concat()))
+                    .collect();
+            }
+            expanded
+        }
+    })
+}
+
+/// Compile an action that is evaluated on connect.
+/// The eBPF program can match at most one process pattern and one pattern of each destination kind.
+fn flow_rule(rule: Rule, patterns: &[&Pattern], resolver: &impl Resolver) -> Result<FlowRule> {
+    let mut flow_rule = FlowRule {
+        rule,
+        process: ProcessMatch::Any,
+        id_kind: IdKind::Uid,
+        id: 0,
+        name_len: 0,
+        name: [0; NAME_LEN],
+        addr: [0; 16],
+        prefix_len: 0,
+        protocol: 0,
+        port_min: 0,
+        port_max: u16::MAX,
+    };
+    let (mut dst, mut port, mut protocol) = (false, false, false);
+    for pattern in patterns {
+        let mut process = |process: ProcessMatch, id_kind: IdKind, id: u64| {
+            ensure!(
+                flow_rule.process == ProcessMatch::Any,
+                "cannot combine multiple process patterns on Linux"
+            );
+            flow_rule.process = process;
+            flow_rule.id_kind = id_kind;
+            flow_rule.id = id;
+            Ok(())
+        };
+        match pattern {
+            Pattern::Pid(pid) => process(ProcessMatch::Pid, IdKind::Uid, *pid as u64)?,
+            Pattern::Tree(pid) => process(ProcessMatch::Id, IdKind::Tree, *pid as u64)?,
+            Pattern::Uid(uid) => process(ProcessMatch::Id, IdKind::Uid, *uid as u64)?,
+            Pattern::Gid(gid) => process(ProcessMatch::Id, IdKind::Gid, *gid as u64)?,
+            Pattern::Cgroup(cgroup) => process(
+                ProcessMatch::Id,
+                IdKind::Cgroup,
+                resolver.cgroup_id(cgroup)?,
+            )?,
+            Pattern::Netns(netns) => process(
+                ProcessMatch::Id,
+                IdKind::Netns,
+                resolver.netns_cookie(netns)?,
+            )?,
+            Pattern::Path(_) => bail!("path patterns are not supported on Linux"),
+            Pattern::ProcessRegex(_) | Pattern::PathRegex(_) => {
+                bail!("regular expressions are not supported on Linux")
+            }
+            Pattern::Process(name) => {
+                process(ProcessMatch::Name, IdKind::Uid, 0)?;
+                flow_rule.name = name_pattern(name)?;
+                flow_rule.name_len = name.len() as u32;
+            }
+            Pattern::Dst(cidr) => {
+                ensure!(
+                    !dst,
+                    "cannot combine multiple destination addresses on Linux"
+                );
+                dst = true;
+                (flow_rule.addr, flow_rule.prefix_len) = cidr.mapped();
+            }
+            Pattern::Port(min, max) => {
+                ensure!(!port, "cannot combine multiple port patterns on Linux");
+                port = true;
+                (flow_rule.port_min, flow_rule.port_max) = (*min, *max);
+            }
+            Pattern::Protocol(p) => {
+                ensure!(
+                    !protocol,
+                    "cannot combine multiple protocol patterns on Linux"
+                );
+                protocol = true;
+                flow_rule.protocol = match p {
+                    Protocol::Tcp => libc::IPPROTO_TCP as u8,
+                    Protocol::Udp => libc::IPPROTO_UDP as u8,
+                };
+            }
+        }
+    }
+    Ok(flow_rule)
+}
+
+impl RuleLookup for EbpfConf {
+    fn default_intercept(&self) -> bool {
+        self.default
+    }
+
+    fn pid_rule(&self, pid: Pid) -> Option<Rule> {
+        self.pid_rules.get(&pid).copied()
+    }
+
+    fn id_rule(&self, kind: IdKind, id: u64) -> Option<Rule> {
+        self.id_rules.get(&(kind, id)).copied()
+    }
+
+    fn name_rule(&self, name: &Name) -> Option<Rule> {
+        self.name_rules.get(name).copied()
+    }
+
+    fn glob_rule(&self, i: u32) -> Option<&GlobRule> {
+        self.glob_rules.get(i as usize)
+    }
+
+    fn flow_rule(&self, i: u32) -> Option<&FlowRule> {
+        self.flow_rules.get(i as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intercept_conf::{mapped, Destination};
+    use mitmproxy_linux_ebpf_common::{should_intercept, Flow, Process, TREE_DEPTH};
+
+    /// Cgroups are `/a` (10) and `/a/b` (11), network namespaces are `foo` (20) and `bar` (21).
+    struct TestResolver;
+
+    impl Resolver for TestResolver {
+        fn cgroup_id(&self, cgroup: &str) -> Result<u64> {
+            match cgroup {
+                "/a" => Ok(10),
+                "/a/b" => Ok(11),
+                _ => cgroup.parse().context("unknown cgroup"),
+            }
+        }
+
+        fn netns_cookie(&self, netns: &str) -> Result<u64> {
+            match netns {
+                "foo" => Ok(20),
+                "bar" => Ok(21),
+                _ => bail!("unknown network namespace"),
+            }
+        }
+    }
+
+    fn process(pid: Pid, name: &str) -> Process {
+        Process {
+            pid,
+            uid: 1000,
+            gid: 1000,
+            netns: 20,
+            cgroups: [1, 10, 0, 0, 0, 0, 0, 0],
+            ancestors: [0; TREE_DEPTH],
+            name: name_from_bytes(name.as_bytes()).unwrap(),
+        }
+    }
+
+    fn ebpf_conf(spec: &str) -> EbpfConf {
+        let conf = InterceptConf::try_from(spec).unwrap();
+        EbpfConf::new(&conf, &TestResolver).unwrap()
+    }
+
+    fn flow(destination: &Destination) -> Flow {
+        Flow {
+            addr: mapped(destination.addr.ip()),
+            port: destination.addr.port(),
+            protocol: match destination.protocol {
+                Protocol::Tcp => libc::IPPROTO_TCP as u8,
+                Protocol::Udp => libc::IPPROTO_UDP as u8,
+            },
+        }
+    }
+
+    fn intercepts(spec: &str, pid: Pid, name: &str) -> bool {
+        should_intercept(&ebpf_conf(spec), &process(pid, name), None)
+    }
+
+    #[test]
+    fn test_ebpf_conf() {
+        assert!(intercepts("1,2,3", 2, "a"));
+        assert!(!intercepts("1,2,3", 4, "a"));
+        assert!(!intercepts("", 1, "a"));
+        assert!(intercepts("!1234", 1, "a"));
+        assert!(!intercepts("!1234", 1234, "a"));
+        assert!(intercepts("curl", 1, "curl"));
+        assert!(!intercepts("curl", 1, "wget"));
+
+        // last match wins, across pattern kinds.
+        assert!(!intercepts("curl,!42", 42, "curl"));
+        assert!(intercepts("!42,curl", 42, "curl"));
+        assert!(intercepts("curl,!curl,curl", 1, "curl"));
+        assert!(!intercepts("!curl,curl,!curl", 1, "curl"));
+    }
+
+    #[test]
+    fn test_corpus() {
+        for (spec, pid, name, destination, expected) in super::super::tests::corpus() {
+            let flow = destination.as_ref().map(flow);
+            assert_eq!(
+                should_intercept(&ebpf_conf(spec), &process(pid, name), flow.as_ref()),
+                expected,
+                "{spec} | {pid} | {name} | {destination:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_linux_patterns() {
+        assert!(intercepts("uid:1000", 1, "a"));
+        assert!(!intercepts("uid:1001", 1, "a"));
+        assert!(intercepts("gid:1000", 1, "a"));
+        assert!(!intercepts("!gid:1000", 1, "a"));
+        assert!(intercepts("netns:foo", 1, "a"));
+        assert!(!intercepts("netns:bar", 1, "a"));
+
+        // cgroups match their descendants.
+        assert!(intercepts("cgroup:/a", 1, "a"));
+        assert!(intercepts("cgroup:1", 1, "a"));
+        assert!(!intercepts("cgroup:/a/b", 1, "a"));
+        let mut child = process(1, "a");
+        child.cgroups[2] = 11;
+        assert!(should_intercept(&ebpf_conf("cgroup:/a/b"), &child, None));
+        assert!(!should_intercept(
+            &ebpf_conf("cgroup:/a,!cgroup:/a/b"),
+            &child,
+            None
+        ));
+        assert!(should_intercept(
+            &ebpf_conf("!cgroup:/a/b,cgroup:/a"),
+            &child,
+            None
+        ));
+
+        assert!(intercepts("cgroup:/a,!uid:1000,curl", 1, "curl"));
+        assert!(!intercepts("cgroup:/a,curl,!uid:1000", 1, "curl"));
+
+        let conf = InterceptConf::try_from("netns:unknown").unwrap();
+        assert!(EbpfConf::new(&conf, &TestResolver).is_err());
+    }
+
+    #[test]
+    fn test_process_trees() {
+        let mut child = process(12, "a");
+        child.ancestors[..3].copy_from_slice(&[11, 10, 1]);
+        assert!(should_intercept(&ebpf_conf("tree:10"), &child, None));
+        assert!(should_intercept(&ebpf_conf("tree:12"), &child, None));
+        assert!(!should_intercept(&ebpf_conf("tree:13"), &child, None));
+        assert!(!should_intercept(
+            &ebpf_conf("tree:10,!tree:11"),
+            &child,
+            None
+        ));
+        assert!(should_intercept(
+            &ebpf_conf("!tree:11,tree:10"),
+            &child,
+            None
+        ));
+        assert!(!should_intercept(&ebpf_conf("tree:1,!a"), &child, None));
+        assert!(!intercepts("tree:10", 1, "a"));
+
+        // Only TREE_DEPTH ancestors are checked.
+        let mut deep = process(100, "a");
+        for (i, ancestor) in deep.ancestors.iter_mut().enumerate() {
+            *ancestor = 99 - i as Pid;
+        }
+        assert!(should_intercept(&ebpf_conf("tree:84"), &deep, None));
+        assert!(!should_intercept(&ebpf_conf("tree:83"), &deep, None));
+    }
+
+    #[test]
+    fn test_flow_rules() {
+        let https = flow(&Destination {
+            addr: "1.2.3.4:443".parse().unwrap(),
+            protocol: Protocol::Tcp,
+        });
+        let intercepts =
+            |spec, process: &Process| should_intercept(&ebpf_conf(spec), process, Some(&https));
+        let mut child = process(12, "a");
+        child.ancestors[..2].copy_from_slice(&[11, 10]);
+        child.cgroups[2] = 11;
+        assert!(intercepts("tree:10&port:443", &child));
+        assert!(!intercepts("tree:13&port:443", &child));
+        assert!(intercepts("uid:1000&port:443", &child));
+        assert!(!intercepts("gid:0&port:443", &child));
+        assert!(intercepts("cgroup:/a/b&port:443", &child));
+        assert!(intercepts("netns:foo&port:443", &child));
+        assert!(!intercepts("netns:bar&port:443", &child));
+        assert!(intercepts("12&proto:tcp", &child));
+        assert!(!intercepts("13&proto:tcp", &child));
+
+        // Actions with a single process pattern don't need a flow rule.
+        assert!(ebpf_conf("curl,!42").flow_rules.is_empty());
+        assert_eq!(ebpf_conf("curl&port:443,port:80").flow_rules.len(), 2);
+
+        for unsupported in [
+            "curl&uid:1000",
+            "curl&uid:1000&port:443",
+            "port:80&port:443",
+            "dst:10.0.0.0/8&dst:10.1.0.0/16",
+            "proto:tcp&proto:udp",
+        ] {
+            let conf = InterceptConf::try_from(unsupported).unwrap();
+            assert!(
+                EbpfConf::new(&conf, &TestResolver).is_err(),
+                "{unsupported}"
+            );
+        }
+        let ports = (0..=FLOW_RULES_LEN)
+            .map(|p| format!("port:{p}"))
+            .collect::<Vec<_>>();
+        let conf = InterceptConf::try_from(ports).unwrap();
+        assert!(EbpfConf::new(&conf, &TestResolver).is_err());
+    }
+
+    #[test]
+    fn test_alternatives() {
+        let conf = ebpf_conf("curl|wget|42|c*");
+        assert_eq!(conf.name_rules.len(), 2);
+        assert_eq!(conf.pid_rules.len(), 1);
+        assert_eq!(conf.glob_rules.len(), 1);
+        assert!(conf.flow_rules.is_empty());
+        assert_eq!(
+            ebpf_conf("(curl|wget)&(port:80|port:443)").flow_rules.len(),
+            4
+        );
+
+        for unsupported in [
+            "(1|2|3)&(port:1|port:2|port:3)",
+            "path:/usr/bin/curl",
+            "name:~curl",
+            "path:~curl&port:443",
+        ] {
+            let conf = InterceptConf::try_from(unsupported).unwrap();
+            assert!(
+                EbpfConf::new(&conf, &TestResolver).is_err(),
+                "{unsupported}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_system_resolver() {
+        let root = fs::metadata(CGROUP_ROOT).map(|m| m.ino()).ok();
+        assert_eq!(SystemResolver.cgroup_id("/").ok(), root);
+        assert_eq!(SystemResolver.cgroup_id("1234").unwrap(), 1234);
+        assert!(SystemResolver.cgroup_id("/does/not/exist").is_err());
+        assert!(SystemResolver.cgroup_id("/a/b/c/d/e/f/g/h").is_err());
+        assert!(SystemResolver.netns_cookie("does-not-exist").is_err());
+    }
+
+    #[test]
+    fn test_large_specs() {
+        let pids = (0..1000).map(|p| p.to_string()).collect::<Vec<_>>();
+        let conf = InterceptConf::try_from(pids).unwrap();
+        let ebpf = EbpfConf::new(&conf, &TestResolver).unwrap();
+        assert!(should_intercept(&ebpf, &process(999, ""), None));
+
+        let long = "a".repeat(NAME_LEN);
+        assert!(intercepts(&long, 1, &long));
+
+        let too_long = "a".repeat(NAME_LEN + 1);
+        let conf = InterceptConf::try_from(too_long.as_str()).unwrap();
+        assert!(EbpfConf::new(&conf, &TestResolver).is_err());
+
+        let pids = (0..=PID_RULES_LEN)
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>();
+        let conf = InterceptConf::try_from(pids).unwrap();
+        assert!(EbpfConf::new(&conf, &TestResolver).is_err());
+
+        let globs = (0..=GLOB_RULES_LEN)
+            .map(|i| format!("{i}*"))
+            .collect::<Vec<_>>();
+        let conf = InterceptConf::try_from(globs).unwrap();
+        assert!(EbpfConf::new(&conf, &TestResolver).is_err());
+    }
+}
@@ -0,0 +1,947 @@
+//! Intercept specs decide which processes are intercepted in local mode.
+//!
+//! A spec is a comma-separated list of actions that are applied in order. Each action includes
+//! (or with a leading `!`, excludes) processes that match its pattern:
+//!
+//!  - A number, or `pid:1234`, matches the process with that PID.
+//!  - `tree:1234` matches the process with PID 1234 and all processes it spawns, including
+//!    their children. This is useful for applications that use helper processes, such as browsers.
+//!  - Everything else matches the file name of the process' executable, e.g. `curl` or
+//!    `firefox.exe`. Patterns must match the entire name, `*` matches any sequence of characters,
+//!    and `?` matches a single byte. For example, `firefox*` matches all names that start with
+//!    `firefox`, and `*fox*` matches all names that contain `fox`. Names can also be written as
+//!    `name:firefox*`, or in double quotes, e.g. `"1234"` for a process that is named `1234`.
+//!  - `path:/usr/bin/*` matches the entire path of the process' executable, which tells apart
+//!    executables that have the same name, e.g. `path:/usr/bin/node` and `path:/opt/*/node`.
+//!  - `name:~<regex>` and `path:~<regex>` match names or paths with a regular expression, e.g.
+//!    `name:~^python3\.\d+$`. Regular expressions are not anchored, and ones that contain
+//!    special characters such as `|` need to be quoted: `name:~"^(curl|wget)$"`.
+//!
+//! Matching is case-sensitive and, except for regular expressions, identical on all platforms.
+//!
+//! Actions can also be restricted to connections to certain destinations:
+//!
+//!  - `dst:10.0.0.0/8` or `dst:2001:db8::1` matches connections to an address or network.
+//!  - `port:443` or `port:8000-8999` matches connections to a port or port range.
+//!  - `proto:tcp` or `proto:udp` matches connections of that protocol.
+//!
+//! Patterns are combined with `&` (and) and `|` (or), where `&` binds more tightly, and can be
+//! grouped with parentheses. For example, `firefox*&port:443` intercepts HTTPS connections of
+//! Firefox, `(curl|wget)&(port:80|port:443)` intercepts web traffic of curl and wget, and
+//! `*,!dst:10.0.0.0/8` intercepts everything except connections to private addresses. Destination
+//! patterns never match if the destination is not known when the decision is made. On macOS, UDP
+//! flows are intercepted before their first datagram, so `dst:` and `port:` never match them, and
+//! `dst:` does not match TCP connections that applications open by hostname.
+//!
+//! Values that contain `,`, `&`, `|`, `"` or unbalanced parentheses must be quoted, with `\"` and
+//! `\\` as escape sequences. See `parser.rs` for the exact grammar. Invalid specs are
+//! reported as [SpecError]s that point to the invalid part of the spec.
+//!
+//! On Linux, processes can additionally be matched by:
+//!
+//!  - `uid:1001` and `gid:1001`: the (real) user or group id.
+//!  - `cgroup:/system.slice/docker-<id>.scope`: a cgroup (relative to the cgroup2 mount) or any of its
+//!    descendants, for example everything running in a container. Numeric cgroup ids are accepted too.
+//!  - `netns:name`: the network namespace with that name in `/run/netns`, or a namespace file
+//!    such as `/proc/1234/ns/net`.
+//!
+//! Path patterns and regular expressions are not supported on Linux, where the eBPF program only
+//! sees the file name of executables.
+
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use anyhow::ensure;
+use mitmproxy_linux_ebpf_common::{glob_match_steps, glob_steps, prefix_match};
+
+use parser::write_value;
+pub use parser::SpecError;
+
+#[cfg(target_os = "linux")]
+pub mod ebpf;
+mod parser;
+
+pub type PID = u32;
+
+#[derive(Debug, Clone, Default)]
+pub struct ProcessInfo {
+    pub pid: PID,
+    /// The file name or path of the process' executable.
+    pub process_name: Option<String>,
+    /// The full path of the process' executable, see [crate::processes::process_info].
+    /// Not populated in Linux local mode, where path patterns are not supported.
+    pub executable: Option<PathBuf>,
+    /// PIDs of the process' parent, grandparent, and so on, as far as they are known.
+    /// Only needed if [InterceptConf::uses_process_trees] is true.
+    pub ancestors: Vec<PID>,
+}
+
+impl ProcessInfo {
+    /// The file name of the process' executable.
+    fn name(&self) -> Option<Cow<'_, str>> {
+        match (&self.process_name, &self.executable) {
+            (Some(name), _) => Some(Cow::Borrowed(file_name(name))),
+            (None, Some(executable)) => Some(executable.file_name()?.to_string_lossy()),
+            (None, None) => None,
+        }
+    }
+
+    fn path(&self) -> Option<Cow<'_, str>> {
+        self.executable.as_ref().map(|e| e.to_string_lossy())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// The remote end of a connection.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Destination {
+    pub addr: SocketAddr,
+    pub protocol: Protocol,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct InterceptConf {
+    default: bool,
+    actions: Vec<Action>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Action {
+    Include(Expr),
+    Exclude(Expr),
+}
+
+impl Action {
+    fn expr(&self) -> &Expr {
+        match self {
+            Action::Include(expr) | Action::Exclude(expr) => expr,
+        }
+    }
+}
+
+/// A combination of patterns. Nested `And`s and `Or`s are flattened.
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Expr {
+    Pattern(Pattern),
+    /// All expressions must match.
+    And(Vec<Expr>),
+    /// At least one expression must match.
+    Or(Vec<Expr>),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Pattern {
+    Pid(PID),
+    Tree(PID),
+    Process(String),
+    Path(String),
+    ProcessRegex(Regex),
+    PathRegex(Regex),
+    // The following patterns are only supported on Linux, where they are evaluated in eBPF.
+    Uid(u32),
+    Gid(u32),
+    Cgroup(String),
+    Netns(String),
+    // Destination patterns.
+    Dst(Cidr),
+    Port(u16, u16),
+    Protocol(Protocol),
+}
+
+/// A regular expression, compared by its source.
+#[derive(Debug, Clone)]
+struct Regex(regex::Regex);
+
+impl Regex {
+    fn new(re: &str) -> Result<Self, String> {
+        regex::Regex::new(re).map(Regex).map_err(|e| {
+            // Syntax errors span multiple lines and repeat the regex, we only want the reason.
+            let e = e.to_string();
+            let reason = e.lines().last().unwrap_or_default();
+            format!(
+                "invalid regular expression: {}",
+                reason.trim_start_matches("error: ")
+            )
+        })
+    }
+}
+
+impl PartialEq for Regex {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Regex {}
+
+/// An IP network such as `10.0.0.0/8`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// The network in IPv4-mapped form, as used by the eBPF program.
+    fn mapped(&self) -> ([u8; 16], u8) {
+        let prefix_len = match self.addr {
+            IpAddr::V4(_) => self.prefix_len + 96,
+            IpAddr::V6(_) => self.prefix_len,
+        };
+        (mapped(self.addr), prefix_len)
+    }
+
+    fn contains(&self, addr: IpAddr) -> bool {
+        let (network, prefix_len) = self.mapped();
+        prefix_match(&network, prefix_len, &mapped(addr))
+    }
+}
+
+/// IPv4 addresses as IPv4-mapped IPv6 addresses, so that dual-stack sockets match IPv4 networks.
+fn mapped(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+impl Expr {
+    fn all(terms: Vec<Expr>) -> Self {
+        let mut flat = Vec::with_capacity(terms.len());
+        for term in terms {
+            match term {
+                Expr::And(nested) => flat.extend(nested),
+                term => flat.push(term),
+            }
+        }
+        if flat.len() == 1 {
+            flat.pop().unwrap()
+        } else {
+            Expr::And(flat)
+        }
+    }
+
+    fn any(alternatives: Vec<Expr>) -> Self {
+        let mut flat = Vec::with_capacity(alternatives.len());
+        for alternative in alternatives {
+            match alternative {
+                Expr::Or(nested) => flat.extend(nested),
+                alternative => flat.push(alternative),
+            }
+        }
+        if flat.len() == 1 {
+            flat.pop().unwrap()
+        } else {
+            Expr::Or(flat)
+        }
+    }
+
+    fn matches(&self, process_info: &ProcessInfo, destination: Option<&Destination>) -> bool {
+        match self {
+            Expr::Pattern(pattern) => pattern.matches(process_info, destination),
+            Expr::And(terms) => terms.iter().all(|e| e.matches(process_info, destination)),
+            Expr::Or(alternatives) => alternatives
+                .iter()
+                .any(|e| e.matches(process_info, destination)),
+        }
+    }
+
+    fn patterns(&self) -> Vec<&Pattern> {
+        match self {
+            Expr::Pattern(pattern) => vec![pattern],
+            Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().flat_map(Expr::patterns).collect(),
+        }
+    }
+
+    /// Describe the processes and connections that match,
+    /// e.g. `processes matching "curl" when connecting to port 443`.
+    fn description(&self) -> String {
+        let terms = match self {
+            Expr::Pattern(_) => std::slice::from_ref(self),
+            Expr::And(terms) => terms.as_slice(),
+            Expr::Or(alternatives) => {
+                let alternatives: Vec<String> =
+                    alternatives.iter().map(Expr::description).collect();
+                return alternatives.join(" or ");
+            }
+        };
+        let (destination, process): (Vec<&Expr>, Vec<&Expr>) = terms
+            .iter()
+            .partition(|e| matches!(e, Expr::Pattern(p) if p.is_destination()));
+        let mut description = if process.is_empty() {
+            "all processes".to_string()
+        } else {
+            let process: Vec<String> = process
+                .iter()
+                .map(|e| match e {
+                    Expr::Pattern(p) => p.description(),
+                    e => format!("({})", e.description()),
+                })
+                .collect();
+            process.join(" if they are also ")
+        };
+        if !destination.is_empty() {
+            description.push_str(" when connecting");
+        }
+        let has_dst = destination
+            .iter()
+            .any(|e| matches!(e, Expr::Pattern(Pattern::Dst(_))));
+        for expr in destination {
+            match expr {
+                Expr::Pattern(Pattern::Protocol(Protocol::Tcp)) => description.push_str(" via TCP"),
+                Expr::Pattern(Pattern::Protocol(Protocol::Udp)) => description.push_str(" via UDP"),
+                Expr::Pattern(Pattern::Dst(cidr)) => description.push_str(&format!(" to {cidr}")),
+                Expr::Pattern(Pattern::Port(min, max)) => {
+                    description.push_str(if has_dst { " on" } else { " to" });
+                    if min == max {
+                        description.push_str(&format!(" port {min}"));
+                    } else {
+                        description.push_str(&format!(" ports {min}-{max}"));
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        description
+    }
+}
+
+/// Match a glob pattern against a process name or path, which may be longer than eBPF names.
+fn glob(pattern: &str, value: &str) -> bool {
+    let steps = glob_steps(pattern.len(), value.len());
+    glob_match_steps(pattern.as_bytes(), value.as_bytes(), steps)
+}
+
+impl Pattern {
+    #[inline(always)]
+    fn matches(&self, process_info: &ProcessInfo, destination: Option<&Destination>) -> bool {
+        match self {
+            Pattern::Pid(pid) => process_info.pid == *pid,
+            Pattern::Tree(pid) => process_info.pid == *pid || process_info.ancestors.contains(pid),
+            Pattern::Process(name) => process_info.name().is_some_and(|n| glob(name, &n)),
+            Pattern::Path(path) => process_info.path().is_some_and(|p| glob(path, &p)),
+            Pattern::ProcessRegex(re) => process_info.name().is_some_and(|n| re.0.is_match(&n)),
+            Pattern::PathRegex(re) => process_info.path().is_some_and(|p| re.0.is_match(&p)),
+            Pattern::Uid(_) | Pattern::Gid(_) | Pattern::Cgroup(_) | Pattern::Netns(_) => false,
+            Pattern::Dst(cidr) => destination.is_some_and(|d| cidr.contains(d.addr.ip())),
+            Pattern::Port(min, max) => {
+                destination.is_some_and(|d| (*min..=*max).contains(&d.addr.port()))
+            }
+            Pattern::Protocol(protocol) => destination.is_some_and(|d| d.protocol == *protocol),
+        }
+    }
+
+    /// Describe a process pattern.
+    fn description(&self) -> String {
+        match self {
+            Pattern::Pid(pid) => format!("PID {pid}"),
+            Pattern::Tree(pid) => format!("PID {pid} and all processes it spawns"),
+            Pattern::Process(name) => format!("processes matching \"{name}\""),
+            Pattern::Path(path) => format!("processes with a path matching \"{path}\""),
+            Pattern::ProcessRegex(re) => {
+                format!("processes with a name matching the regex \"{}\"", re.0)
+            }
+            Pattern::PathRegex(re) => {
+                format!("processes with a path matching the regex \"{}\"", re.0)
+            }
+            Pattern::Uid(uid) => format!("processes of user {uid}"),
+            Pattern::Gid(gid) => format!("processes of group {gid}"),
+            Pattern::Cgroup(cgroup) => format!("processes in cgroup {cgroup}"),
+            Pattern::Netns(netns) => format!("processes in network namespace {netns}"),
+            Pattern::Dst(_) | Pattern::Port(_, _) | Pattern::Protocol(_) => unreachable!(),
+        }
+    }
+
+    fn is_destination(&self) -> bool {
+        matches!(
+            self,
+            Pattern::Dst(_) | Pattern::Port(_, _) | Pattern::Protocol(_)
+        )
+    }
+
+    fn linux_only(&self) -> bool {
+        matches!(
+            self,
+            Pattern::Uid(_) | Pattern::Gid(_) | Pattern::Cgroup(_) | Pattern::Netns(_)
+        )
+    }
+}
+
+/// The file name of a Windows or Unix path.
+fn file_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+impl TryFrom<&str> for InterceptConf {
+    type Error = anyhow::Error;
+
+    /// Parse a spec. Errors are [SpecError]s that point to the invalid part of the spec.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Ok(InterceptConf::new(parser::parse_spec(value)?))
+    }
+}
+
+impl<T: AsRef<str>> TryFrom<Vec<T>> for InterceptConf {
+    type Error = anyhow::Error;
+
+    /// Parse a list of actions, as returned by [InterceptConf::actions].
+    fn try_from(value: Vec<T>) -> Result<Self, Self::Error> {
+        let actions = value
+            .into_iter()
+            .map(|a| parser::parse_action(a.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(InterceptConf::new(actions))
+    }
+}
+
+impl TryFrom<&str> for Cidr {
+    type Error = anyhow::Error;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (addr, prefix_len) = match value.split_once('/') {
+            Some((addr, prefix_len)) => (addr.parse::<IpAddr>()?, Some(prefix_len.parse::<u8>()?)),
+            None => (value.parse::<IpAddr>()?, None),
+        };
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        ensure!(prefix_len <= max_len, "invalid prefix length: {value}");
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if matches!(self, Action::Exclude(_)) {
+            write!(f, "!")?;
+        }
+        write!(f, "{}", self.expr())
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (exprs, separator) = match self {
+            Expr::Pattern(pattern) => return write!(f, "{pattern}"),
+            Expr::And(terms) => (terms, "&"),
+            Expr::Or(alternatives) => (alternatives, "|"),
+        };
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                write!(f, "{separator}")?;
+            }
+            // `&` binds more tightly than `|`.
+            if matches!(expr, Expr::Or(_)) {
+                write!(f, "({expr})")?;
+            } else {
+                write!(f, "{expr}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let max_len = if self.addr.is_ipv4() { 32 } else { 128 };
+        if self.prefix_len == max_len {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix_len)
+        }
+    }
+}
+
+impl std::fmt::Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Udp => write!(f, "udp"),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Pid(pid) => write!(f, "{pid}"),
+            Pattern::Tree(pid) => write!(f, "tree:{pid}"),
+            Pattern::Process(name) => write_value(f, name, true),
+            Pattern::Path(path) => {
+                write!(f, "path:")?;
+                write_value(f, path, false)
+            }
+            Pattern::ProcessRegex(re) => {
+                write!(f, "name:~")?;
+                write_value(f, re.0.as_str(), false)
+            }
+            Pattern::PathRegex(re) => {
+                write!(f, "path:~")?;
+                write_value(f, re.0.as_str(), false)
+            }
+            Pattern::Uid(uid) => write!(f, "uid:{uid}"),
+            Pattern::Gid(gid) => write!(f, "gid:{gid}"),
+            Pattern::Cgroup(cgroup) => {
+                write!(f, "cgroup:")?;
+                write_value(f, cgroup, false)
+            }
+            Pattern::Netns(netns) => {
+                write!(f, "netns:")?;
+                write_value(f, netns, false)
+            }
+            Pattern::Dst(cidr) => write!(f, "dst:{cidr}"),
+            Pattern::Port(min, max) if min == max => write!(f, "port:{min}"),
+            Pattern::Port(min, max) => write!(f, "port:{min}-{max}"),
+            Pattern::Protocol(protocol) => write!(f, "proto:{protocol}"),
+        }
+    }
+}
+
+impl InterceptConf {
+    fn new(actions: Vec<Action>) -> Self {
+        let default = matches!(actions.first(), Some(Action::Exclude(_)));
+        Self { default, actions }
+    }
+
+    pub fn disabled() -> Self {
+        Self::new(vec![])
+    }
+
+    pub fn actions(&self) -> Vec<String> {
+        self.actions.iter().map(|a| a.to_string()).collect()
+    }
+
+    pub fn default(&self) -> bool {
+        self.default
+    }
+
+    /// Whether the spec contains patterns that can only be evaluated on Linux.
+    pub fn requires_linux(&self) -> bool {
+        self.patterns().any(Pattern::linux_only)
+    }
+
+    /// Whether the spec contains patterns that match the destination address or port,
+    /// which are not known for all connections on macOS.
+    pub fn matches_addresses(&self) -> bool {
+        self.patterns()
+            .any(|p| matches!(p, Pattern::Dst(_) | Pattern::Port(_, _)))
+    }
+
+    /// Whether the spec contains process tree patterns,
+    /// i.e. whether [ProcessInfo::ancestors] needs to be populated.
+    pub fn uses_process_trees(&self) -> bool {
+        self.patterns().any(|p| matches!(p, Pattern::Tree(_)))
+    }
+
+    fn patterns(&self) -> impl Iterator<Item = &Pattern> {
+        self.actions.iter().flat_map(|a| a.expr().patterns())
+    }
+
+    /// Decide whether a connection should be intercepted.
+    /// `destination` is `None` if it is not known yet, in which case destination patterns don't match.
+    pub fn should_intercept(
+        &self,
+        process_info: &ProcessInfo,
+        destination: Option<&Destination>,
+    ) -> bool {
+        let mut intercept = self.default;
+        for action in &self.actions {
+            let matches = || action.expr().matches(process_info, destination);
+            match action {
+                Action::Include(_) => {
+                    intercept = intercept || matches();
+                }
+                Action::Exclude(_) => {
+                    intercept = intercept && !matches();
+                }
+            }
+        }
+        intercept
+    }
+
+    pub fn description(&self) -> String {
+        if self.actions.is_empty() {
+            return "Intercept nothing.".to_string();
+        }
+        let parts: Vec<String> = self
+            .actions
+            .iter()
+            .map(|a| {
+                let verb = match a {
+                    Action::Include(_) => "Include",
+                    Action::Exclude(_) => "Exclude",
+                };
+                format!("{verb} {}.", a.expr().description())
+            })
+            .collect();
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intercept_conf() {
+        let a = ProcessInfo {
+            pid: 1,
+            process_name: Some("a".into()),
+            ..Default::default()
+        };
+        let b = ProcessInfo {
+            pid: 2242,
+            process_name: Some("mitmproxy".into()),
+            ..Default::default()
+        };
+
+        let conf = InterceptConf::try_from("1,2,3").unwrap();
+        assert!(conf.should_intercept(&a, None));
+        assert!(!conf.should_intercept(&b, None));
+
+        let conf = InterceptConf::try_from("").unwrap();
+        assert!(!conf.should_intercept(&a, None));
+        assert!(!conf.should_intercept(&b, None));
+        assert_eq!(conf, InterceptConf::disabled());
+
+        let conf = InterceptConf::try_from("!1234").unwrap();
+        assert!(conf.should_intercept(&a, None));
+        assert!(conf.should_intercept(&b, None));
+
+        let conf = InterceptConf::try_from("mitm*").unwrap();
+        assert!(!conf.should_intercept(&a, None));
+        assert!(conf.should_intercept(&b, None));
+
+        assert!(InterceptConf::try_from(",,").is_err());
+    }
+
+    #[test]
+    fn test_linux_patterns() {
+        let conf =
+            InterceptConf::try_from("uid:1001,!gid:0,cgroup:/system.slice,netns:foo").unwrap();
+        assert_eq!(
+            conf.actions(),
+            vec!["uid:1001", "!gid:0", "cgroup:/system.slice", "netns:foo"]
+        );
+        assert!(conf.requires_linux());
+        assert_eq!(
+            conf.description(),
+            "Include processes of user 1001. Exclude processes of group 0. \
+             Include processes in cgroup /system.slice. Include processes in network namespace foo."
+        );
+        assert!(!InterceptConf::try_from("curl,42").unwrap().requires_linux());
+        assert!(!InterceptConf::try_from("curl&proto:udp")
+            .unwrap()
+            .matches_addresses());
+        assert!(InterceptConf::try_from("curl&(proto:udp|port:53)")
+            .unwrap()
+            .matches_addresses());
+
+        assert!(InterceptConf::try_from("uid:root").is_err());
+        assert!(InterceptConf::try_from("cgroup:").is_err());
+    }
+
+    #[test]
+    fn test_process_trees() {
+        let conf = InterceptConf::try_from("tree:10,!tree:20").unwrap();
+        assert_eq!(conf.actions(), vec!["tree:10", "!tree:20"]);
+        assert!(conf.uses_process_trees());
+        assert!(!conf.requires_linux());
+        assert_eq!(
+            conf.description(),
+            "Include PID 10 and all processes it spawns. \
+             Exclude PID 20 and all processes it spawns."
+        );
+        assert!(!InterceptConf::try_from("10").unwrap().uses_process_trees());
+
+        let process = |pid, ancestors: &[PID]| ProcessInfo {
+            pid,
+            process_name: None,
+            ancestors: ancestors.to_vec(),
+            ..Default::default()
+        };
+        assert!(conf.should_intercept(&process(10, &[1]), None));
+        assert!(conf.should_intercept(&process(11, &[10, 1]), None));
+        assert!(conf.should_intercept(&process(12, &[11, 10, 1]), None));
+        assert!(!conf.should_intercept(&process(20, &[10, 1]), None));
+        assert!(!conf.should_intercept(&process(21, &[20, 10, 1]), None));
+        assert!(!conf.should_intercept(&process(1, &[]), None));
+        assert!(!conf.should_intercept(&process(30, &[1]), None));
+
+        assert!(InterceptConf::try_from("tree:").is_err());
+        assert!(InterceptConf::try_from("tree:firefox").is_err());
+    }
+
+    #[test]
+    fn test_destinations() {
+        let conf = InterceptConf::try_from(
+            "firefox*&port:443,!dst:10.0.0.0/8,curl&proto:udp&dst:2001:db8::1&port:53-54",
+        )
+        .unwrap();
+        assert_eq!(
+            conf.actions(),
+            vec![
+                "firefox*&port:443",
+                "!dst:10.0.0.0/8",
+                "curl&proto:udp&dst:2001:db8::1&port:53-54"
+            ]
+        );
+        assert!(!conf.requires_linux());
+        assert_eq!(
+            conf.description(),
+            "Include processes matching \"firefox*\" when connecting to port 443. \
+             Exclude all processes when connecting to 10.0.0.0/8. \
+             Include processes matching \"curl\" when connecting via UDP to 2001:db8::1 on ports 53-54."
+        );
+        assert_eq!(
+            InterceptConf::try_from("curl&uid:0").unwrap().description(),
+            "Include processes matching \"curl\" if they are also processes of user 0."
+        );
+
+        for invalid in [
+            "dst:",
+            "dst:10.0.0.0/33",
+            "dst:::1/129",
+            "dst:example.com",
+            "port:",
+            "port:65536",
+            "port:2-1",
+            "proto:icmp",
+            "curl&",
+            "&port:443",
+        ] {
+            assert!(InterceptConf::try_from(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_grammar() {
+        for (spec, canonical) in [
+            ("curl", "curl"),
+            (" curl , ! wget ", "curl,!wget"),
+            ("a | b & c", "a|b&c"),
+            ("(a|b)&c", "(a|b)&c"),
+            ("((a|b)|c)", "a|b|c"),
+            ("(a&b)&(c)", "a&b&c"),
+            ("!(a|b)&(c|d)", "!(a|b)&(c|d)"),
+            ("pid:42", "42"),
+            ("name:curl", "curl"),
+            ("name:42", "\"42\""),
+            ("\"42\"", "\"42\""),
+            ("name:\"a,b\"", "\"a,b\""),
+            ("\"say \\\"hi\\\"\"", "\"say \\\"hi\\\"\""),
+            ("\"tree:1\"", "\"tree:1\""),
+            (
+                "Google Chrome Helper (Renderer)",
+                "Google Chrome Helper (Renderer)",
+            ),
+            (
+                "(Helper (Renderer)|Helper (GPU))",
+                "Helper (Renderer)|Helper (GPU)",
+            ),
+            ("\" spaces \"", "\" spaces \""),
+            ("C:\\curl.exe", "C:\\curl.exe"),
+            ("path:/usr/bin/*", "path:/usr/bin/*"),
+            ("path:\"C:\\Program Files\\*\"", "path:C:\\Program Files\\*"),
+            ("path:\"~/bin/*\"", "path:\"~/bin/*\""),
+            ("name:~^fire(fox)?$", "name:~^fire(fox)?$"),
+            ("name:~\"^fire(fox|wall)$\"", "name:~\"^fire(fox|wall)$\""),
+            ("name: ~ \"a|b\"", "name:~\"a|b\""),
+            ("path:~\"\\\\\\\\bin\"", "path:~\\\\bin"),
+            ("cgroup:\"/a,b\"", "cgroup:\"/a,b\""),
+        ] {
+            let conf = InterceptConf::try_from(spec).unwrap();
+            assert_eq!(conf.actions().join(","), canonical, "{spec}");
+            assert_eq!(InterceptConf::try_from(canonical).unwrap(), conf, "{spec}");
+            assert_eq!(InterceptConf::try_from(conf.actions()).unwrap(), conf);
+        }
+
+        assert_eq!(
+            InterceptConf::try_from("(curl|42)&port:443,!uid:0")
+                .unwrap()
+                .description(),
+            "Include (processes matching \"curl\" or PID 42) when connecting to port 443. \
+             Exclude processes of user 0."
+        );
+        assert_eq!(
+            InterceptConf::try_from("curl&port:443|wget")
+                .unwrap()
+                .description(),
+            "Include processes matching \"curl\" when connecting to port 443 \
+             or processes matching \"wget\"."
+        );
+    }
+
+    #[test]
+    fn test_spec_errors() {
+        for (spec, message, span) in [
+            (",,", "expected a pattern", 0..0),
+            ("curl,", "expected a pattern", 5..5),
+            ("curl&", "expected a pattern", 5..5),
+            ("&port:443", "expected a pattern", 0..0),
+            ("(curl", "unclosed '('", 0..1),
+            ("(\"curl\" x)", "expected '&', '|' or ')'", 8..9),
+            ("curl)", "unmatched ')'", 4..5),
+            ("\"curl\" x", "expected ',', '&' or '|'", 7..8),
+            (
+                "a&!b",
+                "'!' is only allowed at the start of an action",
+                2..3,
+            ),
+            ("name:\"curl", "unterminated string", 5..10),
+            ("\"\"", "process name must not be empty", 0..2),
+            ("pid:", "expected a value after 'pid:'", 4..4),
+            ("curl,tree:firefox", "invalid PID", 10..17),
+            ("uid:root", "invalid user id", 4..8),
+            ("dst:10.0.0.0/33", "invalid address or network", 4..15),
+            ("port: 65536 ", "invalid port", 6..11),
+            ("port:2-1", "invalid port range", 5..8),
+            ("proto:icmp", "unknown protocol, expected tcp or udp", 6..10),
+            (
+                "name:~\"(a\"",
+                "invalid regular expression: unclosed group",
+                6..10,
+            ),
+        ] {
+            let err = InterceptConf::try_from(spec).unwrap_err();
+            let err = err.downcast_ref::<SpecError>().unwrap();
+            assert_eq!(err.message, message, "{spec}");
+            assert_eq!(err.span, span, "{spec}");
+        }
+
+        let err = InterceptConf::try_from("curl,tree:firefox").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid intercept spec: invalid PID\n    curl,tree:firefox\n              ^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn test_paths_and_regexes() {
+        let process = |path: &str| ProcessInfo {
+            pid: 1,
+            executable: Some(path.into()),
+            ..Default::default()
+        };
+        let conf = InterceptConf::try_from("path:/usr/bin/*").unwrap();
+        assert!(conf.should_intercept(&process("/usr/bin/curl"), None));
+        assert!(!conf.should_intercept(&process("/usr/local/bin/curl"), None));
+        assert!(!conf.should_intercept(&process("curl"), None));
+
+        // Different executables with the same name can be told apart.
+        let conf = InterceptConf::try_from("node,!path:/opt/*/node").unwrap();
+        assert!(conf.should_intercept(&process("/usr/bin/node"), None));
+        assert!(!conf.should_intercept(&process("/opt/app/bin/node"), None));
+
+        // Paths may be longer than the names the eBPF matcher is limited to.
+        let long = format!("/home/{}/.local/bin/tool", "a".repeat(100));
+        let conf = InterceptConf::try_from("path:/home/*/bin/tool").unwrap();
+        assert!(conf.should_intercept(&process(&long), None));
+
+        // Path patterns only match the executable, not the process name.
+        let name_only = ProcessInfo {
+            pid: 1,
+            process_name: Some("/usr/bin/curl".to_string()),
+            ..Default::default()
+        };
+        assert!(!InterceptConf::try_from("path:/usr/bin/*")
+            .unwrap()
+            .should_intercept(&name_only, None));
+        assert!(!conf.should_intercept(&ProcessInfo::default(), None));
+
+        // Name regexes match the file name, path regexes the entire path. Both are unanchored.
+        let conf = InterceptConf::try_from("name:~^fire").unwrap();
+        assert!(conf.should_intercept(&process("/usr/bin/firefox"), None));
+        assert!(!conf.should_intercept(&process("/opt/firefox/bin"), None));
+        let conf = InterceptConf::try_from("path:~/firefox/").unwrap();
+        assert!(conf.should_intercept(&process("/opt/firefox/bin"), None));
+        assert!(!conf.should_intercept(&process("/usr/bin/firefox"), None));
+
+        assert_eq!(
+            InterceptConf::try_from("path:/usr/*,!name:~\\d")
+                .unwrap()
+                .description(),
+            "Include processes with a path matching \"/usr/*\". \
+             Exclude processes with a name matching the regex \"\\d\"."
+        );
+        assert!(!InterceptConf::try_from("name:~x").unwrap().requires_linux());
+    }
+
+    /// Test cases shared with the eBPF matcher:
+    /// `spec | pid | process name | [destination |] intercept`.
+    pub(super) fn corpus(
+    ) -> impl Iterator<Item = (&'static str, PID, &'static str, Option<Destination>, bool)> {
+        include_str!("corpus.txt")
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let fields = line.split(" | ").map(str::trim).collect::<Vec<_>>();
+                let (spec, pid, name, destination, intercept) = match fields[..] {
+                    [spec, pid, name, intercept] => (spec, pid, name, "-", intercept),
+                    [spec, pid, name, destination, intercept] => {
+                        (spec, pid, name, destination, intercept)
+                    }
+                    _ => panic!("invalid corpus line: {line}"),
+                };
+                let destination = match destination.split_once(' ') {
+                    Some(("tcp", addr)) => Some(Destination {
+                        addr: addr.parse().unwrap(),
+                        protocol: Protocol::Tcp,
+                    }),
+                    Some(("udp", addr)) => Some(Destination {
+                        addr: addr.parse().unwrap(),
+                        protocol: Protocol::Udp,
+                    }),
+                    _ => None,
+                };
+                (
+                    spec,
+                    pid.parse().unwrap(),
+                    name,
+                    destination,
+                    intercept.parse().unwrap(),
+                )
+            })
+    }
+
+    #[test]
+    fn test_corpus() {
+        for (spec, pid, name, destination, expected) in corpus() {
+            let conf = InterceptConf::try_from(spec).unwrap();
+            assert_eq!(conf.actions().join(","), spec, "round trip");
+            let process_info = ProcessInfo {
+                pid,
+                process_name: Some(name.to_string()),
+                ..Default::default()
+            };
+            assert_eq!(
+                conf.should_intercept(&process_info, destination.as_ref()),
+                expected,
+                "{spec} | {pid} | {name} | {destination:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_paths() {
+        let conf = InterceptConf::try_from("curl").unwrap();
+        for path in ["curl", "/usr/bin/curl", "C:\\curl\\curl"] {
+            let process_info = ProcessInfo {
+                pid: 1,
+                process_name: Some(path.to_string()),
+                ..Default::default()
+            };
+            assert!(conf.should_intercept(&process_info, None), "{path}");
+        }
+        let process_info = ProcessInfo {
+            pid: 1,
+            process_name: Some("/usr/bin/curl/wget".to_string()),
+            ..Default::default()
+        };
+        assert!(!conf.should_intercept(&process_info, None));
+    }
+}
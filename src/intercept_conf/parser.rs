@@ -0,0 +1,327 @@
+//! Parser for intercept specs.
+//!
+//! ```text
+//! spec    = action *("," action)
+//! action  = ["!"] expr
+//! expr    = and *("|" and)
+//! and     = term *("&" term)
+//! term    = "(" expr ")" / pattern
+//! pattern = [prefix ":"] ["~"] value
+//! value   = quoted / bare
+//! ```
+//!
+//! Bare values extend up to the next `,`, `&`, `|` or unbalanced `)`, so that names such as
+//! `Google Chrome Helper (Renderer)` don't need to be quoted. Whitespace around values and
+//! operators is ignored. Quoted values support `\"` and `\\` as escape sequences, all other
+//! backslashes are kept as-is.
+
+use std::fmt;
+use std::ops::Range;
+
+use super::{Action, Cidr, Expr, Pattern, Protocol, Regex, PID};
+
+/// Prefixes of typed patterns.
+const PREFIXES: &[&str] = &[
+    "pid", "tree", "name", "path", "uid", "gid", "cgroup", "netns", "dst", "port", "proto",
+];
+
+/// An invalid intercept spec, pointing to the part of the spec that is invalid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecError {
+    pub message: String,
+    /// Byte range of the invalid part of the spec.
+    pub span: Range<usize>,
+    spec: String,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start = self.spec[..self.span.start].chars().count();
+        let len = self.spec[self.span.clone()].chars().count().max(1);
+        write!(
+            f,
+            "invalid intercept spec: {}\n    {}\n    {}{}",
+            self.message,
+            self.spec,
+            " ".repeat(start),
+            "^".repeat(len)
+        )
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+/// Parse a comma-separated list of actions.
+pub(super) fn parse_spec(spec: &str) -> Result<Vec<Action>, SpecError> {
+    if spec.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let mut parser = Parser { spec, pos: 0 };
+    let mut actions = vec![parser.action()?];
+    while parser.eat(',') {
+        actions.push(parser.action()?);
+    }
+    parser.end()?;
+    Ok(actions)
+}
+
+/// Parse a single action, as returned by [super::InterceptConf::actions].
+pub(super) fn parse_action(action: &str) -> Result<Action, SpecError> {
+    let mut parser = Parser {
+        spec: action,
+        pos: 0,
+    };
+    let action = parser.action()?;
+    parser.end()?;
+    Ok(action)
+}
+
+struct Parser<'a> {
+    spec: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>, span: Range<usize>) -> Result<T, SpecError> {
+        Err(SpecError {
+            message: message.into(),
+            span,
+            spec: self.spec.to_string(),
+        })
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.spec[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// The next non-whitespace character.
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.spec[self.pos..].chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn end(&mut self) -> Result<(), SpecError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(')') => self.error("unmatched ')'", self.pos..self.pos + 1),
+            Some(c) => self.error(
+                "expected ',', '&' or '|'",
+                self.pos..self.pos + c.len_utf8(),
+            ),
+        }
+    }
+
+    fn action(&mut self) -> Result<Action, SpecError> {
+        if self.eat('!') {
+            Ok(Action::Exclude(self.expr()?))
+        } else {
+            Ok(Action::Include(self.expr()?))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, SpecError> {
+        let mut alternatives = vec![self.and()?];
+        while self.eat('|') {
+            alternatives.push(self.and()?);
+        }
+        Ok(Expr::any(alternatives))
+    }
+
+    fn and(&mut self) -> Result<Expr, SpecError> {
+        let mut terms = vec![self.term()?];
+        while self.eat('&') {
+            terms.push(self.term()?);
+        }
+        Ok(Expr::all(terms))
+    }
+
+    fn term(&mut self) -> Result<Expr, SpecError> {
+        match self.peek() {
+            Some('(') => {
+                let open = self.pos;
+                self.pos += 1;
+                let expr = self.expr()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    None => self.error("unclosed '('", open..open + 1),
+                    Some(c) => self.error(
+                        "expected '&', '|' or ')'",
+                        self.pos..self.pos + c.len_utf8(),
+                    ),
+                }
+            }
+            Some('!') => self.error(
+                "'!' is only allowed at the start of an action",
+                self.pos..self.pos + 1,
+            ),
+            None | Some(',' | '&' | '|' | ')') => {
+                self.error("expected a pattern", self.pos..self.pos)
+            }
+            Some(_) => self.pattern().map(Expr::Pattern),
+        }
+    }
+
+    fn pattern(&mut self) -> Result<Pattern, SpecError> {
+        let prefix = prefix(&self.spec[self.pos..]);
+        if let Some(prefix) = prefix {
+            self.pos += prefix.len() + 1;
+        }
+        let regex = matches!(prefix, Some("name" | "path")) && self.eat('~');
+        self.skip_whitespace();
+        let start = self.pos;
+        let (value, quoted) = self.value()?;
+        let span = if quoted {
+            start..self.pos
+        } else {
+            start..start + value.len()
+        };
+        match pattern(prefix, regex, value, quoted) {
+            Ok(pattern) => Ok(pattern),
+            Err(message) => self.error(message, span),
+        }
+    }
+
+    /// Read a quoted or bare value.
+    fn value(&mut self) -> Result<(String, bool), SpecError> {
+        let start = self.pos;
+        let rest = &self.spec[start..];
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut chars = quoted.char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        self.pos = start + 1 + i + 1;
+                        return Ok((value, true));
+                    }
+                    '\\' if matches!(chars.clone().next(), Some((_, '"' | '\\'))) => {
+                        value.extend(chars.next().map(|(_, c)| c));
+                    }
+                    c => value.push(c),
+                }
+            }
+            return self.error("unterminated string", start..self.spec.len());
+        }
+        let mut parens = 0usize;
+        let len = rest
+            .find(|c| match c {
+                ',' | '&' | '|' => true,
+                '(' => {
+                    parens += 1;
+                    false
+                }
+                ')' if parens == 0 => true,
+                ')' => {
+                    parens -= 1;
+                    false
+                }
+                _ => false,
+            })
+            .unwrap_or(rest.len());
+        let value = rest[..len].trim_end();
+        self.pos = start + len;
+        Ok((value.to_string(), false))
+    }
+}
+
+/// The prefix of a typed pattern such as `pid:1234`.
+fn prefix(pattern: &str) -> Option<&str> {
+    let (prefix, _) = pattern.split_once(':')?;
+    PREFIXES.contains(&prefix).then_some(prefix)
+}
+
+/// Build a pattern from its parts, or return an error message for the value.
+fn pattern(
+    prefix: Option<&str>,
+    regex: bool,
+    value: String,
+    quoted: bool,
+) -> Result<Pattern, String> {
+    if value.is_empty() {
+        return Err(match prefix {
+            Some(prefix) => format!("expected a value after '{prefix}:'"),
+            None => "process name must not be empty".to_string(),
+        });
+    }
+    let pid = |value: &str| value.parse::<PID>().map_err(|_| "invalid PID".to_string());
+    Ok(match prefix {
+        None => match value.parse() {
+            Ok(pid) if !quoted => Pattern::Pid(pid),
+            _ => Pattern::Process(value),
+        },
+        Some("pid") => Pattern::Pid(pid(&value)?),
+        Some("tree") => Pattern::Tree(pid(&value)?),
+        Some("name") if regex => Pattern::ProcessRegex(Regex::new(&value)?),
+        Some("name") => Pattern::Process(value),
+        Some("path") if regex => Pattern::PathRegex(Regex::new(&value)?),
+        Some("path") => Pattern::Path(value),
+        Some("uid") => Pattern::Uid(value.parse().map_err(|_| "invalid user id")?),
+        Some("gid") => Pattern::Gid(value.parse().map_err(|_| "invalid group id")?),
+        Some("cgroup") => Pattern::Cgroup(value),
+        Some("netns") => Pattern::Netns(value),
+        Some("dst") => {
+            Pattern::Dst(Cidr::try_from(value.as_str()).map_err(|_| "invalid address or network")?)
+        }
+        Some("port") => {
+            let port = |p: &str| p.parse::<u16>().map_err(|_| "invalid port".to_string());
+            let (min, max) = match value.split_once('-') {
+                Some((min, max)) => (port(min)?, port(max)?),
+                None => (port(&value)?, port(&value)?),
+            };
+            if min > max {
+                return Err("invalid port range".to_string());
+            }
+            Pattern::Port(min, max)
+        }
+        Some("proto") => Pattern::Protocol(match value.as_str() {
+            "tcp" => Protocol::Tcp,
+            "udp" => Protocol::Udp,
+            _ => return Err("unknown protocol, expected tcp or udp".to_string()),
+        }),
+        Some(prefix) => unreachable!("unknown prefix: {prefix}"),
+    })
+}
+
+/// Write a value so that it is parsed back unchanged, quoting it if necessary.
+/// Process names are never typed, so they also need quotes if they look like a PID or prefix.
+pub(super) fn write_value(f: &mut fmt::Formatter<'_>, value: &str, name: bool) -> fmt::Result {
+    let mut parens = 0usize;
+    let balanced = value.chars().all(|c| match c {
+        '(' => {
+            parens += 1;
+            true
+        }
+        ')' => parens.checked_sub(1).map(|p| parens = p).is_some(),
+        _ => true,
+    }) && parens == 0;
+    let needs_quotes = value.is_empty()
+        || value.trim() != value
+        || value.contains([',', '&', '|', '"'])
+        || value.starts_with(['(', '!', '~'])
+        || !balanced
+        || (name && (value.parse::<PID>().is_ok() || prefix(value).is_some()));
+    if !needs_quotes {
+        return write!(f, "{value}");
+    }
+    write!(f, "\"")?;
+    for c in value.chars() {
+        if matches!(c, '"' | '\\') {
+            write!(f, "\\")?;
+        }
+        write!(f, "{c}")?;
+    }
+    write!(f, "\"")
+}
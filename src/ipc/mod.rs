@@ -65,4 +65,13 @@ mod tests {
         assert!(SocketAddr::try_from(&c).is_ok());
         assert_eq!(SocketAddr::try_from(&a), SocketAddr::try_from(&c));
     }
+
+    #[test]
+    fn test_intercept_conf_round_trip() {
+        let conf =
+            intercept_conf::InterceptConf::try_from("firefox*&port:443,!dst:10.0.0.0/8").unwrap();
+        let ipc = InterceptConf::from(conf.clone());
+        assert_eq!(ipc.actions, vec!["firefox*&port:443", "!dst:10.0.0.0/8"]);
+        assert_eq!(intercept_conf::InterceptConf::try_from(ipc).unwrap(), conf);
+    }
 }
@@ -11,3 +11,4 @@ pub mod processes;
 pub mod shutdown;
 #[cfg(windows)]
 pub mod windows;
+pub mod wireguard_config;
@@ -1,6 +1,8 @@
 use std::fmt;
 use std::fmt::Formatter;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Result};
 use internet_packet::{InternetPacket, TransportProtocol};
@@ -12,6 +14,7 @@ pub enum TunnelInfo {
     WireGuard {
         src_addr: SocketAddr,
         dst_addr: SocketAddr,
+        peer: Arc<WireGuardPeerTunnelInfo>,
     },
     LocalRedirector {
         pid: Option<u32>,
@@ -23,6 +26,18 @@ pub enum TunnelInfo {
     None,
 }
 
+/// The WireGuard peer that a connection was received from.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WireGuardPeerTunnelInfo {
+    /// The peer's public key, base64-encoded.
+    pub public_key: String,
+    /// The configured name of the peer, or a prefix of its public key.
+    pub name: String,
+    /// Endpoints the peer has sent authenticated packets from (oldest first),
+    /// with the time at which the peer roamed to them.
+    pub endpoints: Vec<(SocketAddr, SystemTime)>,
+}
+
 /// Events that are sent by WireGuard to the TCP stack.
 #[derive(Debug)]
 pub enum NetworkEvent {
@@ -86,10 +101,66 @@ pub enum TransportEvent {
         src_addr: SocketAddr,
         dst_addr: SocketAddr,
         tunnel_info: TunnelInfo,
+        /// Parameters of the client's initial SYN, if this is a TCP connection
+        /// intercepted at the packet level.
+        tcp_syn: Option<TcpSynInfo>,
         // Channel over which the stream should emit commands.
         // If command_tx is None, the main channel is used.
+        // Either way, ConnectionClosed is sent over the event channel.
         command_tx: Option<mpsc::UnboundedSender<TransportCommand>>,
     },
+    ConnectionClosed {
+        connection_id: ConnectionId,
+        reason: CloseReason,
+    },
+}
+
+/// Parameters of a client's initial TCP SYN packet, e.g. for fingerprinting.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TcpSynInfo {
+    /// IPv4 TTL or IPv6 hop limit.
+    pub ttl: u8,
+    pub window: u16,
+    /// TCP option kinds in the order in which they appeared, including NOPs.
+    pub options: Vec<u8>,
+    pub mss: Option<u16>,
+    pub window_scale: Option<u8>,
+    pub sack_permitted: bool,
+    /// TSval and TSecr of the timestamp option.
+    pub timestamp: Option<(u32, u32)>,
+}
+
+/// The reason why a connection was closed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CloseReason {
+    /// The connection was closed gracefully (TCP FIN handshake).
+    Fin,
+    /// The connection was reset by the peer (TCP RST).
+    Reset,
+    /// The peer stopped acknowledging our data (TCP), or the connection was idle for too long (UDP).
+    Timeout,
+    /// The peer stopped responding to TCP keepalive probes.
+    KeepaliveExpired,
+    /// The server was shut down.
+    Shutdown,
+}
+
+impl CloseReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloseReason::Fin => "fin",
+            CloseReason::Reset => "reset",
+            CloseReason::Timeout => "timeout",
+            CloseReason::KeepaliveExpired => "keepalive_expired",
+            CloseReason::Shutdown => "shutdown",
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 /// Commands that are sent by the Python side to the TCP stack.
@@ -196,6 +267,13 @@ impl SmolPacket {
         }
     }
 
+    pub fn hop_limit(&self) -> u8 {
+        match self {
+            SmolPacket::V4(packet) => packet.hop_limit(),
+            SmolPacket::V6(packet) => packet.hop_limit(),
+        }
+    }
+
     pub fn transport_protocol(&self) -> IpProtocol {
         match self {
             SmolPacket::V4(packet) => packet.next_header(),
@@ -21,18 +21,38 @@ pub struct NetworkStack<'a> {
 }
 
 impl NetworkStack<'_> {
-    pub fn new(net_tx: Sender<NetworkCommand>) -> Self {
+    pub fn new(net_tx: Sender<NetworkCommand>, py_tx: Sender<TransportEvent>) -> Self {
         Self {
-            tcp: TcpHandler::new(net_tx.clone()),
-            udp: UdpHandler::new(),
+            tcp: TcpHandler::new(net_tx.clone(), py_tx.clone()),
+            udp: UdpHandler::new(py_tx),
             net_tx,
         }
     }
 
+    /// Advertise TCP Fast Open support to clients.
+    pub fn set_tcp_fast_open(&mut self, enabled: bool) {
+        self.tcp.set_fast_open(enabled);
+    }
+
     pub fn handle_network_event(
         &mut self,
         event: NetworkEvent,
         permit: Permit<'_, TransportEvent>,
+    ) -> Result<()> {
+        self.receive_network_event(event, Some(permit))
+    }
+
+    /// Handle a network event while draining after a shutdown request.
+    /// Packets for existing TCP connections are still processed, but new connections are
+    /// refused and UDP packets are discarded as nobody is left to read them.
+    pub fn handle_network_event_draining(&mut self, event: NetworkEvent) -> Result<()> {
+        self.receive_network_event(event, None)
+    }
+
+    fn receive_network_event(
+        &mut self,
+        event: NetworkEvent,
+        permit: Option<Permit<'_, TransportEvent>>,
     ) -> Result<()> {
         let (packet, tunnel_info) = match event {
             NetworkEvent::ReceivePacket {
@@ -51,6 +71,10 @@ impl NetworkStack<'_> {
         match packet.transport_protocol() {
             IpProtocol::Tcp => self.tcp.receive_packet(packet, tunnel_info, permit),
             IpProtocol::Udp => {
+                let Some(permit) = permit else {
+                    log::debug!("Discarding UDP packet during shutdown.");
+                    return Ok(());
+                };
                 match UdpPacket::try_from(packet) {
                     Ok(packet) => self.udp.receive_data(packet, tunnel_info, permit),
                     Err(e) => log::debug!("Received invalid UDP packet: {e}"),
@@ -116,6 +140,16 @@ impl NetworkStack<'_> {
         self.udp.poll();
         self.tcp.poll()
     }
+
+    /// Close all TCP connections once their pending data has been sent.
+    pub fn start_draining(&mut self) {
+        self.tcp.start_draining();
+    }
+
+    /// Returns `true` once all TCP connections have flushed their data and have been closed.
+    pub fn is_drained(&self) -> bool {
+        self.tcp.is_drained()
+    }
 }
 
 impl fmt::Debug for NetworkStack<'_> {
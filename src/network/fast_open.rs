@@ -0,0 +1,166 @@
+//! TCP Fast Open (RFC 7413) support.
+//!
+//! smoltcp neither accepts data in SYN segments nor knows about the TFO option, so we rewrite
+//! packets around it: SYN payloads are cut off before smoltcp sees them (and their sequence number
+//! adjusted so that smoltcp acknowledges the data), and TFO cookies are added to outgoing SYN/ACKs.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::Result;
+use smoltcp::wire::{
+    IpProtocol, Ipv4Packet, Ipv6Packet, TcpOption, TcpPacket, IPV6_HEADER_LEN, TCP_HEADER_LEN,
+};
+
+use crate::messages::SmolPacket;
+
+/// TCP option kind for TCP Fast Open cookies.
+pub const TFO_OPTION_KIND: u8 = 34;
+
+/// Outgoing SYN/ACKs that should carry a TFO cookie, keyed by (local address, remote address).
+pub type FastOpenCookies = HashMap<(SocketAddr, SocketAddr), [u8; 8]>;
+
+/// Generates TFO cookies, which are a keyed hash of the client's IP address.
+#[derive(Debug, Default)]
+pub struct CookieGenerator(RandomState);
+
+impl CookieGenerator {
+    pub fn cookie(&self, client: IpAddr) -> [u8; 8] {
+        self.0.hash_one(client).to_be_bytes()
+    }
+}
+
+/// Return the cookie of a TFO option in the given TCP options, if present.
+/// Clients request a new cookie by sending an empty TFO option.
+pub fn fast_open_option(mut options: &[u8]) -> Option<&[u8]> {
+    while !options.is_empty() {
+        let Ok((rest, option)) = TcpOption::parse(options) else {
+            break;
+        };
+        match option {
+            TcpOption::EndOfList => break,
+            TcpOption::Unknown {
+                kind: TFO_OPTION_KIND,
+                data,
+            } => return Some(data),
+            _ => {}
+        }
+        options = rest;
+    }
+    None
+}
+
+/// Remove the payload of a SYN segment and advance its sequence number by the length of the data
+/// we have accepted, so that smoltcp's SYN/ACK acknowledges it.
+pub fn strip_syn_payload(packet: SmolPacket, accepted_len: usize) -> Result<SmolPacket> {
+    rewrite_tcp_segment(packet, |buf, tcp_header_len| {
+        buf.truncate(tcp_header_len);
+        let mut tcp = TcpPacket::new_unchecked(&mut buf[..]);
+        let seq_number = tcp.seq_number() + accepted_len;
+        tcp.set_seq_number(seq_number);
+    })
+}
+
+/// Add a TFO cookie to an outgoing SYN/ACK if one has been registered for its connection.
+pub fn add_fast_open_cookie(
+    mut packet: SmolPacket,
+    cookies: &FastOpenCookies,
+) -> Result<SmolPacket> {
+    if cookies.is_empty() || packet.transport_protocol() != IpProtocol::Tcp {
+        return Ok(packet);
+    }
+    let src_ip = packet.src_ip();
+    let dst_ip = packet.dst_ip();
+    let Ok(tcp) = TcpPacket::new_checked(packet.payload_mut()) else {
+        return Ok(packet);
+    };
+    if !(tcp.syn() && tcp.ack()) {
+        return Ok(packet);
+    }
+    let key = (
+        SocketAddr::new(src_ip, tcp.src_port()),
+        SocketAddr::new(dst_ip, tcp.dst_port()),
+    );
+    let Some(cookie) = cookies.get(&key) else {
+        return Ok(packet);
+    };
+    let mut option = vec![TFO_OPTION_KIND, 2 + cookie.len() as u8];
+    option.extend_from_slice(cookie);
+    add_tcp_option(packet, &option)
+}
+
+/// Add a TCP option to the options of a TCP segment, in front of any End of Option List padding.
+/// If the resulting header would exceed the maximum TCP header size, the packet is returned as-is.
+pub fn add_tcp_option(packet: SmolPacket, option: &[u8]) -> Result<SmolPacket> {
+    rewrite_tcp_segment(packet, |buf, tcp_header_len| {
+        let mut options = buf[TCP_HEADER_LEN..tcp_header_len].to_vec();
+        options.truncate(options_len(&options));
+        options.extend_from_slice(option);
+        // pad with End of Option List so that the header length stays a multiple of four.
+        options.resize(options.len().next_multiple_of(4), 0);
+
+        let new_header_len = TCP_HEADER_LEN + options.len();
+        if new_header_len > 60 {
+            log::debug!("Not enough space to add TCP option.");
+            return;
+        }
+        let payload = buf.split_off(tcp_header_len);
+        buf.truncate(TCP_HEADER_LEN);
+        buf.extend(options);
+        buf.extend(payload);
+        TcpPacket::new_unchecked(&mut buf[..]).set_header_len(new_header_len as u8);
+    })
+}
+
+/// Length of the given TCP options up to (excluding) the End of Option List.
+fn options_len(options: &[u8]) -> usize {
+    let mut rest = options;
+    while let Ok((next, option)) = TcpOption::parse(rest) {
+        if option == TcpOption::EndOfList {
+            break;
+        }
+        rest = next;
+    }
+    options.len() - rest.len()
+}
+
+/// Modify the TCP segment of a packet and fix up lengths and checksums afterwards.
+/// `f` is called with the TCP segment and the length of its header.
+/// Fails if the rewritten packet is not a valid IP packet.
+fn rewrite_tcp_segment<F>(mut packet: SmolPacket, f: F) -> Result<SmolPacket>
+where
+    F: FnOnce(&mut Vec<u8>, usize),
+{
+    let src_ip = packet.src_ip();
+    let dst_ip = packet.dst_ip();
+    let ip_header_len = match &packet {
+        SmolPacket::V4(p) => p.header_len() as usize,
+        SmolPacket::V6(_) => IPV6_HEADER_LEN,
+    };
+    let mut segment = packet.payload_mut().to_vec();
+    let Ok(tcp_header_len) = TcpPacket::new_checked(&segment[..]).map(|p| p.header_len() as usize)
+    else {
+        return Ok(packet);
+    };
+
+    f(&mut segment, tcp_header_len);
+
+    let mut buf = packet.into_inner();
+    buf.truncate(ip_header_len);
+    buf.extend_from_slice(&segment);
+    match src_ip {
+        IpAddr::V4(_) => {
+            let mut ip = Ipv4Packet::new_unchecked(&mut buf[..]);
+            ip.set_total_len((ip_header_len + segment.len()) as u16);
+            ip.fill_checksum();
+        }
+        IpAddr::V6(_) => {
+            Ipv6Packet::new_unchecked(&mut buf[..]).set_payload_len(segment.len() as u16);
+        }
+    }
+    TcpPacket::new_unchecked(&mut buf[ip_header_len..])
+        .fill_checksum(&src_ip.into(), &dst_ip.into());
+
+    SmolPacket::try_from(buf)
+}
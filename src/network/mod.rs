@@ -5,6 +5,7 @@ pub use task::NetworkTask;
 mod virtual_device;
 
 mod core;
+mod fast_open;
 mod icmp;
 mod tcp;
 #[cfg(test)]
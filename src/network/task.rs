@@ -8,6 +8,7 @@ use tokio::sync::{
     mpsc::{Permit, Receiver, Sender, UnboundedReceiver},
 };
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
 
 use crate::messages::{NetworkCommand, NetworkEvent, TransportCommand, TransportEvent};
 use crate::network::core::NetworkStack;
@@ -23,11 +24,13 @@ pub struct NetworkTask<'a> {
     io: NetworkStack<'a>,
 }
 
+/// Spawn a [NetworkTask]. If `tcp_fast_open` is set, TCP Fast Open cookies are handed out to clients.
 #[allow(clippy::type_complexity)]
 pub fn add_network_layer(
     transport_events_tx: Sender<TransportEvent>,
     transport_commands_rx: UnboundedReceiver<TransportCommand>,
     shutdown: shutdown::Receiver,
+    tcp_fast_open: bool,
 ) -> (
     JoinHandle<Result<()>>,
     Sender<NetworkEvent>,
@@ -37,13 +40,14 @@ pub fn add_network_layer(
     let (network_events_tx, network_events_rx) = mpsc::channel(256);
     let (network_commands_tx, network_commands_rx) = mpsc::channel(256);
 
-    let task = NetworkTask::new(
+    let mut task = NetworkTask::new(
         network_commands_tx,
         network_events_rx,
         transport_events_tx,
         transport_commands_rx,
         shutdown,
     );
+    task.set_tcp_fast_open(tcp_fast_open);
     let h = tokio::spawn(Box::pin(async move { task.run().await }));
     (h, network_events_tx, network_commands_rx)
 }
@@ -56,7 +60,7 @@ impl NetworkTask<'_> {
         py_rx: UnboundedReceiver<TransportCommand>,
         shutdown: shutdown::Receiver,
     ) -> Self {
-        let io = NetworkStack::new(net_tx.clone());
+        let io = NetworkStack::new(net_tx.clone(), py_tx.clone());
         Self {
             net_tx,
             net_rx,
@@ -67,6 +71,13 @@ impl NetworkTask<'_> {
         }
     }
 
+    /// Advertise TCP Fast Open support to clients by handing out TFO cookies.
+    /// If enabled, data sent along with a SYN is only accepted with a valid cookie,
+    /// otherwise it is always accepted.
+    pub fn set_tcp_fast_open(&mut self, enabled: bool) {
+        self.io.set_tcp_fast_open(enabled);
+    }
+
     pub async fn run(mut self) -> Result<()> {
         let mut py_tx_permit: Option<Permit<TransportEvent>> = None;
         let mut delay: Option<Duration> = None;
@@ -133,11 +144,65 @@ impl NetworkTask<'_> {
             delay = self.io.poll_delay();
         }
 
-        // TODO: process remaining pending data after the shutdown request was received?
+        // release the channel permit, draining needs exclusive access to the task.
+        drop(py_tx_permit);
+        self.drain().await?;
 
         log::debug!("Virtual Network device task shutting down.");
         Ok(())
     }
+
+    /// Process remaining pending data after the shutdown request was received.
+    ///
+    /// New connections are refused, pending transport commands are still handled, and all TCP
+    /// connections are closed once their send buffers have been flushed. We stop once all
+    /// connections are closed or the drain timeout has passed.
+    async fn drain(&mut self) -> Result<()> {
+        let drain_timeout = self.shutdown.drain_timeout();
+        if drain_timeout.is_zero() {
+            return Ok(());
+        }
+        log::debug!("Draining connections for up to {drain_timeout:?} ...");
+        let deadline = Instant::now() + drain_timeout;
+
+        // apply commands that were issued before the shutdown request, e.g. final writes.
+        while let Ok(c) = self.py_rx.try_recv() {
+            self.io.handle_transport_command(c);
+        }
+        self.io.start_draining();
+
+        loop {
+            self.io.poll()?;
+            if self.io.is_drained() {
+                log::debug!("All connections drained.");
+                break;
+            }
+            let delay = self.io.poll_delay();
+            let net_tx_available = self.net_tx.capacity() > 0;
+
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => {
+                    log::debug!("Drain timeout exceeded, discarding remaining data.");
+                    break;
+                },
+                // wait for timeouts when the device is idle
+                _ = async { tokio::time::sleep(delay.unwrap()).await }, if delay.is_some() => {},
+                // process incoming packets for existing connections
+                Some(e) = self.net_rx.recv() => {
+                    self.io.handle_network_event_draining(e)?;
+                },
+                // wait for net_tx capacity...
+                Ok(permit) = self.net_tx.reserve(), if !net_tx_available => {
+                    drop(permit);
+                },
+                // ...or process pending transport commands
+                Some(c) = self.py_rx.recv(), if net_tx_available => {
+                    self.io.handle_transport_command(c);
+                },
+            }
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Debug for NetworkTask<'_> {
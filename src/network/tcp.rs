@@ -1,12 +1,12 @@
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::net::SocketAddr;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
 use std::{cmp, fmt};
 
 use anyhow::Result;
 use pretty_hex::pretty_hex;
 use smoltcp::iface::{Config, SocketSet};
 use smoltcp::socket::{tcp, Socket};
-use smoltcp::wire::{HardwareAddress, Ipv6Address};
+use smoltcp::wire::{HardwareAddress, Ipv6Address, TcpOption};
 use smoltcp::{
     iface::{Interface, SocketHandle},
     time::Instant,
@@ -14,17 +14,21 @@ use smoltcp::{
 };
 use std::time::Duration;
 use tokio::sync::{
-    mpsc::{Permit, Sender},
+    mpsc::{error::TrySendError, Permit, Sender},
     oneshot,
 };
 
 use crate::messages::{
-    ConnectionId, ConnectionIdGenerator, NetworkCommand, SmolPacket, TransportCommand,
-    TransportEvent, TunnelInfo,
+    CloseReason, ConnectionId, ConnectionIdGenerator, NetworkCommand, SmolPacket, TcpSynInfo,
+    TransportCommand, TransportEvent, TunnelInfo,
 };
 
+use super::fast_open::{fast_open_option, strip_syn_payload, CookieGenerator};
 use super::virtual_device::VirtualDevice;
 
+/// Idle time after which smoltcp sends keepalive probes.
+const KEEP_ALIVE_INTERVAL: smoltcp::time::Duration = smoltcp::time::Duration::from_secs(28);
+
 /// Associated data for a smoltcp socket.
 #[derive(Debug)]
 struct SocketData {
@@ -34,11 +38,47 @@ struct SocketData {
     /// we want to send a FIN.
     send_buffer: VecDeque<u8>,
     write_eof: bool,
+    /// Data that was sent along with the SYN (TCP Fast Open).
+    /// It is delivered before anything smoltcp has received.
+    syn_data: Vec<u8>,
+    /// Length of the accepted SYN data, by which the sequence numbers of retransmitted SYNs
+    /// need to be advanced as well.
+    syn_len: usize,
     // Gets notified once there's data to be read.
     recv_waiter: Option<(u32, oneshot::Sender<Vec<u8>>)>,
     // Gets notified once there is enough space in the write buffer.
     drain_waiter: Vec<oneshot::Sender<()>>,
     addr_tuple: (SocketAddr, SocketAddr),
+    // Socket state and send queue length after the last processing round,
+    // used to determine why a connection has been closed.
+    last_state: tcp::State,
+    last_send_queue: usize,
+    /// Whether smoltcp closed the socket because of a RST from the peer.
+    rst_received: bool,
+    /// When we last received a packet from the peer, to tell whether keepalive probes are outstanding.
+    last_received: Instant,
+    /// Whether a ConnectionClosed event has been emitted already.
+    close_reported: bool,
+}
+
+impl SocketData {
+    fn close_reason(&self) -> CloseReason {
+        use tcp::State::*;
+        if self.rst_received {
+            CloseReason::Reset
+        } else if matches!(self.last_state, Closing | LastAck | TimeWait) {
+            CloseReason::Fin
+        } else if self.last_send_queue == 0
+            && matches!(self.last_state, Established | CloseWait)
+            && Instant::now() >= self.last_received + KEEP_ALIVE_INTERVAL
+        {
+            // smoltcp's timeout fired while the connection was idle and we have sent
+            // keepalive probes since the peer's last packet, which were not answered.
+            CloseReason::KeepaliveExpired
+        } else {
+            CloseReason::Timeout
+        }
+    }
 }
 
 pub struct TcpHandler<'a> {
@@ -48,11 +88,16 @@ pub struct TcpHandler<'a> {
     sockets: SocketSet<'a>,
     socket_data: HashMap<ConnectionId, SocketData>,
     remove_conns: Vec<ConnectionId>,
-    active_connections: HashSet<(SocketAddr, SocketAddr)>,
+    active_connections: HashMap<(SocketAddr, SocketAddr), ConnectionId>,
+    py_tx: Sender<TransportEvent>,
+    // ConnectionClosed events that could not be sent yet because the channel was full.
+    close_events: VecDeque<TransportEvent>,
+    // If set, we hand out TCP Fast Open cookies to clients that request them.
+    fast_open: Option<CookieGenerator>,
 }
 
 impl TcpHandler<'_> {
-    pub fn new(net_tx: Sender<NetworkCommand>) -> Self {
+    pub fn new(net_tx: Sender<NetworkCommand>, py_tx: Sender<TransportEvent>) -> Self {
         let mut device = VirtualDevice::new(net_tx);
 
         let config = Config::new(HardwareAddress::Ip);
@@ -82,9 +127,30 @@ impl TcpHandler<'_> {
             device,
             sockets: SocketSet::new(Vec::new()),
             socket_data: HashMap::new(),
-            active_connections: HashSet::new(),
+            active_connections: HashMap::new(),
             connection_id_generator: ConnectionIdGenerator::tcp(),
             remove_conns: Vec::new(),
+            py_tx,
+            close_events: VecDeque::new(),
+            fast_open: None,
+        }
+    }
+
+    /// Advertise TCP Fast Open support to clients by replying to cookie requests.
+    ///
+    /// If enabled, data sent along with a SYN is only accepted if the SYN carries one of our
+    /// cookies. Otherwise, it is always accepted: clients may have received a cookie from the
+    /// server we are intercepting, which we cannot check.
+    pub fn set_fast_open(&mut self, enabled: bool) {
+        self.fast_open = enabled.then(CookieGenerator::default);
+    }
+
+    /// Whether to accept the data of a SYN with the given TFO option.
+    /// Rejected data is acknowledged only partially, so clients send it again after the handshake.
+    fn accepts_syn_data(&self, client: IpAddr, cookie: Option<&[u8]>) -> bool {
+        match &self.fast_open {
+            Some(generator) => cookie == Some(&generator.cookie(client)[..]),
+            None => true,
         }
     }
 
@@ -92,12 +158,13 @@ impl TcpHandler<'_> {
         &mut self,
         mut packet: SmolPacket,
         tunnel_info: TunnelInfo,
-        permit: Permit<'_, TransportEvent>,
+        permit: Option<Permit<'_, TransportEvent>>,
     ) -> Result<()> {
         let src_ip = packet.src_ip();
         let dst_ip = packet.dst_ip();
+        let ttl = packet.hop_limit();
 
-        let tcp_packet = match TcpPacket::new_checked(packet.payload_mut()) {
+        let mut tcp_packet = match TcpPacket::new_checked(packet.payload_mut()) {
             // packet with correct length
             Ok(p) => {
                 // packet with correct checksum
@@ -119,11 +186,75 @@ impl TcpHandler<'_> {
 
         let src_addr = SocketAddr::new(src_ip, tcp_packet.src_port());
         let dst_addr = SocketAddr::new(dst_ip, tcp_packet.dst_port());
+        let is_syn = tcp_packet.syn() && !tcp_packet.ack();
+        let fast_open_cookie = if is_syn {
+            fast_open_option(tcp_packet.options_mut()).map(<[u8]>::to_vec)
+        } else {
+            None
+        };
+        let (mut syn_data, syn_info) = if is_syn {
+            let syn_info = TcpSynInfo {
+                ttl,
+                window: tcp_packet.window_len(),
+                ..parse_syn_options(tcp_packet.options_mut())
+            };
+            (tcp_packet.payload_mut().to_vec(), Some(syn_info))
+        } else {
+            (Vec::new(), None)
+        };
+
+        let is_rst = tcp_packet.rst();
+        if let Some(&connection_id) = self.active_connections.get(&(src_addr, dst_addr)) {
+            let data = self.socket_data.get_mut(&connection_id).unwrap();
+            data.last_received = Instant::now();
+            if is_rst {
+                self.receive_rst(connection_id, packet);
+                return Ok(());
+            }
+        }
+
+        // smoltcp would discard SYN data, so we take it out ourselves. Retransmitted SYNs,
+        // with or without data, need the same sequence number adjustment as the original.
+        let syn_len = if is_syn {
+            let payload_len = syn_data.len();
+            let syn_len = match self.active_connections.get(&(src_addr, dst_addr)) {
+                Some(connection_id) => self.socket_data[connection_id].syn_len,
+                None if self.accepts_syn_data(src_ip, fast_open_cookie.as_deref()) => {
+                    syn_data.len()
+                }
+                None => {
+                    if !syn_data.is_empty() {
+                        log::debug!(
+                            "Ignoring SYN data from {src_addr} without a valid TFO cookie."
+                        );
+                        syn_data.clear();
+                    }
+                    0
+                }
+            };
+            if payload_len > 0 || syn_len > 0 {
+                packet = match strip_syn_payload(packet, syn_len) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        log::debug!("Dropping TCP SYN from {src_addr}: {e}");
+                        return Ok(());
+                    }
+                };
+            }
+            syn_len
+        } else {
+            0
+        };
+
+        if is_syn && !self.active_connections.contains_key(&(src_addr, dst_addr)) {
+            // Without a permit we are shutting down. We don't create a socket,
+            // so smoltcp will reply with a RST.
+            let Some(permit) = permit else {
+                log::debug!("Refusing new TCP connection from {src_addr} during shutdown.");
+                self.device.receive_packet(packet);
+                return Ok(());
+            };
 
-        if tcp_packet.syn()
-            && !tcp_packet.ack()
-            && !self.active_connections.contains(&(src_addr, dst_addr))
-        {
             let mut socket = tcp::Socket::new(
                 tcp::SocketBuffer::new(vec![0u8; 64 * 1024]),
                 tcp::SocketBuffer::new(vec![0u8; 64 * 1024]),
@@ -131,7 +262,7 @@ impl TcpHandler<'_> {
 
             socket.listen(dst_addr)?;
             socket.set_timeout(Some(smoltcp::time::Duration::from_secs(60)));
-            socket.set_keep_alive(Some(smoltcp::time::Duration::from_secs(28)));
+            socket.set_keep_alive(Some(KEEP_ALIVE_INTERVAL));
 
             let handle = self.sockets.add(socket);
 
@@ -141,18 +272,37 @@ impl TcpHandler<'_> {
                 handle,
                 send_buffer: VecDeque::new(),
                 write_eof: false,
+                syn_data,
+                syn_len,
                 recv_waiter: None,
                 drain_waiter: Vec::new(),
                 addr_tuple: (src_addr, dst_addr),
+                last_state: tcp::State::Listen,
+                last_send_queue: 0,
+                rst_received: false,
+                last_received: Instant::now(),
+                close_reported: false,
             };
             self.socket_data.insert(connection_id, data);
-            self.active_connections.insert((src_addr, dst_addr));
+            self.active_connections
+                .insert((src_addr, dst_addr), connection_id);
+
+            if let Some(generator) = self
+                .fast_open
+                .as_ref()
+                .filter(|_| fast_open_cookie.is_some())
+            {
+                self.device
+                    .fast_open_cookies_mut()
+                    .insert((dst_addr, src_addr), generator.cookie(src_ip));
+            }
 
             let event = TransportEvent::ConnectionEstablished {
                 connection_id,
                 src_addr,
                 dst_addr,
                 tunnel_info,
+                tcp_syn: syn_info,
                 command_tx: None,
             };
             permit.send(event);
@@ -162,6 +312,26 @@ impl TcpHandler<'_> {
         Ok(())
     }
 
+    /// Process a RST for an existing connection on its own, so that we can tell whether smoltcp
+    /// accepted it. RSTs outside of the receive window are answered with a challenge ACK instead.
+    fn receive_rst(&mut self, connection_id: ConnectionId, packet: SmolPacket) {
+        // Process everything that came before first, so that it does not affect the state below.
+        self.iface
+            .poll(Instant::now(), &mut self.device, &mut self.sockets);
+        let handle = self.socket_data[&connection_id].handle;
+        let was_closed = self.sockets.get::<tcp::Socket>(handle).state() == tcp::State::Closed;
+
+        self.device.receive_packet(packet);
+        self.iface
+            .poll(Instant::now(), &mut self.device, &mut self.sockets);
+        if !was_closed && self.sockets.get::<tcp::Socket>(handle).state() == tcp::State::Closed {
+            self.socket_data
+                .get_mut(&connection_id)
+                .unwrap()
+                .rst_received = true;
+        }
+    }
+
     pub fn poll_delay(&mut self) -> Option<Duration> {
         self.iface
             .poll_delay(Instant::now(), &self.sockets)
@@ -223,6 +393,23 @@ impl TcpHandler<'_> {
         }
     }
 
+    pub fn start_draining(&mut self) {
+        for data in self.socket_data.values_mut() {
+            data.write_eof = true;
+        }
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.socket_data.values().all(|data| {
+            let socket = self.sockets.get::<tcp::Socket>(data.handle);
+            use tcp::State::*;
+            data.send_buffer.is_empty()
+                && !data.write_eof
+                && socket.send_queue() == 0
+                && matches!(socket.state(), Closed | FinWait2 | TimeWait)
+        })
+    }
+
     pub fn poll(&mut self) -> Result<()> {
         // poll virtual network device
         #[cfg(debug_assertions)]
@@ -249,7 +436,14 @@ impl TcpHandler<'_> {
 
             // receive data over the socket
             if data.recv_waiter.is_some() {
-                if socket.can_recv() {
+                if !data.syn_data.is_empty() {
+                    let (n, tx) = data.recv_waiter.take().unwrap();
+                    let n = cmp::min(data.syn_data.len(), n as usize);
+                    let buf: Vec<u8> = data.syn_data.drain(..n).collect();
+                    if tx.send(buf).is_err() {
+                        log::debug!("Cannot send received data, channel was already closed.");
+                    }
+                } else if socket.can_recv() {
                     let (n, tx) = data.recv_waiter.take().unwrap();
                     let bytes_available = socket.recv_queue();
 
@@ -310,21 +504,100 @@ impl TcpHandler<'_> {
                 data.write_eof = false;
             }
 
+            // Once our FIN has been acknowledged and the peer's FIN has been received,
+            // the connection is closed as far as Python is concerned.
+            // We don't make Python wait for TIME-WAIT to expire.
+            if socket.state() == tcp::State::TimeWait && !data.close_reported {
+                data.close_reported = true;
+                self.close_events
+                    .push_back(TransportEvent::ConnectionClosed {
+                        connection_id: *connection_id,
+                        reason: CloseReason::Fin,
+                    });
+            }
+
             // if socket is closed, mark connection for removal
             if socket.state() == tcp::State::Closed {
                 self.remove_conns.push(*connection_id);
+                if !data.close_reported {
+                    data.close_reported = true;
+                    self.close_events
+                        .push_back(TransportEvent::ConnectionClosed {
+                            connection_id: *connection_id,
+                            reason: data.close_reason(),
+                        });
+                }
             }
+
+            data.last_state = socket.state();
+            data.last_send_queue = socket.send_queue();
         }
 
         for connection_id in self.remove_conns.drain(..) {
             let data = self.socket_data.remove(&connection_id).unwrap();
             self.sockets.remove(data.handle);
             self.active_connections.remove(&data.addr_tuple);
+            let (src_addr, dst_addr) = data.addr_tuple;
+            self.device
+                .fast_open_cookies_mut()
+                .remove(&(dst_addr, src_addr));
+        }
+
+        // notify Python about closed connections
+        while let Some(event) = self.close_events.pop_front() {
+            match self.py_tx.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(event)) => {
+                    self.close_events.push_front(event);
+                    break;
+                }
+                Err(TrySendError::Closed(_)) => {
+                    self.close_events.clear();
+                    break;
+                }
+            }
         }
         Ok(())
     }
 }
 
+/// Record the order and values of the options in a SYN packet.
+fn parse_syn_options(mut options: &[u8]) -> TcpSynInfo {
+    let mut info = TcpSynInfo::default();
+    while !options.is_empty() {
+        let Ok((rest, option)) = TcpOption::parse(options) else {
+            break;
+        };
+        match option {
+            TcpOption::EndOfList => {
+                info.options.push(0);
+                break;
+            }
+            TcpOption::NoOperation => info.options.push(1),
+            TcpOption::MaxSegmentSize(mss) => {
+                info.options.push(2);
+                info.mss = Some(mss);
+            }
+            TcpOption::WindowScale(scale) => {
+                info.options.push(3);
+                info.window_scale = Some(scale);
+            }
+            TcpOption::SackPermitted => {
+                info.options.push(4);
+                info.sack_permitted = true;
+            }
+            TcpOption::SackRange(_) => info.options.push(5),
+            TcpOption::TimeStamp { tsval, tsecr } => {
+                info.options.push(8);
+                info.timestamp = Some((tsval, tsecr));
+            }
+            TcpOption::Unknown { kind, .. } => info.options.push(kind),
+        }
+        options = rest;
+    }
+    info
+}
+
 impl fmt::Debug for TcpHandler<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let sockets: Vec<String> = self
@@ -1,8 +1,11 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
+use super::fast_open::{add_tcp_option, fast_open_option, TFO_OPTION_KIND};
 use super::task::NetworkTask;
 use crate::messages::{
-    NetworkCommand, NetworkEvent, SmolPacket, TransportCommand, TransportEvent, TunnelInfo,
+    CloseReason, NetworkCommand, NetworkEvent, SmolPacket, TransportCommand, TransportEvent,
+    TunnelInfo, WireGuardPeerTunnelInfo,
 };
 use crate::shutdown;
 use anyhow::{anyhow, Result};
@@ -10,7 +13,6 @@ use core::net::Ipv4Addr;
 use core::net::Ipv6Addr;
 use internet_packet::InternetPacket;
 use smoltcp::{phy::ChecksumCapabilities, wire::*};
-use tokio::sync::watch;
 use tokio::{
     sync::{
         mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedSender},
@@ -26,12 +28,16 @@ struct MockNetwork {
     py_to_smol_tx: UnboundedSender<TransportCommand>,
     smol_to_py_rx: Receiver<TransportEvent>,
 
-    sd_trigger: watch::Sender<()>,
+    sd_trigger: shutdown::Sender,
     handle: JoinHandle<Result<()>>,
 }
 
 impl MockNetwork {
     async fn init() -> Result<Self> {
+        Self::init_with(false).await
+    }
+
+    async fn init_with(tcp_fast_open: bool) -> Result<Self> {
         let (wg_to_smol_tx, wg_to_smol_rx) = channel(16);
         let (smol_to_wg_tx, smol_to_wg_rx) = channel(16);
 
@@ -40,13 +46,14 @@ impl MockNetwork {
 
         let (sd_trigger, sd_watcher) = shutdown::channel();
 
-        let task = NetworkTask::new(
+        let mut task = NetworkTask::new(
             smol_to_wg_tx,
             wg_to_smol_rx,
             smol_to_py_tx,
             py_to_smol_rx,
             sd_watcher,
         );
+        task.set_tcp_fast_open(tcp_fast_open);
 
         let handle = tokio::spawn(task.run());
 
@@ -61,7 +68,7 @@ impl MockNetwork {
     }
 
     async fn stop(self) -> Result<()> {
-        self.sd_trigger.send(())?;
+        self.sd_trigger.send()?;
         self.handle.await?
     }
 
@@ -69,6 +76,11 @@ impl MockNetwork {
         let tunnel_info = TunnelInfo::WireGuard {
             src_addr: "192.168.86.134:12345".parse()?,
             dst_addr: "0.0.0.0:0".parse()?,
+            peer: Arc::new(WireGuardPeerTunnelInfo {
+                public_key: String::new(),
+                name: String::new(),
+                endpoints: vec![],
+            }),
         };
         let event = NetworkEvent::ReceivePacket {
             packet,
@@ -197,6 +209,23 @@ fn build_ipv6_tcp_packet(
     ip_packet
 }
 
+/// Parse an IPv4 TCP packet emitted by smoltcp into its control flag, sequence number and payload.
+fn parse_ipv4_tcp_packet(packet: SmolPacket) -> (TcpControl, TcpSeqNumber, Vec<u8>) {
+    let SmolPacket::V4(mut packet) = packet else {
+        panic!("Received unexpected IPv6 packet!");
+    };
+    let src_addr = packet.src_addr();
+    let dst_addr = packet.dst_addr();
+    let repr = TcpRepr::parse(
+        &TcpPacket::new_unchecked(packet.payload_mut()),
+        &src_addr.into(),
+        &dst_addr.into(),
+        &ChecksumCapabilities::default(),
+    )
+    .unwrap();
+    (repr.control, repr.seq_number, repr.payload.to_vec())
+}
+
 fn build_ipv4_udp_packet(
     src_addr: Ipv4Addr,
     dst_addr: Ipv4Addr,
@@ -360,7 +389,10 @@ async fn udp_read_write(
         src_addr: recv_src_addr,
         dst_addr: recv_dst_addr,
         ..
-    } = event;
+    } = event
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
 
     assert_eq!(src_addr, recv_src_addr);
     assert_eq!(dst_addr, recv_dst_addr);
@@ -496,7 +528,10 @@ async fn tcp_ipv4_connection() -> Result<()> {
         src_addr: tcp_src_sock,
         dst_addr: tcp_dst_sock,
         ..
-    } = event;
+    } = event
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
     assert_eq!(IpAddress::Ipv4(src_addr), tcp_src_sock.ip().into());
     assert_eq!(IpAddress::Ipv4(dst_addr), tcp_dst_sock.ip().into());
 
@@ -673,7 +708,10 @@ async fn tcp_ipv6_connection() -> Result<()> {
         src_addr: tcp_src_sock,
         dst_addr: tcp_dst_sock,
         ..
-    } = event;
+    } = event
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
     assert_eq!(IpAddress::Ipv6(src_addr), tcp_src_sock.ip().into());
     assert_eq!(IpAddress::Ipv6(dst_addr), tcp_dst_sock.ip().into());
 
@@ -857,3 +895,621 @@ async fn receive_icmp6_echo() -> Result<()> {
 
     mock.stop().await
 }
+
+#[tokio::test]
+async fn tcp_shutdown_drains_connections() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+    let mut seq = TcpSeqNumber(rand::random::<i32>());
+
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+
+    // establish connection
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Syn,
+        seq,
+        None,
+        &[],
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+    let (control, synack_seq, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::Syn);
+    let mut ack = synack_seq + 1;
+    seq += 1;
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let Some(TransportEvent::ConnectionEstablished { connection_id, .. }) =
+        mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // write data and immediately request a draining shutdown
+    mock.push_py_command(TransportCommand::WriteData(
+        connection_id,
+        b"pending data".to_vec(),
+    ))
+    .await?;
+    mock.sd_trigger
+        .send_with_drain_timeout(std::time::Duration::from_secs(5))?;
+
+    // expect pending data, followed by a FIN
+    let mut received = Vec::new();
+    loop {
+        let (control, seq_number, payload) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+        received.extend_from_slice(&payload);
+        ack = seq_number + payload.len();
+        if control == TcpControl::Fin {
+            ack += 1;
+            break;
+        }
+    }
+    assert_eq!(received, b"pending data");
+
+    // new connections are refused while draining
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1235,
+        31337,
+        TcpControl::Syn,
+        TcpSeqNumber(42),
+        None,
+        &[],
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+    let (control, _, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::Rst);
+
+    // acknowledge the FIN, which completes the drain.
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    tokio::time::timeout(std::time::Duration::from_secs(1), mock.handle).await???;
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_reset_emits_connection_closed() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+    let mut seq = TcpSeqNumber(rand::random::<i32>());
+
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+
+    // establish connection
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Syn,
+        seq,
+        None,
+        &[],
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+    let (control, synack_seq, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::Syn);
+    let ack = synack_seq + 1;
+    seq += 1;
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let Some(TransportEvent::ConnectionEstablished {
+        connection_id: established_id,
+        ..
+    }) = mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // reset connection
+    let tcp_rst = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Rst,
+        seq,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_rst.into()).await?;
+
+    let Some(TransportEvent::ConnectionClosed {
+        connection_id,
+        reason,
+    }) = mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionClosed event");
+    };
+    assert_eq!(connection_id, established_id);
+    assert_eq!(reason, CloseReason::Reset);
+
+    mock.stop().await
+}
+
+#[tokio::test]
+async fn tcp_out_of_window_reset_is_ignored() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+    let mut seq = TcpSeqNumber(rand::random::<i32>());
+
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+
+    // establish connection
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Syn,
+        seq,
+        None,
+        &[],
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+    let (control, synack_seq, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::Syn);
+    let ack = synack_seq + 1;
+    seq += 1;
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let Some(TransportEvent::ConnectionEstablished {
+        connection_id: established_id,
+        ..
+    }) = mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // a RST outside of the receive window is answered with a challenge ACK
+    let tcp_rst = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Rst,
+        seq + 1_000_000,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_rst.into()).await?;
+    let (control, _, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::None);
+
+    // the connection is still usable, and closing it is not reported as a reset
+    let tcp_fin = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Fin,
+        seq,
+        Some(ack),
+        &[],
+    );
+    mock.push_smol_packet(tcp_fin.into()).await?;
+    mock.push_py_command(TransportCommand::CloseConnection(established_id, false))
+        .await?;
+    let fin_seq = loop {
+        let (control, seq_number, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+        if control == TcpControl::Fin {
+            break seq_number;
+        }
+    };
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq + 1,
+        Some(fin_seq + 1),
+        &[],
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let Some(TransportEvent::ConnectionClosed { reason, .. }) = mock.pull_py_event().await else {
+        panic!("expected ConnectionClosed event");
+    };
+    assert_eq!(reason, CloseReason::Fin);
+
+    mock.stop().await
+}
+
+#[tokio::test]
+async fn tcp_fin_emits_connection_closed_before_time_wait() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+    let mut seq = TcpSeqNumber(rand::random::<i32>());
+
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+
+    // establish connection
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Syn,
+        seq,
+        None,
+        &[],
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+    let (control, synack_seq, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::Syn);
+    seq += 1;
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq,
+        Some(synack_seq + 1),
+        &[],
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let Some(TransportEvent::ConnectionEstablished {
+        connection_id: established_id,
+        ..
+    }) = mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // close connection and answer our FIN with FIN/ACK
+    mock.push_py_command(TransportCommand::CloseConnection(established_id, false))
+        .await?;
+    let fin_seq = loop {
+        let (control, seq_number, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+        if control == TcpControl::Fin {
+            break seq_number;
+        }
+    };
+    let tcp_fin = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Fin,
+        seq,
+        Some(fin_seq + 1),
+        &[],
+    );
+    mock.push_smol_packet(tcp_fin.into()).await?;
+
+    // the socket is in TIME-WAIT now, which must not delay the close event.
+    let Some(TransportEvent::ConnectionClosed {
+        connection_id,
+        reason,
+    }) = tokio::time::timeout(std::time::Duration::from_secs(1), mock.pull_py_event()).await?
+    else {
+        panic!("expected ConnectionClosed event");
+    };
+    assert_eq!(connection_id, established_id);
+    assert_eq!(reason, CloseReason::Fin);
+
+    mock.stop().await
+}
+
+#[tokio::test]
+async fn tcp_syn_data() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+    let seq = TcpSeqNumber(rand::random::<i32>());
+
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+
+    // send TCP SYN with data
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Syn,
+        seq,
+        None,
+        b"hello",
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+
+    // expect the SYN/ACK to acknowledge the data
+    let SmolPacket::V4(mut synack) = mock.pull_smol_packet().await else {
+        panic!("Received unexpected IPv6 packet!");
+    };
+    let synack_src_addr = synack.src_addr();
+    let synack_dst_addr = synack.dst_addr();
+    let synack_repr = TcpRepr::parse(
+        &TcpPacket::new_unchecked(synack.payload_mut()),
+        &synack_src_addr.into(),
+        &synack_dst_addr.into(),
+        &ChecksumCapabilities::default(),
+    )
+    .unwrap();
+    assert_eq!(synack_repr.control, TcpControl::Syn);
+    assert_eq!(synack_repr.ack_number.unwrap(), seq + 1 + 5);
+
+    let Some(TransportEvent::ConnectionEstablished { connection_id, .. }) =
+        mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // SYN data is delivered first...
+    let (tx, rx) = oneshot::channel();
+    mock.push_py_command(TransportCommand::ReadData(connection_id, 4096, tx))
+        .await?;
+    assert_eq!(rx.await?, b"hello");
+
+    // ...followed by data sent after the handshake.
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq + 1 + 5,
+        Some(synack_repr.seq_number + 1),
+        b" world",
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let (tx, rx) = oneshot::channel();
+    mock.push_py_command(TransportCommand::ReadData(connection_id, 4096, tx))
+        .await?;
+    assert_eq!(rx.await?, b" world");
+
+    mock.stop().await
+}
+
+#[tokio::test]
+async fn tcp_syn_data_retransmitted_syn() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+    let seq = TcpSeqNumber(rand::random::<i32>());
+
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+    let syn = |payload: &[u8]| {
+        build_ipv4_tcp_packet(
+            src_addr,
+            dst_addr,
+            1234,
+            31337,
+            TcpControl::Syn,
+            seq,
+            None,
+            payload,
+        )
+    };
+
+    mock.push_smol_packet(syn(b"hello").into()).await?;
+    let (control, synack_seq, _) = parse_ipv4_tcp_packet(mock.pull_smol_packet().await);
+    assert_eq!(control, TcpControl::Syn);
+    let Some(TransportEvent::ConnectionEstablished { connection_id, .. }) =
+        mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // a retransmission without data must neither open a new connection
+    // nor change which sequence number the handshake is completed with.
+    mock.push_smol_packet(syn(b"").into()).await?;
+    let tcp_ack = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::None,
+        seq + 1 + 5,
+        Some(synack_seq + 1),
+        b" world",
+    );
+    mock.push_smol_packet(tcp_ack.into()).await?;
+
+    let (tx, rx) = oneshot::channel();
+    mock.push_py_command(TransportCommand::ReadData(connection_id, 4096, tx))
+        .await?;
+    assert_eq!(rx.await?, b"hello");
+    let (tx, rx) = oneshot::channel();
+    mock.push_py_command(TransportCommand::ReadData(connection_id, 4096, tx))
+        .await?;
+    assert_eq!(rx.await?, b" world");
+
+    mock.stop().await
+}
+
+#[tokio::test]
+async fn tcp_fast_open_cookie_validation() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init_with(true).await?;
+    let src_addr = "10.0.0.1".parse()?;
+    let dst_addr = "10.0.0.42".parse()?;
+
+    // request a cookie...
+    let tcp_syn = build_ipv4_tcp_packet(
+        src_addr,
+        dst_addr,
+        1234,
+        31337,
+        TcpControl::Syn,
+        TcpSeqNumber(rand::random::<i32>()),
+        None,
+        &[],
+    );
+    mock.push_smol_packet(add_tcp_option(tcp_syn.into(), &[TFO_OPTION_KIND, 2])?)
+        .await?;
+    let SmolPacket::V4(mut synack) = mock.pull_smol_packet().await else {
+        panic!("Received unexpected IPv6 packet!");
+    };
+    let mut tcp_synack = TcpPacket::new_checked(synack.payload_mut())?;
+    let cookie = fast_open_option(tcp_synack.options_mut()).unwrap().to_vec();
+    let Some(TransportEvent::ConnectionEstablished { .. }) = mock.pull_py_event().await else {
+        panic!("expected ConnectionEstablished event");
+    };
+
+    // ...and use it for new connections. Data with an invalid cookie is not acknowledged,
+    // so that the client sends it again after the handshake.
+    for (src_port, cookie, accepted) in [(1235, cookie, true), (1236, vec![0; 8], false)] {
+        let seq = TcpSeqNumber(rand::random::<i32>());
+        let tcp_syn = build_ipv4_tcp_packet(
+            src_addr,
+            dst_addr,
+            src_port,
+            31337,
+            TcpControl::Syn,
+            seq,
+            None,
+            b"hello",
+        );
+        let mut option = vec![TFO_OPTION_KIND, 2 + cookie.len() as u8];
+        option.extend(cookie);
+        mock.push_smol_packet(add_tcp_option(tcp_syn.into(), &option)?)
+            .await?;
+
+        let SmolPacket::V4(mut synack) = mock.pull_smol_packet().await else {
+            panic!("Received unexpected IPv6 packet!");
+        };
+        let tcp_synack = TcpPacket::new_checked(synack.payload_mut())?;
+        let expected = if accepted { seq + 1 + 5 } else { seq + 1 };
+        assert_eq!(tcp_synack.ack_number(), expected);
+        let Some(TransportEvent::ConnectionEstablished { .. }) = mock.pull_py_event().await else {
+            panic!("expected ConnectionEstablished event");
+        };
+    }
+
+    mock.stop().await
+}
+
+#[tokio::test]
+async fn tcp_fast_open_cookie() -> Result<()> {
+    init_logger();
+    for tcp_fast_open in [false, true] {
+        let mut mock = MockNetwork::init_with(tcp_fast_open).await?;
+
+        // send TCP SYN with a TFO cookie request
+        let tcp_syn = build_ipv4_tcp_packet(
+            "10.0.0.1".parse()?,
+            "10.0.0.42".parse()?,
+            1234,
+            31337,
+            TcpControl::Syn,
+            TcpSeqNumber(rand::random::<i32>()),
+            None,
+            &[],
+        );
+        let tcp_syn = add_tcp_option(tcp_syn.into(), &[TFO_OPTION_KIND, 2])?;
+        mock.push_smol_packet(tcp_syn).await?;
+
+        // expect a cookie in the SYN/ACK if TFO is enabled
+        let SmolPacket::V4(mut synack) = mock.pull_smol_packet().await else {
+            panic!("Received unexpected IPv6 packet!");
+        };
+        assert!(synack.verify_checksum());
+        let mut tcp_synack = TcpPacket::new_checked(synack.payload_mut())?;
+        assert!(tcp_synack.syn() && tcp_synack.ack());
+        let cookie = fast_open_option(tcp_synack.options_mut()).map(<[u8]>::to_vec);
+        if tcp_fast_open {
+            assert_eq!(cookie.map(|c| c.len()), Some(8));
+        } else {
+            assert_eq!(cookie, None);
+        }
+
+        mock.stop().await?;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn tcp_syn_info() -> Result<()> {
+    init_logger();
+    let mut mock = MockNetwork::init().await?;
+
+    let tcp_syn = build_ipv4_tcp_packet(
+        "10.0.0.1".parse()?,
+        "10.0.0.42".parse()?,
+        1234,
+        31337,
+        TcpControl::Syn,
+        TcpSeqNumber(rand::random::<i32>()),
+        None,
+        &[],
+    );
+    mock.push_smol_packet(tcp_syn.into()).await?;
+
+    let Some(TransportEvent::ConnectionEstablished {
+        tcp_syn: Some(syn_info),
+        ..
+    }) = mock.pull_py_event().await
+    else {
+        panic!("expected ConnectionEstablished event with SYN info");
+    };
+    assert_eq!(syn_info.ttl, 255);
+    assert_eq!(syn_info.window, 64240);
+    assert_eq!(syn_info.mss, Some(1380));
+    assert_eq!(syn_info.window_scale, Some(8));
+    assert!(syn_info.sack_permitted);
+    assert_eq!(syn_info.timestamp, None);
+    assert_eq!(
+        syn_info
+            .options
+            .iter()
+            .filter(|&&kind| kind > 1)
+            .collect::<Vec<_>>(),
+        [&2, &3, &4]
+    );
+
+    mock.stop().await
+}
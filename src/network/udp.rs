@@ -1,13 +1,15 @@
 use std::collections::VecDeque;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use lru_time_cache::LruCache;
-use tokio::sync::mpsc::Permit;
+use lru_time_cache::{LruCache, TimedEntry};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Permit, Sender};
 use tokio::sync::oneshot;
 
 use crate::messages::{
-    ConnectionId, ConnectionIdGenerator, SmolPacket, TransportCommand, TransportEvent, TunnelInfo,
+    CloseReason, ConnectionId, ConnectionIdGenerator, SmolPacket, TransportCommand, TransportEvent,
+    TunnelInfo,
 };
 use internet_packet::InternetPacket;
 use smoltcp::phy::ChecksumCapabilities;
@@ -58,6 +60,8 @@ impl ConnectionState {
 }
 
 pub const UDP_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often we check for idle connections. Connections are closed up to this much after UDP_TIMEOUT.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
 
 type FourTuple = (SocketAddr, SocketAddr);
 
@@ -65,16 +69,24 @@ pub struct UdpHandler {
     connection_id_generator: ConnectionIdGenerator,
     id_lookup: LruCache<FourTuple, ConnectionId>,
     connections: LruCache<ConnectionId, (ConnectionState, FourTuple)>,
+    py_tx: Sender<TransportEvent>,
+    // ConnectionClosed events that could not be sent yet because the channel was full.
+    close_events: VecDeque<TransportEvent>,
+    // Deadline for the next check for expired connections, which is when we need to be polled.
+    next_expiry_check: Instant,
 }
 
 impl UdpHandler {
-    pub fn new() -> Self {
+    pub fn new(py_tx: Sender<TransportEvent>) -> Self {
         // This implementation is largely based on the fact that LruCache eventually
         // drops the state, which closes the respective channels.
         Self {
             connections: LruCache::with_expiry_duration(UDP_TIMEOUT),
             id_lookup: LruCache::with_expiry_duration(UDP_TIMEOUT),
             connection_id_generator: ConnectionIdGenerator::udp(),
+            py_tx,
+            close_events: VecDeque::new(),
+            next_expiry_check: Instant::now() + EXPIRY_CHECK_INTERVAL,
         }
     }
 
@@ -160,24 +172,57 @@ impl UdpHandler {
                     src_addr: packet.src_addr,
                     dst_addr: packet.dst_addr,
                     tunnel_info,
+                    tcp_syn: None,
                     command_tx: None,
                 });
             }
         };
     }
 
+    /// Time until the next check for expired connections, if there is anything to check or to send.
     pub fn poll_delay(&mut self) -> Option<Duration> {
-        if self.connections.is_empty() {
+        if self.connections.is_empty() && self.close_events.is_empty() {
             None
         } else {
-            Some(Duration::from_secs(5))
+            Some(
+                self.next_expiry_check
+                    .saturating_duration_since(Instant::now()),
+            )
         }
     }
 
     pub fn poll(&mut self) {
+        let now = Instant::now();
+        if now >= self.next_expiry_check {
+            self.next_expiry_check = now + EXPIRY_CHECK_INTERVAL;
+        }
+
         // Creating an iterator removes expired entries.
-        self.connections.iter();
+        for entry in self.connections.notify_iter() {
+            if let TimedEntry::Expired(connection_id, _) = entry {
+                self.close_events
+                    .push_back(TransportEvent::ConnectionClosed {
+                        connection_id,
+                        reason: CloseReason::Timeout,
+                    });
+            }
+        }
         self.id_lookup.iter();
+
+        // notify Python about closed connections
+        while let Some(event) = self.close_events.pop_front() {
+            match self.py_tx.try_send(event) {
+                Ok(()) => {}
+                Err(TrySendError::Full(event)) => {
+                    self.close_events.push_front(event);
+                    break;
+                }
+                Err(TrySendError::Closed(_)) => {
+                    self.close_events.clear();
+                    break;
+                }
+            }
+        }
     }
 }
 
@@ -311,6 +356,30 @@ mod tests {
         assert!(rx.blocking_recv().is_err());
     }
 
+    #[test]
+    fn test_poll_delay() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let mut handler = UdpHandler::new(tx.clone());
+        assert_eq!(handler.poll_delay(), None);
+
+        handler.receive_data(
+            UdpPacket {
+                src_addr: "10.0.0.1:1234".parse().unwrap(),
+                dst_addr: "10.0.0.2:53".parse().unwrap(),
+                payload: vec![1, 2, 3],
+            },
+            TunnelInfo::None {},
+            tx.try_reserve().unwrap(),
+        );
+        handler.poll();
+
+        // the deadline does not move when we are polled again before it has passed.
+        let delay = handler.poll_delay().unwrap();
+        assert!(delay <= EXPIRY_CHECK_INTERVAL);
+        handler.poll();
+        assert!(handler.poll_delay().unwrap() <= delay);
+    }
+
     #[tokio::test]
     async fn test_udp_server_echo() -> anyhow::Result<()> {
         let (commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
@@ -355,7 +424,7 @@ mod tests {
         commands_tx.send(TransportCommand::ReadData(connection_id, 0, data_tx))?;
         assert!(data_rx.await.is_err());
 
-        shutdown_tx.send(())?;
+        shutdown_tx.send()?;
         handle.await??;
 
         Ok(())
@@ -7,12 +7,15 @@ use smoltcp::{
 use tokio::sync::mpsc::{Permit, Sender};
 
 use crate::messages::{NetworkCommand, SmolPacket};
+use crate::network::fast_open::{add_fast_open_cookie, FastOpenCookies};
 
 /// A virtual smoltcp device into which we manually feed packets using
 /// [VirtualDevice::receive_packet] and which send outgoing packets to a channel.
 pub struct VirtualDevice {
     rx_buffer: VecDeque<Vec<u8>>,
     tx_channel: Sender<NetworkCommand>,
+    /// TCP Fast Open cookies that are added to outgoing SYN/ACKs.
+    fast_open_cookies: FastOpenCookies,
 }
 
 impl VirtualDevice {
@@ -20,12 +23,17 @@ impl VirtualDevice {
         VirtualDevice {
             rx_buffer: VecDeque::new(),
             tx_channel,
+            fast_open_cookies: FastOpenCookies::new(),
         }
     }
 
     pub fn receive_packet(&mut self, packet: SmolPacket) {
         self.rx_buffer.push_back(packet.into_inner());
     }
+
+    pub fn fast_open_cookies_mut(&mut self) -> &mut FastOpenCookies {
+        &mut self.fast_open_cookies
+    }
 }
 
 impl Device for VirtualDevice {
@@ -46,7 +54,10 @@ impl Device for VirtualDevice {
         if let Ok(permit) = self.tx_channel.try_reserve() {
             if let Some(buffer) = self.rx_buffer.pop_front() {
                 let rx = Self::RxToken { buffer };
-                let tx = VirtualTxToken { permit };
+                let tx = VirtualTxToken {
+                    permit,
+                    fast_open_cookies: &self.fast_open_cookies,
+                };
                 return Some((rx, tx));
             }
         }
@@ -56,7 +67,10 @@ impl Device for VirtualDevice {
 
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
         match self.tx_channel.try_reserve() {
-            Ok(permit) => Some(VirtualTxToken { permit }),
+            Ok(permit) => Some(VirtualTxToken {
+                permit,
+                fast_open_cookies: &self.fast_open_cookies,
+            }),
             Err(_) => None,
         }
     }
@@ -71,6 +85,7 @@ impl Device for VirtualDevice {
 
 pub struct VirtualTxToken<'a> {
     permit: Permit<'a, NetworkCommand>,
+    fast_open_cookies: &'a FastOpenCookies,
 }
 
 impl TxToken for VirtualTxToken<'_> {
@@ -82,9 +97,10 @@ impl TxToken for VirtualTxToken<'_> {
         let result = f(&mut buffer);
 
         match SmolPacket::try_from(buffer) {
-            Ok(packet) => {
-                self.permit.send(NetworkCommand::SendPacket(packet));
-            }
+            Ok(packet) => match add_fast_open_cookie(packet, self.fast_open_cookies) {
+                Ok(packet) => self.permit.send(NetworkCommand::SendPacket(packet)),
+                Err(err) => log::error!("Dropping packet, failed to add TFO cookie: {err:?}"),
+            },
             Err(err) => {
                 log::error!("Failed to parse packet from smol: {err:?}")
             }
@@ -24,6 +24,7 @@ use tokio::time::timeout;
 async fn start_redirector(
     executable: &Path,
     listener_addr: &Path,
+    cgroup: Option<&str>,
     shutdown: shutdown::Receiver,
 ) -> Result<PathBuf> {
     debug!("Elevating privileges...");
@@ -46,6 +47,7 @@ async fn start_redirector(
         .arg("--preserve-env")
         .arg(executable)
         .arg(listener_addr)
+        .args(cgroup)
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -113,6 +115,10 @@ async fn start_redirector(
 
 pub struct LinuxConf {
     pub executable_path: PathBuf,
+    /// Only intercept processes in this cgroup subtree (relative to the cgroup2 mount).
+    pub cgroup: Option<String>,
+    /// Hand out TCP Fast Open cookies to intercepted processes.
+    pub tcp_fast_open: bool,
 }
 
 // We implement AsyncRead/AsyncWrite for UnixDatagram to have a common interface
@@ -169,8 +175,13 @@ impl PacketSourceConf for LinuxConf {
         let datagram_dir = tempdir().context("failed to create temp dir")?;
 
         let channel = UnixDatagram::bind(datagram_dir.path().join("mitmproxy"))?;
-        let dst =
-            start_redirector(&self.executable_path, datagram_dir.path(), shutdown.clone()).await?;
+        let dst = start_redirector(
+            &self.executable_path,
+            datagram_dir.path(),
+            self.cgroup.as_deref(),
+            shutdown.clone(),
+        )
+        .await?;
 
         channel
             .connect(&dst)
@@ -186,6 +197,7 @@ impl PacketSourceConf for LinuxConf {
                 transport_commands_rx,
                 conf_rx,
                 shutdown,
+                tcp_fast_open: self.tcp_fast_open,
             },
             conf_tx,
         ))
@@ -199,6 +211,7 @@ pub struct LinuxTask {
     transport_commands_rx: UnboundedReceiver<TransportCommand>,
     conf_rx: UnboundedReceiver<InterceptConf>,
     shutdown: shutdown::Receiver,
+    tcp_fast_open: bool,
 }
 
 impl PacketSourceTask for LinuxTask {
@@ -209,6 +222,7 @@ impl PacketSourceTask for LinuxTask {
             self.transport_commands_rx,
             self.conf_rx,
             self.shutdown,
+            self.tcp_fast_open,
         )
         .await?;
         drop(self.datagram_dir);
@@ -1,6 +1,8 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-use crate::messages::{ConnectionIdGenerator, TransportCommand, TransportEvent, TunnelInfo};
+use crate::messages::{
+    CloseReason, ConnectionId, ConnectionIdGenerator, TransportCommand, TransportEvent, TunnelInfo,
+};
 
 use crate::intercept_conf::InterceptConf;
 use crate::ipc;
@@ -16,6 +18,7 @@ use prost::bytes::BytesMut;
 use prost::Message;
 
 use std::process::Stdio;
+use std::sync::{Arc, Mutex};
 
 use std::time::Duration;
 
@@ -100,6 +103,8 @@ impl PacketSourceConf for MacosConf {
                 control_channel,
                 listener,
                 connections: JoinSet::new(),
+                tcp_ids: Arc::new(Mutex::new(ConnectionIdGenerator::tcp())),
+                udp_ids: Arc::new(Mutex::new(ConnectionIdGenerator::udp())),
                 transport_events_tx,
                 conf_rx,
                 shutdown,
@@ -113,6 +118,9 @@ pub struct MacOsTask {
     control_channel: UnixStream,
     listener: UnixListener,
     connections: JoinSet<Result<()>>,
+    /// Connection IDs are shared by all connection tasks, so that they are unique.
+    tcp_ids: Arc<Mutex<ConnectionIdGenerator>>,
+    udp_ids: Arc<Mutex<ConnectionIdGenerator>>,
     transport_events_tx: Sender<TransportEvent>,
     conf_rx: UnboundedReceiver<InterceptConf>,
     shutdown: shutdown::Receiver,
@@ -142,6 +150,8 @@ impl PacketSourceTask for MacOsTask {
                         Ok((stream, _)) => {
                             let task = ConnectionTask::new(
                                 stream,
+                                self.tcp_ids.clone(),
+                                self.udp_ids.clone(),
                                 self.transport_events_tx.clone(),
                                 self.shutdown.clone(),
                             );
@@ -165,6 +175,8 @@ impl PacketSourceTask for MacOsTask {
 
 struct ConnectionTask {
     stream: UnixStream,
+    tcp_ids: Arc<Mutex<ConnectionIdGenerator>>,
+    udp_ids: Arc<Mutex<ConnectionIdGenerator>>,
     events: Sender<TransportEvent>,
     shutdown: shutdown::Receiver,
 }
@@ -172,11 +184,15 @@ struct ConnectionTask {
 impl ConnectionTask {
     pub fn new(
         stream: UnixStream,
+        tcp_ids: Arc<Mutex<ConnectionIdGenerator>>,
+        udp_ids: Arc<Mutex<ConnectionIdGenerator>>,
         events: Sender<TransportEvent>,
         shutdown: shutdown::Receiver,
     ) -> Self {
         Self {
             stream,
+            tcp_ids,
+            udp_ids,
             events,
             shutdown,
         }
@@ -196,24 +212,33 @@ impl ConnectionTask {
             NewFlow::decode(buf.as_slice()).context("Invalid handshake IPC")?
         };
 
+        let events = self.events.clone();
         match new_flow {
             NewFlow {
                 message: Some(ipc::new_flow::Message::Tcp(tcp_flow)),
-            } => self
-                .handle_tcp(tcp_flow)
-                .await
-                .context("failed to handle TCP stream"),
+            } => {
+                let connection_id = self.tcp_ids.lock().unwrap().next_id();
+                let result = self.handle_tcp(connection_id, tcp_flow).await;
+                send_connection_closed(&events, connection_id, &result).await;
+                result.map(drop).context("failed to handle TCP stream")
+            }
             NewFlow {
                 message: Some(ipc::new_flow::Message::Udp(udp_flow)),
-            } => self
-                .handle_udp(udp_flow)
-                .await
-                .context("failed to handle UDP stream"),
+            } => {
+                let connection_id = self.udp_ids.lock().unwrap().next_id();
+                let result = self.handle_udp(connection_id, udp_flow).await;
+                send_connection_closed(&events, connection_id, &result).await;
+                result.map(drop).context("failed to handle UDP stream")
+            }
             _ => bail!("Received invalid IPC message: {:?}", new_flow),
         }
     }
 
-    async fn handle_udp(mut self, flow: UdpFlow) -> Result<()> {
+    async fn handle_udp(
+        mut self,
+        connection_id: ConnectionId,
+        flow: UdpFlow,
+    ) -> Result<CloseReason> {
         // For UDP connections, we pass length-delimited protobuf messages over the unix socket
         // in both directions.
         let mut write_buf = BytesMut::new();
@@ -243,9 +268,9 @@ impl ConnectionTask {
 
         let mut state = ConnectionState::default();
 
-        loop {
+        let reason = loop {
             tokio::select! {
-                _ = self.shutdown.recv() => break,
+                _ = self.shutdown.recv() => break CloseReason::Shutdown,
                 Some(packet) = stream.next(), if state.packet_queue_len() < 10 => {
                     let packet = ipc::UdpPacket::decode(
                         packet.context("IPC read error")?
@@ -259,10 +284,11 @@ impl ConnectionTask {
                     if let Some((tunnel_info, local_address, command_tx)) = first_packet.take() {
                         remote_address = dst_addr;
                         self.events.send(TransportEvent::ConnectionEstablished {
-                            connection_id: ConnectionIdGenerator::udp().next_id(),
+                            connection_id,
                             src_addr: local_address,
                             dst_addr,
                             tunnel_info,
+                            tcp_syn: None,
                             command_tx: Some(command_tx),
                         }).await?;
                     } else if remote_address != dst_addr {
@@ -293,18 +319,22 @@ impl ConnectionTask {
                         TransportCommand::CloseConnection(_, half_close) => {
                             if !half_close {
                                 state.close();
-                                break;
+                                break CloseReason::Fin;
                             }
                         }
                     }
                 }
             }
-        }
+        };
 
-        Ok(())
+        Ok(reason)
     }
 
-    async fn handle_tcp(mut self, flow: TcpFlow) -> Result<()> {
+    async fn handle_tcp(
+        mut self,
+        connection_id: ConnectionId,
+        flow: TcpFlow,
+    ) -> Result<CloseReason> {
         let mut write_buf = BytesMut::new();
         let mut drain_tx: Option<oneshot::Sender<()>> = None;
         let mut read_tx: Option<(usize, oneshot::Sender<Vec<u8>>)> = None;
@@ -323,20 +353,21 @@ impl ConnectionTask {
 
         self.events
             .send(TransportEvent::ConnectionEstablished {
-                connection_id: ConnectionIdGenerator::tcp().next_id(),
+                connection_id,
                 src_addr,
                 dst_addr,
                 tunnel_info,
+                tcp_syn: None,
                 command_tx: Some(command_tx),
             })
             .await?;
 
-        loop {
+        let reason = loop {
             tokio::select! {
-                _ = self.shutdown.recv() => break,
+                _ = self.shutdown.recv() => break CloseReason::Shutdown,
                 Ok(()) = self.stream.writable(), if !write_buf.is_empty() => {
                     let Ok(_) = self.stream.write_buf(&mut write_buf).await else {
-                        break;  // Client has disconnected.
+                        break CloseReason::Reset;  // Client has disconnected.
                     };
                     if write_buf.is_empty() {
                         if let Some(tx) = drain_tx.take() {
@@ -371,13 +402,29 @@ impl ConnectionTask {
                             self.stream.flush().await.ok(); // supposedly this is a no-op on unix sockets.
                             self.stream.shutdown().await.ok();
                             if !half_close {
-                                break;
+                                break CloseReason::Fin;
                             }
                         }
                     }
                 },
             }
-        }
-        Ok(())
+        };
+        Ok(reason)
     }
 }
+
+/// Tell Python that a connection has been closed. Connections that failed count as reset.
+async fn send_connection_closed(
+    events: &Sender<TransportEvent>,
+    connection_id: ConnectionId,
+    result: &Result<CloseReason>,
+) {
+    let reason = *result.as_ref().unwrap_or(&CloseReason::Reset);
+    events
+        .send(TransportEvent::ConnectionClosed {
+            connection_id,
+            reason,
+        })
+        .await
+        .ok();
+}
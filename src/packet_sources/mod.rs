@@ -18,11 +18,16 @@ pub mod linux;
 #[cfg(target_os = "macos")]
 pub mod macos;
 #[cfg(target_os = "linux")]
+pub mod tap;
+#[cfg(target_os = "linux")]
 pub mod tun;
+#[cfg(target_os = "linux")]
+pub mod tun_routing;
 pub mod udp;
 #[cfg(windows)]
 pub mod windows;
 pub mod wireguard;
+pub mod wireguard_client;
 
 pub trait PacketSourceConf {
     type Task: PacketSourceTask + Send + 'static;
@@ -52,10 +57,15 @@ async fn forward_packets<T: AsyncRead + AsyncWrite + Unpin>(
     transport_commands_rx: UnboundedReceiver<TransportCommand>,
     mut conf_rx: UnboundedReceiver<InterceptConf>,
     shutdown: shutdown::Receiver,
+    tcp_fast_open: bool,
 ) -> Result<()> {
     let mut buf = Vec::with_capacity(IPC_BUF_SIZE);
-    let (mut network_task_handle, net_tx, mut net_rx) =
-        add_network_layer(transport_events_tx, transport_commands_rx, shutdown);
+    let (mut network_task_handle, net_tx, mut net_rx) = add_network_layer(
+        transport_events_tx,
+        transport_commands_rx,
+        shutdown,
+        tcp_fast_open,
+    );
 
     loop {
         buf.clear();
@@ -0,0 +1,647 @@
+use crate::messages::{
+    NetworkCommand, NetworkEvent, SmolPacket, TransportCommand, TransportEvent, TunnelInfo,
+};
+use crate::network::add_network_layer;
+use crate::packet_sources::tun_routing::RouteManager;
+use crate::packet_sources::{PacketSourceConf, PacketSourceTask};
+use crate::shutdown;
+use anyhow::{ensure, Context, Result};
+use lru_time_cache::LruCache;
+use smoltcp::phy::ChecksumCapabilities;
+use smoltcp::wire::{
+    ArpOperation, ArpPacket, ArpRepr, EthernetAddress, EthernetFrame, EthernetProtocol,
+    EthernetRepr, Icmpv6Packet, Icmpv6Repr, IpProtocol, Ipv4Packet, Ipv6Packet, Ipv6Repr,
+    NdiscNeighborFlags, NdiscRepr, RawHardwareAddress, ETHERNET_HEADER_LEN,
+};
+use std::collections::VecDeque;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use tokio::sync::mpsc::{Permit, Receiver, Sender, UnboundedReceiver};
+use tun::AbstractDevice;
+
+/// The MAC address we use on the link unless configured otherwise (locally administered).
+pub const DEFAULT_MAC_ADDRESS: EthernetAddress =
+    EthernetAddress([0x02, 0x6d, 0x69, 0x74, 0x6d, 0x00]);
+
+/// Maximum number of hosts on the link whose MAC addresses we remember.
+const MAX_NEIGHBORS: usize = 1024;
+
+/// A TAP (layer-2) interface. In contrast to TUN mode, we see Ethernet frames and act like a
+/// host on the link: ARP requests and IPv6 neighbor solicitations for the gateway are answered
+/// with our MAC address, so that hosts on the link send their traffic to us.
+///
+/// The interface can be bridged with the interfaces of VMs (e.g. a QEMU tap netdev) or
+/// containers (e.g. the host side of a veth pair) to intercept their traffic.
+pub struct TapConf {
+    pub tap_name: Option<String>,
+    /// Attach the interface to this bridge, e.g. `br0`. Requires `gateways`.
+    pub bridge: Option<String>,
+    pub mac_address: EthernetAddress,
+    /// The addresses we answer address resolution for, usually the default gateway of the hosts
+    /// on the link. If empty, we answer for all addresses that have not been seen on the link,
+    /// which is only safe if no other hosts share the link, so it must not be empty with a bridge.
+    pub gateways: Vec<IpAddr>,
+}
+
+impl PacketSourceConf for TapConf {
+    type Task = TapTask;
+    type Data = String;
+
+    fn name(&self) -> &'static str {
+        "TAP interface"
+    }
+
+    async fn build(
+        self,
+        transport_events_tx: Sender<TransportEvent>,
+        transport_commands_rx: UnboundedReceiver<TransportCommand>,
+        shutdown: shutdown::Receiver,
+    ) -> Result<(Self::Task, Self::Data)> {
+        // Without gateways we would answer for every address on the bridge that we haven't seen
+        // yet, and take over traffic between the hosts on it.
+        ensure!(
+            self.bridge.is_none() || !self.gateways.is_empty(),
+            "gateways must be specified when attaching the TAP interface to a bridge"
+        );
+        let (device, tap_name) = create_tap_device(self.tap_name)?;
+        if let Some(bridge) = &self.bridge {
+            RouteManager::new()?
+                .add_to_bridge(&tap_name, bridge)
+                .await?;
+        }
+
+        let (network_task_handle, net_tx, net_rx) =
+            add_network_layer(transport_events_tx, transport_commands_rx, shutdown, false);
+
+        Ok((
+            TapTask {
+                device,
+                adapter: EthernetAdapter::new(self.mac_address, self.gateways),
+                net_tx,
+                net_rx,
+                network_task_handle,
+            },
+            tap_name,
+        ))
+    }
+}
+
+pub fn create_tap_device(tap_name: Option<String>) -> Result<(tun::AsyncDevice, String)> {
+    let mut config = tun::Configuration::default();
+    config.layer(tun::Layer::L2);
+    config.up();
+    if let Some(tap_name) = &tap_name {
+        config.tun_name(tap_name);
+    }
+
+    match tun::create_as_async(&config) {
+        Ok(device) => {
+            let tap_name = device.tun_name().context("Failed to get TAP name")?;
+            Ok((device, tap_name))
+        }
+        Err(tun::Error::Io(e)) if e.kind() == ErrorKind::PermissionDenied => {
+            // Like in TUN mode, we may reuse a pre-configured persistent interface
+            // without CAP_NET_ADMIN, as long as we do not try to bring it up.
+            if let Some(tap_name) = tap_name {
+                let mut config = tun::Configuration::default();
+                config.layer(tun::Layer::L2).tun_name(&tap_name);
+                tun::create_as_async(&config).map(|d| (d, tap_name))
+            } else {
+                Err(tun::Error::Io(e))
+            }
+        }
+        Err(e) => Err(e),
+    }
+    .context("Failed to create TAP device")
+}
+
+/// What to do with an Ethernet frame read from the TAP device.
+#[derive(Debug)]
+pub enum FrameAction {
+    /// Pass the IP packet to the network stack.
+    Receive(SmolPacket),
+    /// Send this frame back to the link, e.g. an ARP reply.
+    Reply(Vec<u8>),
+    Drop,
+}
+
+/// Translates between Ethernet frames and the IP packets our network stack operates on.
+///
+/// We answer address resolution for the configured gateways, and remember the MAC addresses of
+/// the hosts we receive packets from to address our responses.
+pub struct EthernetAdapter {
+    mac_address: EthernetAddress,
+    gateways: Vec<IpAddr>,
+    neighbors: LruCache<IpAddr, EthernetAddress>,
+}
+
+impl EthernetAdapter {
+    pub fn new(mac_address: EthernetAddress, gateways: Vec<IpAddr>) -> Self {
+        Self {
+            mac_address,
+            gateways,
+            neighbors: LruCache::with_capacity(MAX_NEIGHBORS),
+        }
+    }
+
+    /// Whether we answer address resolution for the given address. Without configured gateways,
+    /// we answer for addresses that no host on the link has used, similar to proxy ARP.
+    fn resolves(&self, addr: IpAddr) -> bool {
+        if self.gateways.is_empty() {
+            !self.neighbors.contains_key(&addr)
+        } else {
+            self.gateways.contains(&addr)
+        }
+    }
+
+    pub fn process_frame(&mut self, data: &[u8]) -> FrameAction {
+        let frame = match EthernetFrame::new_checked(data) {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::debug!("Received invalid Ethernet frame: {e}");
+                return FrameAction::Drop;
+            }
+        };
+        let dst_addr = frame.dst_addr();
+        if dst_addr != self.mac_address && dst_addr.is_unicast() {
+            return FrameAction::Drop;
+        }
+
+        let src_addr = frame.src_addr();
+        match frame.ethertype() {
+            EthernetProtocol::Arp => self.process_arp(src_addr, frame.payload()),
+            EthernetProtocol::Ipv4 => match ip_packet(frame.payload()) {
+                Some(packet @ SmolPacket::V4(_)) => {
+                    self.neighbors.insert(packet.src_ip(), src_addr);
+                    FrameAction::Receive(packet)
+                }
+                _ => FrameAction::Drop,
+            },
+            EthernetProtocol::Ipv6 => self.process_ipv6(src_addr, frame.payload()),
+            _ => FrameAction::Drop,
+        }
+    }
+
+    /// Wrap an outgoing IP packet in an Ethernet frame addressed to the host we have seen the
+    /// destination address from, or broadcast it if we have not seen it yet.
+    pub fn wrap_packet(&self, packet: SmolPacket) -> Vec<u8> {
+        let dst_addr = match self.neighbors.peek(&packet.dst_ip()) {
+            Some(addr) => *addr,
+            None => {
+                log::debug!("No MAC address for {}, broadcasting.", packet.dst_ip());
+                EthernetAddress::BROADCAST
+            }
+        };
+        let ethertype = match packet {
+            SmolPacket::V4(_) => EthernetProtocol::Ipv4,
+            SmolPacket::V6(_) => EthernetProtocol::Ipv6,
+        };
+        self.frame(dst_addr, ethertype, &packet.into_inner())
+    }
+
+    fn process_arp(&mut self, src_addr: EthernetAddress, payload: &[u8]) -> FrameAction {
+        let repr = match ArpPacket::new_checked(payload).and_then(|p| ArpRepr::parse(&p)) {
+            Ok(repr) => repr,
+            Err(e) => {
+                log::debug!("Received invalid ARP packet: {e}");
+                return FrameAction::Drop;
+            }
+        };
+        let ArpRepr::EthernetIpv4 {
+            operation,
+            source_hardware_addr,
+            source_protocol_addr,
+            target_protocol_addr,
+            ..
+        } = repr
+        else {
+            return FrameAction::Drop;
+        };
+
+        // ARP probes (RFC 5227) have an unspecified sender address and must not be answered,
+        // otherwise the host would consider its address to be in use.
+        if source_protocol_addr.is_unspecified() {
+            return FrameAction::Drop;
+        }
+        self.neighbors
+            .insert(source_protocol_addr.into(), source_hardware_addr);
+        // Gratuitous ARP announces the sender's own address.
+        if operation != ArpOperation::Request
+            || target_protocol_addr == source_protocol_addr
+            || !self.resolves(target_protocol_addr.into())
+        {
+            return FrameAction::Drop;
+        }
+
+        let reply = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Reply,
+            source_hardware_addr: self.mac_address,
+            source_protocol_addr: target_protocol_addr,
+            target_hardware_addr: source_hardware_addr,
+            target_protocol_addr: source_protocol_addr,
+        };
+        let mut buf = vec![0; reply.buffer_len()];
+        reply.emit(&mut ArpPacket::new_unchecked(&mut buf[..]));
+        FrameAction::Reply(self.frame(src_addr, EthernetProtocol::Arp, &buf))
+    }
+
+    fn process_ipv6(&mut self, src_addr: EthernetAddress, payload: &[u8]) -> FrameAction {
+        let packet = match ip_packet(payload) {
+            Some(SmolPacket::V6(packet)) => packet,
+            _ => return FrameAction::Drop,
+        };
+        let src_ip = packet.src_addr();
+        let dst_ip = packet.dst_addr();
+        if !src_ip.is_unspecified() {
+            self.neighbors.insert(src_ip.into(), src_addr);
+        }
+
+        if packet.next_header() == IpProtocol::Icmpv6 {
+            let payload = Ipv6Packet::new_unchecked(packet.as_ref()).payload();
+            let ndisc = Icmpv6Packet::new_checked(payload)
+                .and_then(|icmp| {
+                    Icmpv6Repr::parse(&src_ip, &dst_ip, &icmp, &ChecksumCapabilities::default())
+                })
+                .ok()
+                .and_then(|repr| match repr {
+                    Icmpv6Repr::Ndisc(ndisc) => Some(ndisc),
+                    _ => None,
+                });
+            match ndisc {
+                // Solicitations for duplicate address detection have an unspecified source
+                // address and must not be answered.
+                Some(NdiscRepr::NeighborSolicit { target_addr, .. })
+                    if !src_ip.is_unspecified()
+                        && target_addr != src_ip
+                        && self.resolves(target_addr.into()) =>
+                {
+                    // Only configured gateways are routers.
+                    let mut flags = NdiscNeighborFlags::SOLICITED | NdiscNeighborFlags::OVERRIDE;
+                    if self.gateways.contains(&target_addr.into()) {
+                        flags |= NdiscNeighborFlags::ROUTER;
+                    }
+                    let advert = Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+                        flags,
+                        target_addr,
+                        lladdr: Some(RawHardwareAddress::from_bytes(self.mac_address.as_bytes())),
+                    });
+                    let ip_repr = Ipv6Repr {
+                        src_addr: target_addr,
+                        dst_addr: src_ip,
+                        next_header: IpProtocol::Icmpv6,
+                        payload_len: advert.buffer_len(),
+                        hop_limit: 255,
+                    };
+                    let mut buf = vec![0; ip_repr.buffer_len() + advert.buffer_len()];
+                    let mut reply = Ipv6Packet::new_unchecked(&mut buf[..]);
+                    ip_repr.emit(&mut reply);
+                    advert.emit(
+                        &target_addr,
+                        &src_ip,
+                        &mut Icmpv6Packet::new_unchecked(reply.payload_mut()),
+                        &ChecksumCapabilities::default(),
+                    );
+                    return FrameAction::Reply(self.frame(src_addr, EthernetProtocol::Ipv6, &buf));
+                }
+                // Other neighbor discovery messages are not meant for the network stack.
+                Some(_) => return FrameAction::Drop,
+                None => {}
+            }
+        }
+
+        FrameAction::Receive(SmolPacket::V6(packet))
+    }
+
+    fn frame(
+        &self,
+        dst_addr: EthernetAddress,
+        ethertype: EthernetProtocol,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let repr = EthernetRepr {
+            src_addr: self.mac_address,
+            dst_addr,
+            ethertype,
+        };
+        let mut buf = vec![0; repr.buffer_len() + payload.len()];
+        let mut frame = EthernetFrame::new_unchecked(&mut buf[..]);
+        repr.emit(&mut frame);
+        frame.payload_mut().copy_from_slice(payload);
+        buf
+    }
+}
+
+/// Parse the IP packet in an Ethernet frame, stripping the padding of short frames.
+fn ip_packet(payload: &[u8]) -> Option<SmolPacket> {
+    let len = match payload.first()? >> 4 {
+        4 => Ipv4Packet::new_checked(payload).ok()?.total_len() as usize,
+        6 => Ipv6Packet::new_checked(payload).ok()?.total_len(),
+        _ => return None,
+    };
+    SmolPacket::try_from(payload[..len].to_vec()).ok()
+}
+
+pub struct TapTask {
+    device: tun::AsyncDevice,
+    adapter: EthernetAdapter,
+
+    net_tx: Sender<NetworkEvent>,
+    net_rx: Receiver<NetworkCommand>,
+    network_task_handle: tokio::task::JoinHandle<Result<()>>,
+}
+
+impl PacketSourceTask for TapTask {
+    async fn run(mut self) -> Result<()> {
+        let size =
+            self.device.mtu()? as usize + ETHERNET_HEADER_LEN + tun::PACKET_INFORMATION_LENGTH;
+        let mut buf = vec![0; size];
+
+        // Frames to write to the device: replies to address resolution and outgoing packets.
+        let mut frames_to_send: VecDeque<Vec<u8>> = VecDeque::new();
+        let mut permit: Option<Permit<NetworkEvent>> = None;
+
+        loop {
+            tokio::select! {
+                // Monitor the network task for errors or planned shutdown.
+                // This way we implicitly monitor the shutdown channel.
+                exit = &mut self.network_task_handle => break exit.context("network task panic")?.context("network task error")?,
+                // wait for transport_events_tx channel capacity...
+                Ok(p) = self.net_tx.reserve(), if permit.is_none() => {
+                    permit = Some(p);
+                },
+                // ... or process incoming frames
+                r = self.device.recv(buf.as_mut_slice()), if permit.is_some() => {
+                    let len = r.context("TAP read() failed")?;
+                    match self.adapter.process_frame(&buf[..len]) {
+                        FrameAction::Receive(packet) => {
+                            permit.take().unwrap().send(NetworkEvent::ReceivePacket {
+                                packet,
+                                tunnel_info: TunnelInfo::None,
+                            });
+                        }
+                        FrameAction::Reply(frame) => frames_to_send.push_back(frame),
+                        FrameAction::Drop => {}
+                    }
+                },
+                // send is cancel safe, so we can use that for backpressure.
+                r = self.device.send(frames_to_send.front().map(Vec::as_slice).unwrap_or_default()), if !frames_to_send.is_empty() => {
+                    let sent = r.context("TAP write() failed")?;
+                    let frame = frames_to_send.pop_front().unwrap();
+                    if sent != frame.len() {
+                        log::debug!("device.send: {} of {} bytes sent.", sent, frame.len());
+                    }
+                },
+                Some(command) = self.net_rx.recv(), if frames_to_send.is_empty() => {
+                    match command {
+                        NetworkCommand::SendPacket(packet) => {
+                            frames_to_send.push_back(self.adapter.wrap_packet(packet));
+                        }
+                    }
+                }
+            }
+        }
+
+        // flush outgoing packet queue before shutdown
+        for frame in frames_to_send {
+            self.device
+                .send(&frame)
+                .await
+                .context("TAP write() failed")?;
+        }
+        while let Some(command) = self.net_rx.recv().await {
+            match command {
+                NetworkCommand::SendPacket(packet) => {
+                    self.device
+                        .send(&self.adapter.wrap_packet(packet))
+                        .await
+                        .context("TAP write() failed")?;
+                }
+            }
+        }
+
+        log::debug!("TAP interface task shutting down.");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::wire::{Ipv4Address, Ipv4Repr, Ipv6Address};
+
+    const HOST_MAC: EthernetAddress = EthernetAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+
+    fn adapter() -> EthernetAdapter {
+        let gateways = vec![
+            Ipv4Address::new(192, 168, 122, 1).into(),
+            Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1).into(),
+        ];
+        EthernetAdapter::new(DEFAULT_MAC_ADDRESS, gateways)
+    }
+
+    fn host_frame(
+        dst_addr: EthernetAddress,
+        ethertype: EthernetProtocol,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut frame = EthernetAdapter::new(HOST_MAC, vec![]).frame(dst_addr, ethertype, payload);
+        // Ethernet frames are padded to at least 60 bytes.
+        frame.resize(frame.len().max(60), 0);
+        frame
+    }
+
+    fn arp_request(source: Ipv4Address, target: Ipv4Address) -> Vec<u8> {
+        let repr = ArpRepr::EthernetIpv4 {
+            operation: ArpOperation::Request,
+            source_hardware_addr: HOST_MAC,
+            source_protocol_addr: source,
+            target_hardware_addr: EthernetAddress([0; 6]),
+            target_protocol_addr: target,
+        };
+        let mut buf = vec![0; repr.buffer_len()];
+        repr.emit(&mut ArpPacket::new_unchecked(&mut buf[..]));
+        host_frame(EthernetAddress::BROADCAST, EthernetProtocol::Arp, &buf)
+    }
+
+    #[test]
+    fn test_arp() {
+        let mut adapter = adapter();
+        let host = Ipv4Address::new(192, 168, 122, 10);
+        let gateway = Ipv4Address::new(192, 168, 122, 1);
+
+        let FrameAction::Reply(reply) = adapter.process_frame(&arp_request(host, gateway)) else {
+            panic!("expected ARP reply");
+        };
+        let frame = EthernetFrame::new_checked(&reply[..]).unwrap();
+        assert_eq!(frame.dst_addr(), HOST_MAC);
+        assert_eq!(frame.src_addr(), DEFAULT_MAC_ADDRESS);
+        assert_eq!(
+            ArpRepr::parse(&ArpPacket::new_checked(frame.payload()).unwrap()).unwrap(),
+            ArpRepr::EthernetIpv4 {
+                operation: ArpOperation::Reply,
+                source_hardware_addr: DEFAULT_MAC_ADDRESS,
+                source_protocol_addr: gateway,
+                target_hardware_addr: HOST_MAC,
+                target_protocol_addr: host,
+            }
+        );
+
+        // probes and announcements are not answered.
+        assert!(matches!(
+            adapter.process_frame(&arp_request(Ipv4Address::UNSPECIFIED, gateway)),
+            FrameAction::Drop
+        ));
+        assert!(matches!(
+            adapter.process_frame(&arp_request(host, host)),
+            FrameAction::Drop
+        ));
+
+        // other hosts on the link answer for themselves.
+        let other = Ipv4Address::new(192, 168, 122, 20);
+        assert!(matches!(
+            adapter.process_frame(&arp_request(host, other)),
+            FrameAction::Drop
+        ));
+    }
+
+    #[test]
+    fn test_arp_without_gateways() {
+        let mut adapter = EthernetAdapter::new(DEFAULT_MAC_ADDRESS, vec![]);
+        let host = Ipv4Address::new(192, 168, 122, 10);
+        let other = Ipv4Address::new(192, 168, 122, 20);
+
+        // addresses that have not been seen on the link are answered...
+        assert!(matches!(
+            adapter.process_frame(&arp_request(host, other)),
+            FrameAction::Reply(_)
+        ));
+        // ... but not once a host uses them.
+        adapter.process_frame(&arp_request(other, host));
+        assert!(matches!(
+            adapter.process_frame(&arp_request(host, other)),
+            FrameAction::Drop
+        ));
+    }
+
+    #[test]
+    fn test_ipv4() {
+        let mut adapter = adapter();
+        let ip_repr = Ipv4Repr {
+            src_addr: Ipv4Address::new(192, 168, 122, 10),
+            dst_addr: Ipv4Address::new(1, 1, 1, 1),
+            next_header: IpProtocol::Udp,
+            payload_len: 0,
+            hop_limit: 64,
+        };
+        let mut buf = vec![0; ip_repr.buffer_len()];
+        ip_repr.emit(
+            &mut Ipv4Packet::new_unchecked(&mut buf[..]),
+            &ChecksumCapabilities::default(),
+        );
+
+        // frames for other hosts are ignored.
+        assert!(matches!(
+            adapter.process_frame(&host_frame(HOST_MAC, EthernetProtocol::Ipv4, &buf)),
+            FrameAction::Drop
+        ));
+        let FrameAction::Receive(packet) = adapter.process_frame(&host_frame(
+            DEFAULT_MAC_ADDRESS,
+            EthernetProtocol::Ipv4,
+            &buf,
+        )) else {
+            panic!("expected IPv4 packet");
+        };
+        // padding is stripped.
+        assert_eq!(packet.clone().into_inner(), buf);
+
+        // responses are addressed to the host we have seen the packet from.
+        let mut response = Ipv4Packet::new_unchecked(buf.clone());
+        response.set_src_addr(ip_repr.dst_addr);
+        response.set_dst_addr(ip_repr.src_addr);
+        response.fill_checksum();
+        let frame = adapter.wrap_packet(SmolPacket::from(response));
+        let frame = EthernetFrame::new_checked(&frame[..]).unwrap();
+        assert_eq!(frame.dst_addr(), HOST_MAC);
+        assert_eq!(frame.ethertype(), EthernetProtocol::Ipv4);
+
+        // unknown hosts get a broadcast.
+        let frame = adapter.wrap_packet(packet);
+        let frame = EthernetFrame::new_checked(&frame[..]).unwrap();
+        assert_eq!(frame.dst_addr(), EthernetAddress::BROADCAST);
+    }
+
+    #[test]
+    fn test_neighbor_solicitation() {
+        let mut adapter = adapter();
+        let host = Ipv6Address::new(0xfe80, 0, 0, 0, 0x5054, 0xff, 0xfe12, 0x3456);
+        let target = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        let solicited_node = Ipv6Address::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 1);
+
+        let solicit = Icmpv6Repr::Ndisc(NdiscRepr::NeighborSolicit {
+            target_addr: target,
+            lladdr: Some(RawHardwareAddress::from_bytes(HOST_MAC.as_bytes())),
+        });
+        let ip_repr = Ipv6Repr {
+            src_addr: host,
+            dst_addr: solicited_node,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: solicit.buffer_len(),
+            hop_limit: 255,
+        };
+        let mut buf = vec![0; ip_repr.buffer_len() + solicit.buffer_len()];
+        let mut packet = Ipv6Packet::new_unchecked(&mut buf[..]);
+        ip_repr.emit(&mut packet);
+        solicit.emit(
+            &host,
+            &solicited_node,
+            &mut Icmpv6Packet::new_unchecked(packet.payload_mut()),
+            &ChecksumCapabilities::default(),
+        );
+        let multicast = EthernetAddress([0x33, 0x33, 0xff, 0x00, 0x00, 0x01]);
+
+        let FrameAction::Reply(reply) =
+            adapter.process_frame(&host_frame(multicast, EthernetProtocol::Ipv6, &buf))
+        else {
+            panic!("expected neighbor advertisement");
+        };
+        let frame = EthernetFrame::new_checked(&reply[..]).unwrap();
+        assert_eq!(frame.dst_addr(), HOST_MAC);
+        let packet = Ipv6Packet::new_checked(frame.payload()).unwrap();
+        assert_eq!(packet.src_addr(), target);
+        assert_eq!(packet.dst_addr(), host);
+        let icmp = Icmpv6Packet::new_checked(packet.payload()).unwrap();
+        let Icmpv6Repr::Ndisc(NdiscRepr::NeighborAdvert {
+            flags,
+            target_addr,
+            lladdr,
+        }) = Icmpv6Repr::parse(&target, &host, &icmp, &ChecksumCapabilities::default()).unwrap()
+        else {
+            panic!("expected neighbor advertisement");
+        };
+        assert_eq!(target_addr, target);
+        assert!(flags.contains(NdiscNeighborFlags::ROUTER));
+        assert_eq!(
+            lladdr,
+            Some(RawHardwareAddress::from_bytes(
+                DEFAULT_MAC_ADDRESS.as_bytes()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bridge_requires_gateways() {
+        let (events_tx, _events_rx) = tokio::sync::mpsc::channel(1);
+        let (_commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+        let conf = TapConf {
+            tap_name: None,
+            bridge: Some("br0".to_string()),
+            mac_address: DEFAULT_MAC_ADDRESS,
+            gateways: vec![],
+        };
+        assert!(conf
+            .build(events_tx, commands_rx, shutdown_rx)
+            .await
+            .is_err());
+    }
+}
@@ -2,17 +2,40 @@ use crate::messages::{
     NetworkCommand, NetworkEvent, SmolPacket, TransportCommand, TransportEvent, TunnelInfo,
 };
 use crate::network::{add_network_layer, MAX_PACKET_SIZE};
+use crate::packet_sources::tun_routing::{RouteManager, RoutingConf, SysctlSnapshot};
 use crate::packet_sources::{PacketSourceConf, PacketSourceTask};
 use crate::shutdown;
 use anyhow::{Context, Result};
+use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
 use std::cmp::max;
+use std::ffi::CStr;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::Arc;
 use std::{fs, io::ErrorKind};
+use tokio::io::unix::AsyncFd;
 use tokio::sync::mpsc::Sender;
-use tokio::sync::mpsc::{Permit, Receiver, UnboundedReceiver};
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+use tokio::task::JoinSet;
 use tun::AbstractDevice;
 
+/// IPv4 address assigned to TUN interfaces.
+pub const IPV4_ADDRESS: Ipv4Cidr = Ipv4Cidr::new(Ipv4Address::new(169, 254, 0, 1), 16);
+/// IPv6 address assigned to TUN interfaces unless configured otherwise.
+pub const DEFAULT_IPV6_ADDRESS: Ipv6Cidr =
+    Ipv6Cidr::new(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), 64);
+
 pub struct TunConf {
     pub tun_name: Option<String>,
+    pub ipv6_address: Ipv6Cidr,
+    /// Number of queues. If larger than one, the device is created with `IFF_MULTI_QUEUE`
+    /// and each queue is read by its own task.
+    pub queues: usize,
+    pub routing: RoutingConf,
+    /// Hand out TCP Fast Open cookies to clients.
+    pub tcp_fast_open: bool,
 }
 
 impl PacketSourceConf for TunConf {
@@ -29,24 +52,62 @@ impl PacketSourceConf for TunConf {
         transport_commands_rx: UnboundedReceiver<TransportCommand>,
         shutdown: shutdown::Receiver,
     ) -> Result<(Self::Task, Self::Data)> {
-        let (device, tun_name) = create_tun_device(self.tun_name)?;
+        let mut route_manager = RouteManager::new()?;
+        let (queues, tun_name) = open_tun_queues(self.tun_name.as_deref(), self.queues.max(1))
+            .context("Failed to create TUN device")?;
 
-        let (network_task_handle, net_tx, net_rx) =
-            add_network_layer(transport_events_tx, transport_commands_rx, shutdown);
+        // IPv6 may be disabled for new interfaces by default.
+        if let Err(e) = route_manager.sysctls.set(
+            format!("/proc/sys/net/ipv6/conf/{tun_name}/disable_ipv6"),
+            "0",
+        ) {
+            log::warn!("Failed to enable IPv6 on {tun_name}: {e}");
+        }
+        let addresses = [IpCidr::Ipv4(IPV4_ADDRESS), IpCidr::Ipv6(self.ipv6_address)];
+        if let Err(e) = route_manager
+            .configure_link(&tun_name, MAX_PACKET_SIZE as u32, &addresses)
+            .await
+        {
+            // As in `create_tun_device`, a persistent interface may be pre-configured,
+            // in which case we do not need CAP_NET_ADMIN.
+            if self.tun_name.is_none() {
+                route_manager.restore().await;
+                return Err(e);
+            }
+            log::warn!("Failed to configure {tun_name}, assuming it is pre-configured: {e:#}");
+        }
+        configure_device(&tun_name, &mut route_manager.sysctls);
+        if let Err(e) = route_manager.install(&tun_name, &self.routing).await {
+            route_manager.restore().await;
+            return Err(e);
+        }
+
+        let (network_task_handle, net_tx, net_rx) = add_network_layer(
+            transport_events_tx,
+            transport_commands_rx,
+            shutdown,
+            self.tcp_fast_open,
+        );
 
         Ok((
             TunTask {
-                device,
+                queues,
                 net_tx,
                 net_rx,
                 network_task_handle,
+                route_manager,
             },
             tun_name,
         ))
     }
 }
 
-pub fn create_tun_device(tun_name: Option<String>) -> Result<(tun::AsyncDevice, String)> {
+/// Create a TUN device. All sysctls that are changed in the process are recorded in `sysctls`,
+/// which restores them once it is dropped.
+pub fn create_tun_device(
+    tun_name: Option<String>,
+    sysctls: &mut SysctlSnapshot,
+) -> Result<(tun::AsyncDevice, String)> {
     let mut config = tun::Configuration::default();
     config.mtu(MAX_PACKET_SIZE as u16);
     // Setting a local address and a destination is required on Linux.
@@ -61,7 +122,7 @@ pub fn create_tun_device(tun_name: Option<String>) -> Result<(tun::AsyncDevice,
     match tun::create_as_async(&config) {
         Ok(device) => {
             let tun_name = device.tun_name().context("Failed to get TUN name")?;
-            configure_device(&tun_name);
+            configure_device(&tun_name, sysctls);
             Ok((device, tun_name))
         }
         Err(tun::Error::Io(e)) if e.kind() == ErrorKind::PermissionDenied => {
@@ -86,11 +147,11 @@ pub fn create_tun_device(tun_name: Option<String>) -> Result<(tun::AsyncDevice,
     .context("Failed to create TUN device")
 }
 
-fn configure_device(tun_name: &str) {
-    if let Err(e) = disable_rp_filter(tun_name) {
+fn configure_device(tun_name: &str, sysctls: &mut SysctlSnapshot) {
+    if let Err(e) = disable_rp_filter(tun_name, sysctls) {
         log::error!("failed to set rp_filter: {e}");
     }
-    if let Err(e) = fs::write(
+    if let Err(e) = sysctls.set(
         format!("/proc/sys/net/ipv4/conf/{tun_name}/route_localnet"),
         "1",
     ) {
@@ -98,7 +159,7 @@ fn configure_device(tun_name: &str) {
     }
     // Update accept_local so that injected packets with a local address (e.g. 127.0.0.1)
     // as source address are accepted.
-    if let Err(e) = fs::write(
+    if let Err(e) = sysctls.set(
         format!("/proc/sys/net/ipv4/conf/{tun_name}/accept_local"),
         "1",
     ) {
@@ -107,72 +168,197 @@ fn configure_device(tun_name: &str) {
 }
 
 pub struct TunTask {
-    device: tun::AsyncDevice,
+    queues: Vec<Arc<TunQueue>>,
 
     net_tx: Sender<NetworkEvent>,
     net_rx: Receiver<NetworkCommand>,
     network_task_handle: tokio::task::JoinHandle<Result<()>>,
+    route_manager: RouteManager,
 }
 
 impl PacketSourceTask for TunTask {
     async fn run(mut self) -> Result<()> {
-        let size = self.device.mtu()? as usize + tun::PACKET_INFORMATION_LENGTH;
-        let mut buf = vec![0; size];
-
-        let mut packet_to_send = Vec::new();
-        let mut permit: Option<Permit<NetworkEvent>> = None;
+        let result = self.process_packets().await;
+        // Remove routes and restore sysctls regardless of how we exited.
+        self.route_manager.restore().await;
+        log::debug!("TUN interface task shutting down.");
+        result
+    }
+}
 
-        // Required on macOS, but currently crashes on Linux with tokio.
-        //let (mut writer, mut reader) = self.device.split().context("failed to split device")?;
+impl TunTask {
+    async fn process_packets(&mut self) -> Result<()> {
+        // Each queue gets its own reader task, so that reads never block writes.
+        let mut readers = JoinSet::new();
+        for queue in &self.queues {
+            readers.spawn(read_packets(queue.clone(), self.net_tx.clone()));
+        }
+        // Outgoing packets are distributed round-robin, the kernel does not care which queue
+        // a packet is written to.
+        let mut next_queue = 0;
 
         loop {
             tokio::select! {
                 // Monitor the network task for errors or planned shutdown.
                 // This way we implicitly monitor the shutdown channel.
                 exit = &mut self.network_task_handle => break exit.context("network task panic")?.context("network task error")?,
-                // wait for transport_events_tx channel capacity...
-                Ok(p) = self.net_tx.reserve(), if permit.is_none() => {
-                    permit = Some(p);
-                },
-                // ... or process incoming packets
-                r = self.device.recv(buf.as_mut_slice()), if permit.is_some() => {
-                    let len = r.context("TUN read() failed")?;
-
-                    let Ok(packet) = SmolPacket::try_from(buf[..len].to_vec()) else {
-                        log::error!("Skipping invalid packet from tun interface: {:?}", &buf[..len]);
-                        continue;
-                    };
-                    permit.take().unwrap().send(NetworkEvent::ReceivePacket {
-                        packet,
-                        tunnel_info: TunnelInfo::None,
-                    });
+                Some(exit) = readers.join_next() => {
+                    exit.context("TUN reader panic")??;
                 },
-                // send_to is cancel safe, so we can use that for backpressure.
-                r = self.device.send(&packet_to_send), if !packet_to_send.is_empty() => {
-                    let sent = r.context("TUN write() failed")?;
-                    if sent != packet_to_send.len() {
-                        log::debug!("device.send: {} of {} bytes sent.", sent, packet_to_send.len());
-                    }
-                    packet_to_send.clear();
-                },
-                Some(command) = self.net_rx.recv(), if packet_to_send.is_empty() => {
+                Some(command) = self.net_rx.recv() => {
                     match command {
                         NetworkCommand::SendPacket(packet) => {
-                            packet_to_send = packet.into_inner();
+                            self.queues[next_queue]
+                                .send(&packet.into_inner())
+                                .await
+                                .context("TUN write() failed")?;
+                            next_queue = (next_queue + 1) % self.queues.len();
                         }
                     }
                 }
             }
         }
-        log::debug!("TUN interface task shutting down.");
+        readers.abort_all();
+
+        // flush outgoing packet queue before shutdown
+        while let Some(command) = self.net_rx.recv().await {
+            match command {
+                NetworkCommand::SendPacket(packet) => {
+                    self.queues[0]
+                        .send(&packet.into_inner())
+                        .await
+                        .context("TUN write() failed")?;
+                }
+            }
+        }
         Ok(())
     }
 }
 
+/// Read packets from a single queue and pass them to the network task.
+async fn read_packets(queue: Arc<TunQueue>, net_tx: Sender<NetworkEvent>) -> Result<()> {
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    loop {
+        // Wait for channel capacity first, so that we apply backpressure to the interface.
+        let Ok(permit) = net_tx.reserve().await else {
+            // The network task has shut down.
+            return Ok(());
+        };
+        let len = queue.recv(&mut buf).await.context("TUN read() failed")?;
+
+        let Ok(packet) = SmolPacket::try_from(buf[..len].to_vec()) else {
+            log::error!(
+                "Skipping invalid packet from tun interface: {:?}",
+                &buf[..len]
+            );
+            continue;
+        };
+        permit.send(NetworkEvent::ReceivePacket {
+            packet,
+            tunnel_info: TunnelInfo::None,
+        });
+    }
+}
+
+/// A single queue of a TUN device, opened without packet information headers.
+/// Reads and writes can happen concurrently.
+pub struct TunQueue(AsyncFd<OwnedFd>);
+
+impl TunQueue {
+    /// Attach to (or create) the TUN device `tun_name`. An empty name lets the kernel pick one.
+    fn open(tun_name: &str, multi_queue: bool) -> io::Result<(Self, String)> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+            .open("/dev/net/tun")?;
+
+        // SAFETY: ifreq is a plain C struct for which all zeroes is a valid value.
+        let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+        if tun_name.len() >= req.ifr_name.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("interface name too long: {tun_name}"),
+            ));
+        }
+        for (dst, src) in req.ifr_name.iter_mut().zip(tun_name.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        let mut flags = libc::IFF_TUN | libc::IFF_NO_PI;
+        if multi_queue {
+            flags |= libc::IFF_MULTI_QUEUE;
+        }
+        req.ifr_ifru.ifru_flags = flags as libc::c_short;
+        // SAFETY: TUNSETIFF reads and writes a single ifreq, which outlives the call.
+        if unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF, &mut req) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: The kernel returns a NUL-terminated name, and we made sure that
+        // the last byte is NUL for the name we passed in.
+        let name = unsafe { CStr::from_ptr(req.ifr_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+
+        Ok((Self(AsyncFd::new(OwnedFd::from(file))?), name))
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.0.readable().await?;
+            // SAFETY: buf is valid for writes of buf.len() bytes.
+            match guard.try_io(|fd| {
+                let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.0.writable().await?;
+            // SAFETY: buf is valid for reads of buf.len() bytes.
+            match guard.try_io(|fd| {
+                let n = unsafe { libc::write(fd.as_raw_fd(), buf.as_ptr().cast(), buf.len()) };
+                if n < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(n as usize)
+                }
+            }) {
+                Ok(result) => return result,
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+/// Open `queues` queues of a TUN device, returning the queues and the device name.
+pub fn open_tun_queues(
+    tun_name: Option<&str>,
+    queues: usize,
+) -> io::Result<(Vec<Arc<TunQueue>>, String)> {
+    let multi_queue = queues > 1;
+    let (first, tun_name) = TunQueue::open(tun_name.unwrap_or_default(), multi_queue)?;
+    let mut all = vec![Arc::new(first)];
+    for _ in 1..queues {
+        let (queue, _) = TunQueue::open(&tun_name, multi_queue)?;
+        all.push(Arc::new(queue));
+    }
+    Ok((all, tun_name))
+}
+
 /// Disable reverse path filtering for our tun interface.
 /// This is necessary so that the kernel does not drop our injected packets.
-fn disable_rp_filter(tun_name: &str) -> Result<()> {
-    fs::write(format!("/proc/sys/net/ipv4/conf/{tun_name}/rp_filter"), "0")
+fn disable_rp_filter(tun_name: &str, sysctls: &mut SysctlSnapshot) -> Result<()> {
+    sysctls
+        .set(format!("/proc/sys/net/ipv4/conf/{tun_name}/rp_filter"), "0")
         .context("failed to disable rp_filter on the interface")?;
 
     // The max value from conf/{all,interface}/rp_filter is used
@@ -182,6 +368,7 @@ fn disable_rp_filter(tun_name: &str) -> Result<()> {
 
     let all_rp_filter = fs::read_to_string("/proc/sys/net/ipv4/conf/all/rp_filter")
         .context("failed to read /proc/sys/net/ipv4/conf/all/rp_filter")?;
+    let all_rp_filter = all_rp_filter.trim();
     if all_rp_filter == "0" {
         return Ok(());
     }
@@ -192,21 +379,19 @@ fn disable_rp_filter(tun_name: &str) -> Result<()> {
         let mut path = dir_entry
             .context("failed to iterate /proc/sys/net/ipv4/conf")?
             .path();
-        if path.ends_with(tun_name) {
+        if path.ends_with(tun_name) || path.ends_with("all") {
             continue;
         }
 
         path.push("rp_filter");
         let interface_rp_filter = fs::read_to_string(&path).unwrap_or_default();
-        let combined = max(&all_rp_filter, &interface_rp_filter);
-        if *combined != interface_rp_filter {
-            fs::write(&path, combined)
-                .with_context(|| format!("failed to set {}", path.display()))?;
-        }
+        let combined = max(all_rp_filter, interface_rp_filter.trim());
+        sysctls.set(path, combined)?;
     }
 
     // We've successfully upgraded all individual interfaces, so we can now downgrade `all`.
-    fs::write("/proc/sys/net/ipv4/conf/all/rp_filter", "0")
+    sysctls
+        .set("/proc/sys/net/ipv4/conf/all/rp_filter", "0")
         .context("failed to disable /proc/sys/net/ipv4/conf/all/rp_filter")?;
     log::debug!("Successfully updated rp_filter.");
     Ok(())
@@ -0,0 +1,344 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use futures_util::TryStreamExt;
+use netlink_packet_route::rule::Nla as RuleNla;
+use netlink_packet_route::{
+    AddressMessage, RouteMessage, RuleMessage, AF_INET, AF_INET6, FR_ACT_TO_TBL, RT_TABLE_MAIN,
+};
+use rtnetlink::Handle;
+use smoltcp::wire::IpCidr;
+
+/// Routing table used for policy routing, unless configured otherwise.
+pub const DEFAULT_ROUTING_TABLE: u32 = 0x6d69;
+/// Priority of our policy rules. Lower values are evaluated first, the main table has 32766.
+const RULE_PRIORITY: u32 = 0x6d69;
+
+/// Routes and policy rules that are installed for a TUN interface and removed when it shuts down.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingConf {
+    /// Destinations that are routed through the interface.
+    pub routes: Vec<IpCidr>,
+    /// Only route packets with this firewall mark through the interface.
+    pub fwmark: Option<u32>,
+    /// Only route packets from sockets owned by these (inclusive) user id ranges.
+    /// This can be used to exclude mitmproxy's own traffic, which would otherwise loop.
+    pub uid_ranges: Vec<(u32, u32)>,
+    /// Routing table for policy routing. Routes are installed into the main table
+    /// if neither `fwmark` nor `uid_ranges` are set.
+    pub table: u32,
+}
+
+impl RoutingConf {
+    fn uses_policy_routing(&self) -> bool {
+        self.fwmark.is_some() || !self.uid_ranges.is_empty()
+    }
+}
+
+/// The original values of all sysctls we have changed, so that they can be restored.
+/// Anything not restored yet is restored when the snapshot is dropped.
+#[derive(Debug, Default)]
+pub struct SysctlSnapshot {
+    original: Vec<(PathBuf, String)>,
+}
+
+impl SysctlSnapshot {
+    /// Set a sysctl via its path in `/proc/sys`, remembering the previous value.
+    pub fn set(&mut self, path: impl Into<PathBuf>, value: &str) -> Result<()> {
+        let path = path.into();
+        let previous = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if previous.trim() == value {
+            return Ok(());
+        }
+        fs::write(&path, value).with_context(|| format!("failed to set {}", path.display()))?;
+        self.original.push((path, previous.trim().to_string()));
+        Ok(())
+    }
+
+    /// Restore all changed sysctls in reverse order.
+    pub fn restore(&mut self) {
+        while let Some((path, value)) = self.original.pop() {
+            if let Err(e) = fs::write(&path, &value) {
+                log::error!("Failed to restore {} to {value}: {e}", path.display());
+            }
+        }
+    }
+}
+
+impl Drop for SysctlSnapshot {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// Addresses, routes and policy rules we have added.
+#[derive(Debug, Default)]
+struct Installed {
+    addresses: Vec<AddressMessage>,
+    routes: Vec<RouteMessage>,
+    rules: Vec<RuleMessage>,
+}
+
+impl Installed {
+    fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.routes.is_empty() && self.rules.is_empty()
+    }
+
+    /// Remove everything in reverse order of installation.
+    async fn remove(&mut self, handle: &Handle) {
+        while let Some(rule) = self.rules.pop() {
+            if let Err(e) = handle.rule().del(rule).execute().await {
+                log::error!("Failed to remove policy rule: {e}");
+            }
+        }
+        while let Some(route) = self.routes.pop() {
+            if let Err(e) = handle.route().del(route).execute().await {
+                log::error!("Failed to remove route: {e}");
+            }
+        }
+        while let Some(address) = self.addresses.pop() {
+            if let Err(e) = handle.address().del(address).execute().await {
+                log::error!("Failed to remove address: {e}");
+            }
+        }
+    }
+}
+
+/// Configures addresses, routes and policy rules via rtnetlink,
+/// and removes them again on [RouteManager::restore].
+///
+/// If the manager is dropped without being restored (e.g. because a task is aborted),
+/// sysctls are restored right away and everything else is removed in a background task.
+pub struct RouteManager {
+    handle: Handle,
+    installed: Installed,
+    pub sysctls: SysctlSnapshot,
+}
+
+impl RouteManager {
+    pub fn new() -> Result<Self> {
+        let (connection, handle, _) =
+            rtnetlink::new_connection().context("failed to open rtnetlink socket")?;
+        tokio::spawn(connection);
+        Ok(Self {
+            handle,
+            installed: Installed::default(),
+            sysctls: SysctlSnapshot::default(),
+        })
+    }
+
+    async fn link_index(&self, tun_name: &str) -> Result<u32> {
+        let link = self
+            .handle
+            .link()
+            .get()
+            .match_name(tun_name.to_string())
+            .execute()
+            .try_next()
+            .await
+            .with_context(|| format!("failed to look up interface {tun_name}"))?
+            .with_context(|| format!("interface {tun_name} does not exist"))?;
+        Ok(link.header.index)
+    }
+
+    /// Set the MTU, assign addresses and bring the interface up.
+    pub async fn configure_link(
+        &mut self,
+        tun_name: &str,
+        mtu: u32,
+        addresses: &[IpCidr],
+    ) -> Result<()> {
+        let index = self.link_index(tun_name).await?;
+        self.handle
+            .link()
+            .set(index)
+            .mtu(mtu)
+            .execute()
+            .await
+            .with_context(|| format!("failed to set MTU of {tun_name}"))?;
+        for cidr in addresses {
+            let mut request =
+                self.handle
+                    .address()
+                    .add(index, cidr.address().into(), cidr.prefix_len());
+            let message = request.message_mut().clone();
+            request
+                .execute()
+                .await
+                .with_context(|| format!("failed to assign {cidr} to {tun_name}"))?;
+            self.installed.addresses.push(message);
+        }
+        self.handle
+            .link()
+            .set(index)
+            .up()
+            .execute()
+            .await
+            .with_context(|| format!("failed to bring up {tun_name}"))?;
+        Ok(())
+    }
+
+    /// Attach an interface to a bridge. This is undone by the kernel when the interface is removed.
+    pub async fn add_to_bridge(&self, name: &str, bridge: &str) -> Result<()> {
+        let index = self.link_index(name).await?;
+        let bridge_index = self.link_index(bridge).await?;
+        self.handle
+            .link()
+            .set(index)
+            .master(bridge_index)
+            .execute()
+            .await
+            .with_context(|| format!("failed to add {name} to bridge {bridge}"))
+    }
+
+    /// Route the configured destinations through the interface.
+    pub async fn install(&mut self, tun_name: &str, conf: &RoutingConf) -> Result<()> {
+        if conf.routes.is_empty() {
+            return Ok(());
+        }
+        let index = self.link_index(tun_name).await?;
+        let table = if conf.uses_policy_routing() {
+            conf.table
+        } else {
+            RT_TABLE_MAIN as u32
+        };
+
+        for cidr in &conf.routes {
+            let result = match cidr {
+                IpCidr::Ipv4(cidr) => {
+                    let mut request = self
+                        .handle
+                        .route()
+                        .add()
+                        .v4()
+                        .destination_prefix(cidr.address(), cidr.prefix_len())
+                        .output_interface(index)
+                        .table_id(table);
+                    let message = request.message_mut().clone();
+                    request.execute().await.map(|_| message)
+                }
+                IpCidr::Ipv6(cidr) => {
+                    let mut request = self
+                        .handle
+                        .route()
+                        .add()
+                        .v6()
+                        .destination_prefix(cidr.address(), cidr.prefix_len())
+                        .output_interface(index)
+                        .table_id(table);
+                    let message = request.message_mut().clone();
+                    request.execute().await.map(|_| message)
+                }
+            };
+            let message = result.with_context(|| format!("failed to add route for {cidr}"))?;
+            log::debug!("Added route for {cidr} via {tun_name}.");
+            self.installed.routes.push(message);
+        }
+
+        if !conf.uses_policy_routing() {
+            return Ok(());
+        }
+        let ipv4 = conf.routes.iter().any(|c| matches!(c, IpCidr::Ipv4(_)));
+        let ipv6 = conf.routes.iter().any(|c| matches!(c, IpCidr::Ipv6(_)));
+        for v6 in [false, true] {
+            if (v6 && !ipv6) || (!v6 && !ipv4) {
+                continue;
+            }
+            if let Some(fwmark) = conf.fwmark {
+                self.add_rule(v6, table, |rule| {
+                    rule.nlas.push(RuleNla::FwMark(fwmark));
+                })
+                .await
+                .with_context(|| format!("failed to add policy rule for fwmark {fwmark}"))?;
+            }
+            for &(start, end) in &conf.uid_ranges {
+                self.add_rule(v6, table, |rule| {
+                    // struct fib_rule_uid_range
+                    let range = [start.to_ne_bytes(), end.to_ne_bytes()].concat();
+                    rule.nlas.push(RuleNla::UidRange(range));
+                })
+                .await
+                .with_context(|| format!("failed to add policy rule for uids {start}-{end}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn add_rule(
+        &mut self,
+        v6: bool,
+        table: u32,
+        configure: impl FnOnce(&mut RuleMessage),
+    ) -> Result<()> {
+        let mut request = self
+            .handle
+            .rule()
+            .add()
+            .table_id(table)
+            .priority(RULE_PRIORITY)
+            .action(FR_ACT_TO_TBL);
+        request.message_mut().header.family = if v6 { AF_INET6 } else { AF_INET } as u8;
+        configure(request.message_mut());
+        let message = request.message_mut().clone();
+        request.execute().await?;
+        self.installed.rules.push(message);
+        Ok(())
+    }
+
+    /// Remove all rules, routes and addresses we have added and restore all changed sysctls.
+    pub async fn restore(&mut self) {
+        self.installed.remove(&self.handle).await;
+        self.sysctls.restore();
+    }
+}
+
+impl Drop for RouteManager {
+    fn drop(&mut self) {
+        if self.installed.is_empty() {
+            return;
+        }
+        let mut installed = std::mem::take(&mut self.installed);
+        let handle = self.handle.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move { installed.remove(&handle).await });
+            }
+            Err(_) => log::error!("Cannot remove routes without a Tokio runtime: {installed:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sysctl_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        fs::write(&a, "1\n").unwrap();
+        fs::write(&b, "0\n").unwrap();
+
+        let mut sysctls = SysctlSnapshot::default();
+        sysctls.set(&a, "0").unwrap();
+        sysctls.set(&b, "0").unwrap();
+        sysctls.set(&a, "2").unwrap();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "2");
+        // unchanged values are not recorded.
+        assert_eq!(sysctls.original.len(), 2);
+        assert!(sysctls.set(dir.path().join("missing"), "1").is_err());
+
+        sysctls.restore();
+        assert_eq!(fs::read_to_string(&a).unwrap(), "1");
+        assert_eq!(fs::read_to_string(&b).unwrap(), "0\n");
+
+        // dropping the snapshot restores everything, e.g. if a task is aborted.
+        let mut sysctls = SysctlSnapshot::default();
+        sysctls.set(&a, "0").unwrap();
+        drop(sysctls);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "1");
+    }
+}
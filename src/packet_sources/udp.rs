@@ -1,4 +1,5 @@
 use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 
@@ -10,6 +11,7 @@ use crate::shutdown;
 use socket2::{Domain, Protocol, Socket, Type};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc::{Permit, Sender, UnboundedReceiver};
+use tokio::time::MissedTickBehavior;
 
 pub fn remote_host_closed_conn<T>(_res: &Result<T, std::io::Error>) -> bool {
     #[cfg(windows)]
@@ -80,7 +82,7 @@ impl PacketSourceConf for UdpConf {
             UdpTask {
                 socket,
                 local_addr,
-                handler: UdpHandler::new(),
+                handler: UdpHandler::new(transport_events_tx.clone()),
                 transport_events_tx,
                 transport_commands_rx,
                 shutdown,
@@ -112,12 +114,17 @@ impl PacketSourceTask for UdpTask {
 
         let mut permit: Option<Permit<TransportEvent>> = None;
 
+        let mut expire_interval = tokio::time::interval(Duration::from_secs(5));
+        expire_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
         loop {
             let py_tx_available = permit.is_some();
 
             tokio::select! {
                 // wait for graceful shutdown
                 _ = self.shutdown.recv() => break,
+                // expire idle connections
+                _ = expire_interval.tick() => self.handler.poll(),
                 // wait for transport_events_tx channel capacity...
                 Ok(p) = transport_events_tx.reserve(), if !py_tx_available => {
                     permit = Some(p);
@@ -155,6 +162,30 @@ impl PacketSourceTask for UdpTask {
                 }
             }
         }
+
+        let drain_timeout = self.shutdown.drain_timeout();
+        if !drain_timeout.is_zero() {
+            // flush pending outgoing packets before shutdown
+            let flush = async {
+                if packet_needs_sending {
+                    self.socket.send_to(&packet_payload, packet_dst).await?;
+                }
+                while let Ok(command) = self.transport_commands_rx.try_recv() {
+                    if let Some(UdpPacket {
+                        payload, dst_addr, ..
+                    }) = self.handler.handle_transport_command(command)
+                    {
+                        self.socket.send_to(&payload, dst_addr).await?;
+                    }
+                }
+                Ok::<(), std::io::Error>(())
+            };
+            match tokio::time::timeout(drain_timeout, flush).await {
+                Ok(r) => r.context("UDP send_to() failed")?,
+                Err(_) => log::debug!("Drain timeout exceeded, discarding remaining packets."),
+            }
+        }
+
         log::debug!("UDP server task shutting down.");
         Ok(())
     }
@@ -19,6 +19,8 @@ use crate::shutdown;
 
 pub struct WindowsConf {
     pub executable_path: PathBuf,
+    /// Hand out TCP Fast Open cookies to intercepted processes.
+    pub tcp_fast_open: bool,
 }
 
 impl PacketSourceConf for WindowsConf {
@@ -101,6 +103,7 @@ impl PacketSourceConf for WindowsConf {
                 transport_commands_rx,
                 conf_rx,
                 shutdown,
+                tcp_fast_open: self.tcp_fast_open,
             },
             conf_tx,
         ))
@@ -113,6 +116,7 @@ pub struct WindowsTask {
     transport_commands_rx: UnboundedReceiver<TransportCommand>,
     conf_rx: UnboundedReceiver<InterceptConf>,
     shutdown: shutdown::Receiver,
+    tcp_fast_open: bool,
 }
 
 impl PacketSourceTask for WindowsTask {
@@ -127,6 +131,7 @@ impl PacketSourceTask for WindowsTask {
             self.transport_commands_rx,
             self.conf_rx,
             self.shutdown,
+            self.tcp_fast_open,
         )
         .await
     }
@@ -1,20 +1,32 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
 
 use crate::messages::{
     NetworkCommand, NetworkEvent, SmolPacket, TransportCommand, TransportEvent, TunnelInfo,
+    WireGuardPeerTunnelInfo,
 };
 use crate::network::{add_network_layer, MAX_PACKET_SIZE};
 use crate::packet_sources::{PacketSourceConf, PacketSourceTask};
 use anyhow::{anyhow, Context, Result};
 use boringtun::noise::{
-    errors::WireGuardError, handshake::parse_handshake_anon, Packet, Tunn, TunnResult,
+    errors::WireGuardError, handshake::parse_handshake_anon, rate_limiter::RateLimiter, Packet,
+    Tunn, TunnResult,
 };
 use boringtun::x25519::{PublicKey, StaticSecret};
+use data_encoding::BASE64;
+use futures_util::FutureExt;
+use lru_time_cache::LruCache;
 use pretty_hex::pretty_hex;
-use smoltcp::wire::{Ipv4Packet, Ipv6Packet};
-use tokio::sync::mpsc::UnboundedReceiver;
+use smoltcp::wire::{IpAddress, IpCidr, Ipv4Packet, Ipv6Packet};
+use tokio::io::ReadBuf;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::time::MissedTickBehavior;
 use tokio::{
     net::UdpSocket,
     sync::{
@@ -27,23 +39,196 @@ use crate::packet_sources::udp::{create_and_bind_udp_socket, remote_host_closed_
 use crate::shutdown;
 
 // WireGuard headers are 60 bytes for IPv4 and 80 bytes for IPv6
-const WG_HEADER_SIZE: usize = 80;
+pub(crate) const WG_HEADER_SIZE: usize = 80;
+
+// boringtun expects its timers to be updated every 250ms.
+pub(crate) const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+// Number of handshakes per second after which we require peers to present a valid cookie,
+// and the interval in which the handshake counter is reset.
+const HANDSHAKE_RATE_LIMIT: u64 = 100;
+const RATE_LIMITER_RESET_INTERVAL: Duration = Duration::from_secs(1);
+
+// Number of endpoints we remember for each peer.
+const MAX_ENDPOINT_HISTORY: usize = 16;
+
+// boringtun keeps the peer index in the upper 24 bits of its 32-bit session indices.
+const MAX_PEER_INDEX: u32 = (1 << 24) - 1;
+
+// Maximum number of destinations for which we remember that we have logged dropped packets.
+const MAX_DROPPED_DESTINATIONS: usize = 1024;
 
 /// A WireGuard peer. We keep track of the tunnel state and the peer address.
 pub struct WireGuardPeer {
+    public_key: PublicKey,
+    name: String,
     tunnel: Tunn,
     endpoint: Option<SocketAddr>,
+    /// The configured endpoint, which we fall back to when the session expires.
+    initial_endpoint: Option<SocketAddr>,
+    /// Index of the socket we last received a datagram from this peer on.
+    socket: Option<usize>,
+    allowed_ips: Vec<IpCidr>,
+    /// Endpoints the peer has roamed to, oldest first.
+    endpoint_history: Vec<(SocketAddr, SystemTime)>,
+    /// Passed along with every packet we receive from the peer, updated when the peer roams.
+    tunnel_info: Arc<WireGuardPeerTunnelInfo>,
+}
+
+impl WireGuardPeer {
+    /// Check if the peer may send packets with the given source address.
+    /// Peers without configured allowed IPs may use any address.
+    fn is_allowed_src(&self, addr: IpAddr) -> bool {
+        self.allowed_ips.is_empty()
+            || self
+                .allowed_ips
+                .iter()
+                .any(|cidr| cidr.contains_addr(&IpAddress::from(addr)))
+    }
+
+    /// Record the endpoint of an authenticated packet. WireGuard peers may roam between
+    /// endpoints at any time, and we always reply to the most recent one.
+    fn update_endpoint(&mut self, endpoint: SocketAddr, socket_idx: usize) {
+        self.endpoint = Some(endpoint);
+        self.socket = Some(socket_idx);
+        if self
+            .endpoint_history
+            .last()
+            .is_some_and(|(addr, _)| *addr == endpoint)
+        {
+            return;
+        }
+        if let Some((previous, _)) = self.endpoint_history.last() {
+            log::info!(
+                "WireGuard peer {} roamed from {previous} to {endpoint}.",
+                self.name
+            );
+        }
+        if self.endpoint_history.len() == MAX_ENDPOINT_HISTORY {
+            self.endpoint_history.remove(0);
+        }
+        self.endpoint_history.push((endpoint, SystemTime::now()));
+        self.tunnel_info = Arc::new(peer_tunnel_info(
+            &self.public_key,
+            &self.name,
+            &self.endpoint_history,
+        ));
+    }
+}
+
+/// The name of a peer without a configured name: the first characters of its public key.
+pub fn default_peer_name(public_key: &PublicKey) -> String {
+    BASE64.encode(public_key.as_bytes())[..8].to_string()
+}
+
+pub(crate) fn peer_tunnel_info(
+    public_key: &PublicKey,
+    name: &str,
+    endpoints: &[(SocketAddr, SystemTime)],
+) -> WireGuardPeerTunnelInfo {
+    WireGuardPeerTunnelInfo {
+        public_key: BASE64.encode(public_key.as_bytes()),
+        name: name.to_string(),
+        endpoints: endpoints.to_vec(),
+    }
+}
+
+/// Configuration for a single WireGuard peer, similar to a `[Peer]` section in wg-quick.
+#[derive(Debug, Clone)]
+pub struct WireGuardPeerConf {
+    pub public_key: PublicKey,
+    /// A human-readable name to attribute connections to, e.g. the device name.
+    /// Defaults to the first characters of the public key.
+    pub name: Option<String>,
+    /// IP ranges that this peer may use as source addresses, and to which outgoing packets are
+    /// routed. If empty, the peer may use any source address and outgoing packets are routed
+    /// based on the addresses we have seen from it.
+    pub allowed_ips: Vec<IpCidr>,
+    pub preshared_key: Option<[u8; 32]>,
+    /// Persistent keepalive interval in seconds.
+    pub persistent_keepalive: Option<u16>,
+    /// The peer's initial endpoint. It is updated whenever we receive an authenticated packet.
+    pub endpoint: Option<SocketAddr>,
+}
+
+impl WireGuardPeerConf {
+    pub fn new(public_key: PublicKey) -> Self {
+        Self {
+            public_key,
+            name: None,
+            allowed_ips: Vec::new(),
+            preshared_key: None,
+            persistent_keepalive: Some(25),
+            endpoint: None,
+        }
+    }
+}
+
+/// Commands to manage the peers of a running WireGuard server.
+#[derive(Debug)]
+pub enum WireGuardCommand {
+    /// Add a peer. An existing peer with the same public key is replaced.
+    AddPeer(WireGuardPeerConf),
+    /// Remove the peer with the given public key.
+    RemovePeer(PublicKey),
+    /// Get the current state of all peers.
+    ListPeers(oneshot::Sender<Vec<WireGuardPeerInfo>>),
+    /// Get the number of outgoing packets that were dropped because they could not be routed to a peer.
+    DroppedPackets(oneshot::Sender<u64>),
+}
+
+/// The current state of a WireGuard peer, similar to what `wg show` displays.
+#[derive(Debug, Clone)]
+pub struct WireGuardPeerInfo {
+    pub public_key: PublicKey,
+    pub name: String,
+    pub allowed_ips: Vec<IpCidr>,
+    pub endpoint: Option<SocketAddr>,
+    /// Endpoints the peer has roamed to (oldest first), with the time at which it did so.
+    pub endpoint_history: Vec<(SocketAddr, SystemTime)>,
+    /// Time of the last completed handshake, if any.
+    pub last_handshake: Option<SystemTime>,
+    pub rx_bytes: usize,
+    pub tx_bytes: usize,
+}
+
+/// Parse an IP range in CIDR notation (e.g. `10.0.0.0/24`).
+/// A plain IP address is treated as a single-address range.
+pub fn parse_allowed_ip(s: &str) -> Result<IpCidr> {
+    let (addr, prefix_len) = match s.split_once('/') {
+        Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+        None => (s, None),
+    };
+    let addr: IpAddr = addr
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid IP address in allowed IPs: {s}"))?;
+    let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix_len = match prefix_len {
+        Some(p) => p
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|p| *p <= max_prefix_len)
+            .with_context(|| format!("invalid prefix length in allowed IPs: {s}"))?,
+        None => max_prefix_len,
+    };
+    Ok(IpCidr::new(addr.into(), prefix_len))
 }
 
 pub struct WireGuardConf {
-    pub listen_addr: SocketAddr,
+    /// Addresses to listen on. All sockets share the same peers,
+    /// so that e.g. IPv4 and IPv6 clients can connect to the same server.
+    pub listen_addrs: Vec<SocketAddr>,
     pub private_key: StaticSecret,
-    pub peer_public_keys: Vec<PublicKey>,
+    pub peers: Vec<WireGuardPeerConf>,
+    /// Hand out TCP Fast Open cookies to clients.
+    pub tcp_fast_open: bool,
 }
 
 impl PacketSourceConf for WireGuardConf {
     type Task = WireGuardTask;
-    type Data = SocketAddr;
+    type Data = (Vec<SocketAddr>, UnboundedSender<WireGuardCommand>);
 
     fn name(&self) -> &'static str {
         "WireGuard server"
@@ -55,71 +240,86 @@ impl PacketSourceConf for WireGuardConf {
         transport_commands_rx: UnboundedReceiver<TransportCommand>,
         shutdown: shutdown::Receiver,
     ) -> Result<(Self::Task, Self::Data)> {
-        let (network_task_handle, net_tx, net_rx) =
-            add_network_layer(transport_events_tx, transport_commands_rx, shutdown);
-
-        // initialize WireGuard server
-        let mut peers_by_idx = HashMap::new();
-        let mut peers_by_key = HashMap::new();
-        for public_key in self.peer_public_keys {
-            let index = peers_by_idx.len() as u32;
-
-            let tunnel = Tunn::new(
-                self.private_key.clone(),
-                public_key,
-                None,
-                Some(25),
-                index,
-                None,
-            );
-
-            let peer = Arc::new(Mutex::new(WireGuardPeer {
-                tunnel,
-                endpoint: None,
-            }));
+        let (network_task_handle, net_tx, net_rx) = add_network_layer(
+            transport_events_tx,
+            transport_commands_rx,
+            shutdown,
+            self.tcp_fast_open,
+        );
 
-            peers_by_idx.insert(index, peer.clone());
-            peers_by_key.insert(public_key, peer);
+        if self.listen_addrs.is_empty() {
+            return Err(anyhow!("No WireGuard listen addresses were configured."));
+        }
+        let mut sockets = Vec::with_capacity(self.listen_addrs.len());
+        let mut local_addrs = Vec::with_capacity(self.listen_addrs.len());
+        for listen_addr in self.listen_addrs {
+            let socket = create_and_bind_udp_socket(listen_addr)?;
+            let local_addr = socket.local_addr()?;
+            log::debug!("WireGuard server listening for UDP connections on {local_addr} ...");
+            sockets.push(socket);
+            local_addrs.push(local_addr);
         }
-
-        let socket = create_and_bind_udp_socket(self.listen_addr)?;
-        let local_addr = socket.local_addr()?;
-
-        log::debug!("WireGuard server listening for UDP connections on {local_addr} ...");
 
         let public_key = PublicKey::from(&self.private_key);
+        let rate_limiter = Arc::new(RateLimiter::new(&public_key, HANDSHAKE_RATE_LIMIT));
+        let (commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
 
-        Ok((
-            WireGuardTask {
-                socket,
-                private_key: self.private_key,
-                public_key,
+        // initialize WireGuard server
+        let mut task = WireGuardTask {
+            sockets,
+            next_socket: 0,
+            local_addrs: local_addrs.clone(),
+            private_key: self.private_key,
+            public_key,
+            rate_limiter,
 
-                peers_by_idx,
-                peers_by_key,
-                peers_by_ip: HashMap::new(),
-                wg_buf: vec![0u8; MAX_PACKET_SIZE],
+            peers_by_idx: HashMap::new(),
+            peers_by_key: HashMap::new(),
+            peers_by_ip: HashMap::new(),
+            routes: Vec::new(),
+            next_peer_index: 0,
+            dropped_packets: 0,
+            dropped_destinations: LruCache::with_capacity(MAX_DROPPED_DESTINATIONS),
+            wg_buf: vec![0u8; MAX_PACKET_SIZE],
 
-                net_tx,
-                net_rx,
-                network_task_handle,
-            },
-            local_addr,
-        ))
+            net_tx,
+            net_rx,
+            commands_rx,
+            network_task_handle,
+        };
+        for peer_conf in self.peers {
+            task.add_peer(peer_conf);
+        }
+
+        Ok((task, (local_addrs, commands_tx)))
     }
 }
 
 pub struct WireGuardTask {
-    socket: UdpSocket,
+    sockets: Vec<UdpSocket>,
+    /// Index of the socket to poll first for incoming datagrams, see [recv_from_any].
+    next_socket: usize,
+    local_addrs: Vec<SocketAddr>,
     private_key: StaticSecret,
     public_key: PublicKey,
+    /// Handshake rate limiter shared across all peers, which answers with cookie replies under load.
+    rate_limiter: Arc<RateLimiter>,
 
     peers_by_idx: HashMap<u32, Arc<Mutex<WireGuardPeer>>>,
     peers_by_key: HashMap<PublicKey, Arc<Mutex<WireGuardPeer>>>,
     peers_by_ip: HashMap<IpAddr, Arc<Mutex<WireGuardPeer>>>,
+    /// Allowed IPs of all peers, sorted by prefix length (longest first).
+    routes: Vec<(IpCidr, Arc<Mutex<WireGuardPeer>>)>,
+    /// Candidate for the next peer index, see [WireGuardTask::allocate_peer_index].
+    next_peer_index: u32,
+    /// Number of outgoing packets that were dropped because they could not be routed to a peer.
+    dropped_packets: u64,
+    /// Destinations we have dropped packets to, so that we only log the first one.
+    dropped_destinations: LruCache<IpAddr, ()>,
 
     net_tx: Sender<NetworkEvent>,
     net_rx: Receiver<NetworkCommand>,
+    commands_rx: UnboundedReceiver<WireGuardCommand>,
 
     wg_buf: Vec<u8>,
     network_task_handle: tokio::task::JoinHandle<Result<()>>,
@@ -127,28 +327,35 @@ pub struct WireGuardTask {
 
 impl PacketSourceTask for WireGuardTask {
     async fn run(mut self) -> Result<()> {
-        if self.peers_by_idx.is_empty() {
-            return Err(anyhow!("No WireGuard peers were configured."));
-        }
-
         let mut udp_buf = vec![0; MAX_PACKET_SIZE];
 
+        let mut timer = tokio::time::interval(TIMER_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut rate_limiter_reset = tokio::time::interval(RATE_LIMITER_RESET_INTERVAL);
+
         loop {
             tokio::select! {
                 exit = &mut self.network_task_handle => break exit.context("network task panic")?.context("network task error")?,
-                // wait for WireGuard packets incoming on the UDP socket
-                r = self.socket.recv_from(udp_buf.as_mut_slice()) => {
+                // send keepalives, (re-)initiate handshakes and expire sessions
+                _ = timer.tick() => self.update_timers().await?,
+                _ = rate_limiter_reset.tick() => self.rate_limiter.reset_count(),
+                // add, remove or list peers
+                Some(cmd) = self.commands_rx.recv() => self.handle_command(cmd).await,
+                // wait for WireGuard packets incoming on any of the UDP sockets
+                (socket_idx, r) = recv_from_any(&self.sockets, &mut self.next_socket, udp_buf.as_mut_slice()) => {
                     if remote_host_closed_conn(&r) {
                         continue;
                     }
                     let (len, src_orig) = r.context("UDP recv() failed")?;
-                    self.process_incoming_datagram(&udp_buf[..len], src_orig).await?;
+                    let datagram = &udp_buf[..len];
+                    catch_panic(self.process_incoming_datagram(datagram, src_orig, socket_idx))
+                        .await?;
                 },
                 // wait for outgoing IP packets
                 Some(e) = self.net_rx.recv() => {
                     match e {
                         NetworkCommand::SendPacket(packet) => {
-                            self.process_outgoing_packet(packet).await?;
+                            catch_panic(self.process_outgoing_packet(packet)).await?;
                         }
                     }
                 }
@@ -159,7 +366,7 @@ impl PacketSourceTask for WireGuardTask {
         while let Some(e) = self.net_rx.recv().await {
             match e {
                 NetworkCommand::SendPacket(packet) => {
-                    self.process_outgoing_packet(packet).await?;
+                    catch_panic(self.process_outgoing_packet(packet)).await?;
                 }
             }
         }
@@ -170,18 +377,151 @@ impl PacketSourceTask for WireGuardTask {
 }
 
 impl WireGuardTask {
-    fn find_peer_for_datagram(&self, data: &[u8]) -> Option<Arc<Mutex<WireGuardPeer>>> {
-        let packet = match Tunn::parse_incoming_packet(data) {
-            Ok(p) => p,
-            Err(error) => {
-                log::error!("Received invalid WireGuard packet: {error:?}");
-                return None;
+    async fn handle_command(&mut self, cmd: WireGuardCommand) {
+        match cmd {
+            WireGuardCommand::AddPeer(conf) => {
+                log::debug!("Adding WireGuard peer.");
+                self.add_peer(conf);
+            }
+            WireGuardCommand::RemovePeer(public_key) => {
+                if !self.remove_peer(&public_key) {
+                    log::warn!("Cannot remove unknown WireGuard peer.");
+                }
+            }
+            WireGuardCommand::ListPeers(tx) => {
+                tx.send(self.peer_info().await).ok();
             }
+            WireGuardCommand::DroppedPackets(tx) => {
+                tx.send(self.dropped_packets).ok();
+            }
+        }
+    }
+
+    /// Add a new peer, replacing any existing peer with the same public key.
+    fn add_peer(&mut self, conf: WireGuardPeerConf) {
+        self.remove_peer(&conf.public_key);
+
+        let index = self.allocate_peer_index();
+
+        let tunnel = Tunn::new(
+            self.private_key.clone(),
+            conf.public_key,
+            conf.preshared_key,
+            conf.persistent_keepalive,
+            index,
+            Some(self.rate_limiter.clone()),
+        );
+
+        let name = conf
+            .name
+            .unwrap_or_else(|| default_peer_name(&conf.public_key));
+        let peer = Arc::new(Mutex::new(WireGuardPeer {
+            public_key: conf.public_key,
+            tunnel_info: Arc::new(peer_tunnel_info(&conf.public_key, &name, &[])),
+            name,
+            tunnel,
+            endpoint: conf.endpoint,
+            initial_endpoint: conf.endpoint,
+            socket: None,
+            allowed_ips: conf.allowed_ips.clone(),
+            endpoint_history: Vec::new(),
+        }));
+
+        for cidr in conf.allowed_ips {
+            self.routes.push((cidr, peer.clone()));
+        }
+        // longest prefix match wins
+        self.routes
+            .sort_by_key(|(cidr, _)| std::cmp::Reverse(cidr.prefix_len()));
+        self.peers_by_idx.insert(index, peer.clone());
+        self.peers_by_key.insert(conf.public_key, peer);
+    }
+
+    /// Find an unused peer index. Indices wrap around within 24 bits, skipping those still in use.
+    /// We never have anywhere close to 2^24 peers, so there always is a free one.
+    fn allocate_peer_index(&mut self) -> u32 {
+        loop {
+            let index = self.next_peer_index;
+            self.next_peer_index = if index == MAX_PEER_INDEX {
+                0
+            } else {
+                index + 1
+            };
+            if !self.peers_by_idx.contains_key(&index) {
+                return index;
+            }
+        }
+    }
+
+    /// Remove a peer. Returns `false` if there is no peer with the given public key.
+    fn remove_peer(&mut self, public_key: &PublicKey) -> bool {
+        let Some(peer) = self.peers_by_key.remove(public_key) else {
+            return false;
         };
+        self.peers_by_idx.retain(|_, p| !Arc::ptr_eq(p, &peer));
+        self.peers_by_ip.retain(|_, p| !Arc::ptr_eq(p, &peer));
+        self.routes.retain(|(_, p)| !Arc::ptr_eq(p, &peer));
+        true
+    }
+
+    /// Collect the current state of all peers, ordered by the time they were added.
+    async fn peer_info(&self) -> Vec<WireGuardPeerInfo> {
+        let mut peers: Vec<_> = self.peers_by_idx.iter().collect();
+        peers.sort_by_key(|(index, _)| **index);
 
+        let mut info = Vec::with_capacity(peers.len());
+        for (_, peer) in peers {
+            let peer = peer.lock().await;
+            let (time_since_handshake, tx_bytes, rx_bytes, _, _) = peer.tunnel.stats();
+            info.push(WireGuardPeerInfo {
+                public_key: peer.public_key,
+                name: peer.name.clone(),
+                allowed_ips: peer.allowed_ips.clone(),
+                endpoint: peer.endpoint,
+                endpoint_history: peer.endpoint_history.clone(),
+                last_handshake: time_since_handshake.and_then(|d| SystemTime::now().checked_sub(d)),
+                rx_bytes,
+                tx_bytes,
+            });
+        }
+        info
+    }
+
+    /// Service the timers of all peers and send the resulting handshake or keepalive datagrams.
+    async fn update_timers(&mut self) -> Result<()> {
+        for peer in self.peers_by_idx.values() {
+            let mut peer_guard = peer.lock().await;
+            match peer_guard.tunnel.update_timers(&mut self.wg_buf) {
+                TunnResult::Done => {}
+                TunnResult::WriteToNetwork(buf) => {
+                    if let Some(endpoint) = peer_guard.endpoint {
+                        log::trace!("WG::update_timers: WriteToNetwork to {endpoint}");
+                        let socket = select_socket(&self.sockets, peer_guard.socket, endpoint);
+                        send_datagram(socket, buf, endpoint).await;
+                    }
+                }
+                TunnResult::Err(WireGuardError::ConnectionExpired) => {
+                    log::debug!("WireGuard session expired.");
+                    peer_guard.endpoint = peer_guard.initial_endpoint;
+                    peer_guard.socket = None;
+                    drop(peer_guard);
+                    self.peers_by_ip.retain(|_, p| !Arc::ptr_eq(p, peer));
+                }
+                TunnResult::Err(error) => {
+                    log::debug!("WG::update_timers: Err: {error:?}");
+                }
+                TunnResult::WriteToTunnelV4(_, _) | TunnResult::WriteToTunnelV6(_, _) => {
+                    log::warn!("WG::update_timers: WriteToTunnel: unexpected event");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn find_peer_for_packet(&self, packet: &Packet) -> Option<Arc<Mutex<WireGuardPeer>>> {
         let peer = match packet {
             Packet::HandshakeInit(p) => {
-                let parsed = parse_handshake_anon(&self.private_key, &self.public_key, &p);
+                let parsed = parse_handshake_anon(&self.private_key, &self.public_key, p);
 
                 let handshake = match parsed {
                     Ok(hs) => hs,
@@ -212,22 +552,47 @@ impl WireGuardTask {
         &mut self,
         data: &[u8],
         sender_addr: SocketAddr,
+        socket_idx: usize,
     ) -> Result<()> {
-        let peer = match self.find_peer_for_datagram(data) {
+        // check the handshake rate before doing any expensive cryptography.
+        let packet =
+            match self
+                .rate_limiter
+                .verify_packet(Some(sender_addr.ip()), data, &mut self.wg_buf)
+            {
+                Ok(packet) => packet,
+                Err(TunnResult::WriteToNetwork(cookie)) => {
+                    log::debug!(
+                        "Too many WireGuard handshakes, sending cookie reply to {sender_addr}."
+                    );
+                    send_datagram(&self.sockets[socket_idx], cookie, sender_addr).await;
+                    return Ok(());
+                }
+                Err(TunnResult::Err(error)) => {
+                    log::error!("Received invalid WireGuard packet: {error:?}");
+                    return Ok(());
+                }
+                Err(_) => return Ok(()),
+            };
+
+        let peer = match self.find_peer_for_packet(&packet) {
             Some(p) => p,
             None => return Ok(()),
         };
 
         let mut result = {
             let mut peer = peer.lock().await;
-            peer.endpoint = Some(sender_addr);
-            peer.tunnel
-                .decapsulate(Some(sender_addr.ip()), data, &mut self.wg_buf)
+            let result = peer.tunnel.handle_verified_packet(packet, &mut self.wg_buf);
+            // only authenticated packets may move the peer to a new endpoint.
+            if !matches!(result, TunnResult::Err(_)) {
+                peer.update_endpoint(sender_addr, socket_idx);
+            }
+            result
         };
 
         while let TunnResult::WriteToNetwork(b) = result {
             log::trace!("WG::process_incoming_datagram: WriteToNetwork");
-            self.socket.send_to(b, sender_addr).await?;
+            send_datagram(&self.sockets[socket_idx], b, sender_addr).await;
 
             // check if there are more things to be handled
             result = peer
@@ -264,12 +629,24 @@ impl WireGuardTask {
                             pretty_hex(&buf),
                         );
 
+                        let peer_info = {
+                            let peer = peer.lock().await;
+                            if !peer.is_allowed_src(packet.src_addr().into()) {
+                                log::warn!(
+                                    "Dropping packet from WireGuard peer with disallowed source address {}.",
+                                    packet.src_addr()
+                                );
+                                return Ok(());
+                            }
+                            peer.tunnel_info.clone()
+                        };
                         self.peers_by_ip.insert(packet.src_addr().into(), peer);
                         let event = NetworkEvent::ReceivePacket {
                             packet: SmolPacket::from(packet),
                             tunnel_info: TunnelInfo::WireGuard {
                                 src_addr: sender_addr,
-                                dst_addr: self.socket.local_addr()?,
+                                dst_addr: self.local_addrs[socket_idx],
+                                peer: peer_info,
                             },
                         };
 
@@ -295,12 +672,24 @@ impl WireGuardTask {
                             pretty_hex(&buf),
                         );
 
+                        let peer_info = {
+                            let peer = peer.lock().await;
+                            if !peer.is_allowed_src(packet.src_addr().into()) {
+                                log::warn!(
+                                    "Dropping packet from WireGuard peer with disallowed source address {}.",
+                                    packet.src_addr()
+                                );
+                                return Ok(());
+                            }
+                            peer.tunnel_info.clone()
+                        };
                         self.peers_by_ip.insert(packet.src_addr().into(), peer);
                         let event = NetworkEvent::ReceivePacket {
                             packet: SmolPacket::from(packet),
                             tunnel_info: TunnelInfo::WireGuard {
                                 src_addr: sender_addr,
-                                dst_addr: self.socket.local_addr()?,
+                                dst_addr: self.local_addrs[socket_idx],
+                                peer: peer_info,
                             },
                         };
 
@@ -318,23 +707,46 @@ impl WireGuardTask {
         Ok(())
     }
 
-    /// process packets and send the encrypted WireGuard datagrams to the peer.
-    async fn process_outgoing_packet(&mut self, packet: SmolPacket) -> Result<()> {
-        let peer = self
-            .peers_by_ip
-            .get(&packet.dst_ip())
-            .or_else(|| {
-                log::warn!(
-                    "No peer found for IP {}, falling back to first peer.",
-                    packet.dst_ip()
-                );
-                self.peers_by_idx.values().next()
+    /// Find the peer that packets to the given IP address should be sent to.
+    ///
+    /// Allowed IPs take precedence (longest prefix match), followed by the addresses
+    /// we have seen from peers without allowed IPs. If there is only a single peer and it
+    /// has no allowed IPs, all other packets are sent to it.
+    fn find_peer_for_ip(&self, ip: IpAddr) -> Option<Arc<Mutex<WireGuardPeer>>> {
+        let addr = IpAddress::from(ip);
+        self.routes
+            .iter()
+            .find(|(cidr, _)| cidr.contains_addr(&addr))
+            .map(|(_, peer)| peer)
+            .or_else(|| self.peers_by_ip.get(&ip))
+            // `routes` holds the allowed IPs of all peers, so it is empty if the peer has none.
+            .or_else(|| match self.peers_by_idx.len() {
+                1 if self.routes.is_empty() => self.peers_by_idx.values().next(),
+                _ => None,
             })
-            .unwrap();
+            .cloned()
+    }
+
+    fn drop_outgoing_packet(&mut self, dst_ip: IpAddr, reason: &str) {
+        self.dropped_packets += 1;
+        if self.dropped_destinations.insert(dst_ip, ()).is_none() {
+            log::debug!("Dropping packets to {dst_ip}: {reason}.");
+        }
+    }
 
+    /// process packets and send the encrypted WireGuard datagrams to the peer.
+    ///
+    /// If the peer has no active session, boringtun queues the packet and initiates a handshake.
+    /// Packets that cannot be routed to a peer with a known endpoint are dropped.
+    async fn process_outgoing_packet(&mut self, packet: SmolPacket) -> Result<()> {
         let src_ip = packet.src_ip();
         let dst_ip = packet.dst_ip();
 
+        let Some(peer) = self.find_peer_for_ip(dst_ip) else {
+            self.drop_outgoing_packet(dst_ip, "no WireGuard peer for this address");
+            return Ok(());
+        };
+
         let packet_bytes = packet.into_inner();
 
         // Tunn.encapsulate panics if the packet is too big for the buffer
@@ -350,6 +762,12 @@ impl WireGuardTask {
         }
 
         let mut peer = peer.lock().await;
+        let peer_socket = peer.socket;
+        let Some(dst_addr) = peer.endpoint else {
+            drop(peer);
+            self.drop_outgoing_packet(dst_ip, "WireGuard peer has no known endpoint");
+            return Ok(());
+        };
         match peer.tunnel.encapsulate(&packet_bytes, &mut self.wg_buf) {
             TunnResult::Done => {
                 log::trace!("WG::process_outgoing_packet: Done");
@@ -358,7 +776,6 @@ impl WireGuardTask {
                 log::error!("WG::process_outgoing_packet: Err: {error:?}");
             }
             TunnResult::WriteToNetwork(buf) => {
-                let dst_addr = peer.endpoint.unwrap();
                 drop(peer);
 
                 log::trace!(
@@ -371,7 +788,8 @@ impl WireGuardTask {
                     pretty_hex(&buf),
                 );
 
-                self.socket.send_to(buf, dst_addr).await?;
+                let socket = select_socket(&self.sockets, peer_socket, dst_addr);
+                send_datagram(socket, buf, dst_addr).await;
             }
             // IPv4 packet
             TunnResult::WriteToTunnelV4(_, _) => {
@@ -386,3 +804,582 @@ impl WireGuardTask {
         Ok(())
     }
 }
+
+/// Send a datagram to a peer. Failing to reach a single peer (e.g. because its endpoint is
+/// unreachable) must not take down the server, so errors are only logged.
+pub(crate) async fn send_datagram(socket: &UdpSocket, buf: &[u8], addr: SocketAddr) {
+    if let Err(e) = socket.send_to(buf, addr).await {
+        log::warn!("Failed to send WireGuard datagram to {addr}: {e}");
+    }
+}
+
+/// Pick the socket to send datagrams to `endpoint` from: the one we last received a datagram
+/// from the peer on, or otherwise the first socket with a matching address family.
+fn select_socket(sockets: &[UdpSocket], last: Option<usize>, endpoint: SocketAddr) -> &UdpSocket {
+    last.and_then(|idx| sockets.get(idx))
+        .or_else(|| {
+            sockets.iter().find(|socket| {
+                socket
+                    .local_addr()
+                    .is_ok_and(|addr| addr.is_ipv4() == endpoint.is_ipv4())
+            })
+        })
+        .unwrap_or(&sockets[0])
+}
+
+/// Receive a datagram on any of the given sockets. Returns the index of the socket alongside
+/// the result of `recv_from`.
+///
+/// Sockets are polled round-robin, starting after the one we last received from (`next`), so that
+/// a busy socket cannot starve the others.
+async fn recv_from_any(
+    sockets: &[UdpSocket],
+    next: &mut usize,
+    buf: &mut [u8],
+) -> (usize, std::io::Result<(usize, SocketAddr)>) {
+    std::future::poll_fn(|cx| {
+        for offset in 0..sockets.len() {
+            let idx = (*next + offset) % sockets.len();
+            let mut read_buf = ReadBuf::new(&mut buf[..]);
+            if let Poll::Ready(r) = sockets[idx].poll_recv_from(cx, &mut read_buf) {
+                let len = read_buf.filled().len();
+                *next = (idx + 1) % sockets.len();
+                return Poll::Ready((idx, r.map(|addr| (len, addr))));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Process a single packet, logging panics instead of letting them kill the server task.
+async fn catch_panic(fut: impl Future<Output = Result<()>>) -> Result<()> {
+    AssertUnwindSafe(fut)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|_| {
+            log::error!("Panic while processing WireGuard packet, dropping packet.");
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::ConnectionId;
+    use mitm_wg_test_client::{ClientConf, WireGuardClient};
+
+    #[test]
+    fn test_parse_allowed_ip() {
+        assert_eq!(
+            parse_allowed_ip("10.0.0.0/24").unwrap(),
+            IpCidr::new(IpAddress::v4(10, 0, 0, 0), 24)
+        );
+        assert_eq!(
+            parse_allowed_ip("10.0.0.1").unwrap(),
+            IpCidr::new(IpAddress::v4(10, 0, 0, 1), 32)
+        );
+        assert_eq!(
+            parse_allowed_ip("::/0").unwrap(),
+            IpCidr::new(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 0), 0)
+        );
+        assert!(parse_allowed_ip("10.0.0.0/33").is_err());
+        assert!(parse_allowed_ip("example.com/24").is_err());
+    }
+
+    fn test_peer(allowed_ips: Vec<IpCidr>) -> WireGuardPeer {
+        let public_key = PublicKey::from([2; 32]);
+        WireGuardPeer {
+            public_key,
+            name: default_peer_name(&public_key),
+            tunnel: Tunn::new(StaticSecret::from([1; 32]), public_key, None, None, 0, None),
+            endpoint: None,
+            initial_endpoint: None,
+            socket: None,
+            allowed_ips,
+            endpoint_history: Vec::new(),
+            tunnel_info: Arc::new(peer_tunnel_info(&public_key, "", &[])),
+        }
+    }
+
+    #[test]
+    fn test_allowed_src() {
+        let peer = test_peer(vec![parse_allowed_ip("10.0.0.0/24").unwrap()]);
+        assert!(peer.is_allowed_src("10.0.0.42".parse().unwrap()));
+        assert!(!peer.is_allowed_src("10.0.1.42".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_endpoint_roaming() {
+        let mut peer = test_peer(vec![]);
+        let a: SocketAddr = "192.0.2.1:51820".parse().unwrap();
+        let b: SocketAddr = "198.51.100.1:4242".parse().unwrap();
+
+        peer.update_endpoint(a, 0);
+        peer.update_endpoint(a, 0);
+        peer.update_endpoint(b, 1);
+        assert_eq!(peer.endpoint, Some(b));
+        assert_eq!(peer.socket, Some(1));
+        let history: Vec<_> = peer
+            .endpoint_history
+            .iter()
+            .map(|(addr, _)| *addr)
+            .collect();
+        assert_eq!(history, vec![a, b]);
+
+        let info = peer.tunnel_info.clone();
+        assert_eq!(info.public_key, BASE64.encode(&[2; 32]));
+        assert_eq!(info.name, "AgICAgIC");
+        assert_eq!(info.endpoints, peer.endpoint_history);
+
+        for port in 0..2 * MAX_ENDPOINT_HISTORY as u16 {
+            peer.update_endpoint(SocketAddr::new(a.ip(), port), 0);
+        }
+        assert_eq!(peer.endpoint_history.len(), MAX_ENDPOINT_HISTORY);
+    }
+
+    const SERVER_KEY: [u8; 32] = [1; 32];
+    const CLIENT_KEY: [u8; 32] = [2; 32];
+
+    async fn start_server(
+        peers: Vec<WireGuardPeerConf>,
+    ) -> Result<(
+        UdpSocket,
+        shutdown::Sender,
+        tokio::task::JoinHandle<Result<()>>,
+    )> {
+        let (events_tx, _events_rx) = tokio::sync::mpsc::channel(1);
+        let (_commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (task, (addrs, _)) = WireGuardConf {
+            listen_addrs: vec!["127.0.0.1:0".parse()?],
+            private_key: StaticSecret::from(SERVER_KEY),
+            peers,
+            tcp_fast_open: false,
+        }
+        .build(events_tx, commands_rx, shutdown_rx)
+        .await?;
+        let handle = tokio::spawn(task.run());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        socket.connect(addrs[0]).await?;
+        Ok((socket, shutdown_tx, handle))
+    }
+
+    fn client_tunnel(preshared_key: Option<[u8; 32]>) -> Tunn {
+        Tunn::new(
+            StaticSecret::from(CLIENT_KEY),
+            PublicKey::from(&StaticSecret::from(SERVER_KEY)),
+            preshared_key,
+            None,
+            0,
+            None,
+        )
+    }
+
+    /// Perform a handshake with the server and return whether it succeeded.
+    async fn handshake(socket: &UdpSocket, client: &mut Tunn) -> Result<bool> {
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let mut recv_buf = vec![0u8; MAX_PACKET_SIZE];
+
+        let TunnResult::WriteToNetwork(init) = client.format_handshake_initiation(&mut buf, false)
+        else {
+            panic!("expected handshake initiation");
+        };
+        socket.send(init).await?;
+        let Ok(n) = tokio::time::timeout(Duration::from_secs(1), socket.recv(&mut recv_buf)).await
+        else {
+            return Ok(false);
+        };
+        match client.decapsulate(None, &recv_buf[..n?], &mut buf) {
+            TunnResult::WriteToNetwork(b) => {
+                socket.send(b).await?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_persistent_keepalive() -> Result<()> {
+        let mut peer = WireGuardPeerConf::new(PublicKey::from(&StaticSecret::from(CLIENT_KEY)));
+        peer.persistent_keepalive = Some(1);
+        let (socket, shutdown_tx, handle) = start_server(vec![peer]).await?;
+
+        let mut client = client_tunnel(None);
+        assert!(handshake(&socket, &mut client).await?);
+
+        // without sending anything else, the server should send a keepalive.
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let mut recv_buf = vec![0u8; MAX_PACKET_SIZE];
+        let n = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut recv_buf)).await??;
+        assert!(matches!(
+            client.decapsulate(None, &recv_buf[..n], &mut buf),
+            TunnResult::Done
+        ));
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multiple_sockets() -> Result<()> {
+        let (events_tx, _events_rx) = tokio::sync::mpsc::channel(1);
+        let (_commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (task, (addrs, _)) = WireGuardConf {
+            listen_addrs: vec!["127.0.0.1:0".parse()?, "127.0.0.1:0".parse()?],
+            private_key: StaticSecret::from(SERVER_KEY),
+            peers: vec![WireGuardPeerConf::new(PublicKey::from(
+                &StaticSecret::from(CLIENT_KEY),
+            ))],
+            tcp_fast_open: false,
+        }
+        .build(events_tx, commands_rx, shutdown_rx)
+        .await?;
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+        let handle = tokio::spawn(task.run());
+
+        // the connected socket only accepts the handshake response if it is sent
+        // from the address we sent the handshake initiation to.
+        for addr in addrs {
+            let socket = UdpSocket::bind("127.0.0.1:0").await?;
+            socket.connect(addr).await?;
+            assert!(handshake(&socket, &mut client_tunnel(None)).await?);
+        }
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recv_from_any_round_robin() -> Result<()> {
+        let sockets = vec![
+            UdpSocket::bind("127.0.0.1:0").await?,
+            UdpSocket::bind("127.0.0.1:0").await?,
+        ];
+        let client = UdpSocket::bind("127.0.0.1:0").await?;
+        for socket in &sockets {
+            for _ in 0..2 {
+                client.send_to(b"x", socket.local_addr()?).await?;
+            }
+        }
+
+        // both sockets have pending datagrams, so they must take turns.
+        let mut next = 0;
+        let mut buf = [0u8; 16];
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            let (idx, r) = recv_from_any(&sockets, &mut next, &mut buf).await;
+            r?;
+            order.push(idx);
+        }
+        assert_eq!(order, [0, 1, 0, 1]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_preshared_key() -> Result<()> {
+        let mut peer = WireGuardPeerConf::new(PublicKey::from(&StaticSecret::from(CLIENT_KEY)));
+        peer.preshared_key = Some([3; 32]);
+        let (socket, shutdown_tx, handle) = start_server(vec![peer]).await?;
+
+        assert!(!handshake(&socket, &mut client_tunnel(None)).await?);
+        assert!(!handshake(&socket, &mut client_tunnel(Some([4; 32]))).await?);
+        assert!(handshake(&socket, &mut client_tunnel(Some([3; 32]))).await?);
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rate_limit() -> Result<()> {
+        let peer = WireGuardPeerConf::new(PublicKey::from(&StaticSecret::from(CLIENT_KEY)));
+        let (socket, shutdown_tx, handle) = start_server(vec![peer]).await?;
+
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let TunnResult::WriteToNetwork(init) =
+            client_tunnel(None).format_handshake_initiation(&mut buf, false)
+        else {
+            panic!("expected handshake initiation");
+        };
+        for _ in 0..2 * HANDSHAKE_RATE_LIMIT {
+            socket.send(init).await?;
+        }
+
+        // once the server is under load, it should answer with a cookie reply.
+        let mut recv_buf = vec![0u8; MAX_PACKET_SIZE];
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let n = socket.recv(&mut recv_buf).await?;
+                if matches!(
+                    Tunn::parse_incoming_packet(&recv_buf[..n]),
+                    Ok(Packet::PacketCookieReply(_))
+                ) {
+                    return Ok::<_, anyhow::Error>(());
+                }
+            }
+        })
+        .await??;
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_management() -> Result<()> {
+        let (events_tx, _events_rx) = tokio::sync::mpsc::channel(1);
+        let (_commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (task, (_, peers_tx)) = WireGuardConf {
+            listen_addrs: vec!["127.0.0.1:0".parse()?],
+            private_key: StaticSecret::from([1; 32]),
+            peers: vec![],
+            tcp_fast_open: false,
+        }
+        .build(events_tx, commands_rx, shutdown_rx)
+        .await?;
+        let handle = tokio::spawn(task.run());
+
+        let list_peers = || async {
+            let (tx, rx) = oneshot::channel();
+            peers_tx.send(WireGuardCommand::ListPeers(tx)).unwrap();
+            rx.await.unwrap()
+        };
+
+        let mut peer = WireGuardPeerConf::new(PublicKey::from([2; 32]));
+        peer.allowed_ips = vec![parse_allowed_ip("10.0.0.2")?];
+        peer.endpoint = Some("127.0.0.1:51820".parse()?);
+        peers_tx.send(WireGuardCommand::AddPeer(peer.clone()))?;
+        peers_tx.send(WireGuardCommand::AddPeer(WireGuardPeerConf::new(
+            PublicKey::from([3; 32]),
+        )))?;
+
+        let peers = list_peers().await;
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].public_key, PublicKey::from([2; 32]));
+        assert_eq!(peers[0].allowed_ips, peer.allowed_ips);
+        assert_eq!(peers[0].endpoint, peer.endpoint);
+        assert_eq!(peers[0].last_handshake, None);
+        assert_eq!(peers[0].rx_bytes, 0);
+        assert_eq!(peers[1].public_key, PublicKey::from([3; 32]));
+
+        // re-adding a peer replaces it.
+        peer.allowed_ips = vec![];
+        peers_tx.send(WireGuardCommand::AddPeer(peer))?;
+        peers_tx.send(WireGuardCommand::RemovePeer(PublicKey::from([3; 32])))?;
+
+        let peers = list_peers().await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].public_key, PublicKey::from([2; 32]));
+        assert!(peers[0].allowed_ips.is_empty());
+
+        let (tx, rx) = oneshot::channel();
+        peers_tx.send(WireGuardCommand::DroppedPackets(tx))?;
+        assert_eq!(rx.await?, 0);
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+
+    /// Echo data back in uppercase, and say goodbye once the client has closed its side.
+    async fn echo(connection_id: ConnectionId, commands_tx: UnboundedSender<TransportCommand>) {
+        loop {
+            let (tx, rx) = oneshot::channel();
+            if commands_tx
+                .send(TransportCommand::ReadData(connection_id, 4096, tx))
+                .is_err()
+            {
+                return;
+            }
+            let data = rx.await.unwrap_or_default();
+            if data.is_empty() {
+                let _ =
+                    commands_tx.send(TransportCommand::WriteData(connection_id, b"bye".to_vec()));
+                let _ = commands_tx.send(TransportCommand::CloseConnection(connection_id, false));
+                return;
+            }
+            let _ = commands_tx.send(TransportCommand::WriteData(
+                connection_id,
+                data.to_ascii_uppercase(),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_echo_through_tunnel() -> Result<()> {
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(16);
+        let (commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (task, (addrs, _)) = WireGuardConf {
+            listen_addrs: vec!["127.0.0.1:0".parse()?],
+            private_key: StaticSecret::from(SERVER_KEY),
+            peers: vec![WireGuardPeerConf::new(PublicKey::from(
+                &StaticSecret::from(CLIENT_KEY),
+            ))],
+            tcp_fast_open: false,
+        }
+        .build(events_tx, commands_rx, shutdown_rx)
+        .await?;
+        let handle = tokio::spawn(task.run());
+        tokio::spawn(async move {
+            while let Some(event) = events_rx.recv().await {
+                if let TransportEvent::ConnectionEstablished { connection_id, .. } = event {
+                    tokio::spawn(echo(connection_id, commands_tx.clone()));
+                }
+            }
+        });
+
+        let mut client = WireGuardClient::connect(ClientConf {
+            private_key: StaticSecret::from(CLIENT_KEY),
+            peer_public_key: PublicKey::from(&StaticSecret::from(SERVER_KEY)),
+            preshared_key: None,
+            endpoint: addrs[0],
+            address: "10.0.0.1".parse()?,
+        })
+        .await?;
+
+        let dst: SocketAddr = "10.0.0.42:53".parse()?;
+        client.send_udp(dst, b"hello").await?;
+        assert_eq!(client.recv_udp().await?, (dst, b"HELLO".to_vec()));
+
+        let mut conn = client.connect_tcp("10.0.0.42:80".parse()?).await?;
+        conn.write_all(b"hello world").await?;
+        assert_eq!(conn.read_exact(11).await?, b"HELLO WORLD");
+
+        // the server does not send anything unless we do.
+        conn.set_timeout(Duration::from_millis(500));
+        assert!(conn.read().await.is_err());
+
+        // after a half-close, we still receive data until the server closes its side.
+        conn.set_timeout(Duration::from_secs(5));
+        conn.shutdown().await?;
+        assert_eq!(conn.read_to_end().await?, b"bye");
+        drop(conn);
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+
+    fn ipv4_packet(dst: &str) -> SmolPacket {
+        let mut buf = vec![0u8; 20];
+        let mut packet = Ipv4Packet::new_unchecked(&mut buf[..]);
+        packet.set_version(4);
+        packet.set_header_len(20);
+        packet.set_total_len(20);
+        packet.set_hop_limit(64);
+        packet.set_next_header(smoltcp::wire::IpProtocol::Udp);
+        packet.set_src_addr("10.0.0.53".parse().unwrap());
+        packet.set_dst_addr(dst.parse().unwrap());
+        packet.fill_checksum();
+        SmolPacket::try_from(buf).unwrap()
+    }
+
+    async fn build_task(peers: Vec<WireGuardPeerConf>) -> Result<WireGuardTask> {
+        let (events_tx, _events_rx) = tokio::sync::mpsc::channel(1);
+        let (_commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (task, _) = WireGuardConf {
+            listen_addrs: vec!["127.0.0.1:0".parse()?],
+            private_key: StaticSecret::from(SERVER_KEY),
+            peers,
+            tcp_fast_open: false,
+        }
+        .build(events_tx, commands_rx, shutdown_rx)
+        .await?;
+        Ok(task)
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_without_endpoint() -> Result<()> {
+        // a single peer that has never connected.
+        let mut task = build_task(vec![WireGuardPeerConf::new(PublicKey::from([2; 32]))]).await?;
+        task.process_outgoing_packet(ipv4_packet("10.0.0.1"))
+            .await?;
+        assert_eq!(task.dropped_packets, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_multiple_peers() -> Result<()> {
+        let endpoint = UdpSocket::bind("127.0.0.1:0").await?;
+
+        let mut a = WireGuardPeerConf::new(PublicKey::from([2; 32]));
+        a.allowed_ips = vec![parse_allowed_ip("10.0.0.1")?];
+        a.endpoint = Some(endpoint.local_addr()?);
+        let mut b = WireGuardPeerConf::new(PublicKey::from([3; 32]));
+        b.allowed_ips = vec![parse_allowed_ip("10.0.0.2")?];
+        let c = WireGuardPeerConf::new(PublicKey::from([4; 32]));
+        let mut task = build_task(vec![a, b, c]).await?;
+
+        // peer a has a static endpoint: the packet is queued and a handshake is initiated.
+        task.process_outgoing_packet(ipv4_packet("10.0.0.1"))
+            .await?;
+        assert_eq!(task.dropped_packets, 0);
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let (n, _) =
+            tokio::time::timeout(Duration::from_secs(5), endpoint.recv_from(&mut buf)).await??;
+        assert!(matches!(
+            Tunn::parse_incoming_packet(&buf[..n]),
+            Ok(Packet::HandshakeInit(_))
+        ));
+
+        // peer b has no endpoint.
+        task.process_outgoing_packet(ipv4_packet("10.0.0.2"))
+            .await?;
+        assert_eq!(task.dropped_packets, 1);
+
+        // with multiple peers, there is no fallback for unknown destinations.
+        task.process_outgoing_packet(ipv4_packet("10.0.0.3"))
+            .await?;
+        assert_eq!(task.dropped_packets, 2);
+        task.process_outgoing_packet(ipv4_packet("10.0.0.3"))
+            .await?;
+        assert_eq!(task.dropped_packets, 3);
+        assert_eq!(task.dropped_destinations.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_peer_index_wraps_around() -> Result<()> {
+        let mut task = build_task(vec![WireGuardPeerConf::new(PublicKey::from([2; 32]))]).await?;
+        assert!(task.peers_by_idx.contains_key(&0));
+
+        task.next_peer_index = MAX_PEER_INDEX;
+        task.add_peer(WireGuardPeerConf::new(PublicKey::from([3; 32])));
+        task.add_peer(WireGuardPeerConf::new(PublicKey::from([4; 32])));
+
+        // index 0 is still in use by the first peer.
+        let mut indices: Vec<u32> = task.peers_by_idx.keys().copied().collect();
+        indices.sort();
+        assert_eq!(indices, [0, 1, MAX_PEER_INDEX]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_outgoing_single_peer_allowed_ips() -> Result<()> {
+        let endpoint = UdpSocket::bind("127.0.0.1:0").await?;
+
+        let mut peer = WireGuardPeerConf::new(PublicKey::from([2; 32]));
+        peer.allowed_ips = vec![parse_allowed_ip("10.0.0.1")?];
+        peer.endpoint = Some(endpoint.local_addr()?);
+        let mut task = build_task(vec![peer]).await?;
+
+        task.process_outgoing_packet(ipv4_packet("10.0.0.1"))
+            .await?;
+        assert_eq!(task.dropped_packets, 0);
+
+        // the only peer does not get packets outside of its allowed IPs.
+        task.process_outgoing_packet(ipv4_packet("10.0.0.2"))
+            .await?;
+        assert_eq!(task.dropped_packets, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use boringtun::noise::{errors::WireGuardError, Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use pretty_hex::pretty_hex;
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::{Receiver, Sender, UnboundedReceiver};
+use tokio::time::MissedTickBehavior;
+
+use crate::messages::{
+    NetworkCommand, NetworkEvent, SmolPacket, TransportCommand, TransportEvent, TunnelInfo,
+    WireGuardPeerTunnelInfo,
+};
+use crate::network::{add_network_layer, MAX_PACKET_SIZE};
+use crate::packet_sources::udp::{create_and_bind_udp_socket, remote_host_closed_conn};
+use crate::packet_sources::wireguard::{
+    default_peer_name, peer_tunnel_info, send_datagram, TIMER_INTERVAL, WG_HEADER_SIZE,
+};
+use crate::packet_sources::{PacketSourceConf, PacketSourceTask};
+use crate::shutdown;
+
+/// Configuration for a WireGuard client that connects to an existing WireGuard network.
+///
+/// In contrast to the WireGuard server, we initiate the handshake with a single peer.
+/// All traffic that the peer routes to us through the tunnel is intercepted, whatever its
+/// destination. There is therefore no tunnel address: the `Address` of `wg-quick` files is ignored.
+pub struct WireGuardClientConf {
+    pub private_key: StaticSecret,
+    pub peer_public_key: PublicKey,
+    pub preshared_key: Option<[u8; 32]>,
+    /// The peer's address as `host:port`. The host may be a domain name.
+    pub endpoint: String,
+    /// Persistent keepalive interval in seconds.
+    /// This should be set so that the peer can reach us through NATs.
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl PacketSourceConf for WireGuardClientConf {
+    type Task = WireGuardClientTask;
+    type Data = SocketAddr;
+
+    fn name(&self) -> &'static str {
+        "WireGuard client"
+    }
+
+    async fn build(
+        self,
+        transport_events_tx: Sender<TransportEvent>,
+        transport_commands_rx: UnboundedReceiver<TransportCommand>,
+        shutdown: shutdown::Receiver,
+    ) -> Result<(Self::Task, Self::Data)> {
+        let endpoint = tokio::net::lookup_host(&self.endpoint)
+            .await
+            .with_context(|| format!("Failed to resolve WireGuard endpoint {}", self.endpoint))?
+            .next()
+            .with_context(|| {
+                format!("No address found for WireGuard endpoint {}", self.endpoint)
+            })?;
+
+        let bind_addr = if endpoint.is_ipv4() {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        };
+        let socket = create_and_bind_udp_socket(bind_addr)?;
+        let local_addr = socket.local_addr()?;
+
+        log::debug!("WireGuard client connecting to {endpoint} from {local_addr} ...");
+
+        let peer_info = Arc::new(peer_tunnel_info(
+            &self.peer_public_key,
+            &default_peer_name(&self.peer_public_key),
+            &[(endpoint, SystemTime::now())],
+        ));
+        let tunnel = Tunn::new(
+            self.private_key,
+            self.peer_public_key,
+            self.preshared_key,
+            self.persistent_keepalive,
+            0,
+            None,
+        );
+
+        let (network_task_handle, net_tx, net_rx) =
+            add_network_layer(transport_events_tx, transport_commands_rx, shutdown, false);
+
+        Ok((
+            WireGuardClientTask {
+                socket,
+                local_addr,
+                endpoint,
+                tunnel,
+                peer_info,
+                wg_buf: vec![0u8; MAX_PACKET_SIZE],
+
+                net_tx,
+                net_rx,
+                network_task_handle,
+            },
+            local_addr,
+        ))
+    }
+}
+
+pub struct WireGuardClientTask {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    endpoint: SocketAddr,
+    tunnel: Tunn,
+    peer_info: Arc<WireGuardPeerTunnelInfo>,
+    wg_buf: Vec<u8>,
+
+    net_tx: Sender<NetworkEvent>,
+    net_rx: Receiver<NetworkCommand>,
+    network_task_handle: tokio::task::JoinHandle<Result<()>>,
+}
+
+impl PacketSourceTask for WireGuardClientTask {
+    async fn run(mut self) -> Result<()> {
+        let mut udp_buf = vec![0; MAX_PACKET_SIZE];
+
+        // Initiate the handshake right away, so that the peer learns our endpoint
+        // and can route traffic to us before we have sent anything.
+        if let TunnResult::WriteToNetwork(buf) = self
+            .tunnel
+            .format_handshake_initiation(&mut self.wg_buf, false)
+        {
+            send_datagram(&self.socket, buf, self.endpoint).await;
+        }
+
+        let mut timer = tokio::time::interval(TIMER_INTERVAL);
+        timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                exit = &mut self.network_task_handle => break exit.context("network task panic")?.context("network task error")?,
+                // send keepalives, (re-)initiate handshakes and expire sessions
+                _ = timer.tick() => self.update_timers().await,
+                // wait for WireGuard packets incoming on the UDP socket
+                r = self.socket.recv_from(udp_buf.as_mut_slice()) => {
+                    if remote_host_closed_conn(&r) {
+                        continue;
+                    }
+                    let (len, src_addr) = r.context("UDP recv() failed")?;
+                    if src_addr != self.endpoint {
+                        log::debug!("Ignoring WireGuard datagram from unknown address {src_addr}.");
+                        continue;
+                    }
+                    self.process_incoming_datagram(&udp_buf[..len]).await;
+                },
+                // wait for outgoing IP packets
+                Some(e) = self.net_rx.recv() => {
+                    match e {
+                        NetworkCommand::SendPacket(packet) => {
+                            self.process_outgoing_packet(packet).await;
+                        }
+                    }
+                }
+            }
+        }
+
+        // flush outgoing packet queue before shutdown
+        while let Some(e) = self.net_rx.recv().await {
+            match e {
+                NetworkCommand::SendPacket(packet) => {
+                    self.process_outgoing_packet(packet).await;
+                }
+            }
+        }
+
+        log::debug!("WireGuard client task shutting down.");
+        Ok(())
+    }
+}
+
+impl WireGuardClientTask {
+    async fn update_timers(&mut self) {
+        match self.tunnel.update_timers(&mut self.wg_buf) {
+            TunnResult::Done => {}
+            TunnResult::WriteToNetwork(buf) => {
+                log::trace!("WG::update_timers: WriteToNetwork");
+                send_datagram(&self.socket, buf, self.endpoint).await;
+            }
+            TunnResult::Err(WireGuardError::ConnectionExpired) => {
+                log::debug!("WireGuard session expired, reconnecting.");
+                if let TunnResult::WriteToNetwork(buf) = self
+                    .tunnel
+                    .format_handshake_initiation(&mut self.wg_buf, false)
+                {
+                    send_datagram(&self.socket, buf, self.endpoint).await;
+                }
+            }
+            TunnResult::Err(error) => {
+                log::debug!("WG::update_timers: Err: {error:?}");
+            }
+            TunnResult::WriteToTunnelV4(_, _) | TunnResult::WriteToTunnelV6(_, _) => {
+                log::warn!("WG::update_timers: WriteToTunnel: unexpected event");
+            }
+        }
+    }
+
+    /// process WireGuard datagrams from the peer and forward the decrypted packets.
+    async fn process_incoming_datagram(&mut self, data: &[u8]) {
+        let mut result = self
+            .tunnel
+            .decapsulate(Some(self.endpoint.ip()), data, &mut self.wg_buf);
+
+        while let TunnResult::WriteToNetwork(b) = result {
+            log::trace!("WG::process_incoming_datagram: WriteToNetwork");
+            send_datagram(&self.socket, b, self.endpoint).await;
+
+            // check if there are more things to be handled
+            result = self.tunnel.decapsulate(None, &[0; 0], &mut self.wg_buf);
+        }
+
+        let buf = match result {
+            TunnResult::Done => {
+                log::trace!("WG::process_incoming_datagram: Done");
+                return;
+            }
+            TunnResult::Err(error) => {
+                log::debug!("WG::process_incoming_datagram: Err: {error:?}");
+                return;
+            }
+            TunnResult::WriteToTunnelV4(buf, _) | TunnResult::WriteToTunnelV6(buf, _) => buf,
+            TunnResult::WriteToNetwork(_) => unreachable!(),
+        };
+
+        let packet = match SmolPacket::try_from(buf.to_vec()) {
+            Ok(packet) => packet,
+            Err(error) => {
+                log::warn!("Invalid IP packet: {error}");
+                return;
+            }
+        };
+        log::trace!(
+            "WG::process_incoming_datagram: WriteToTunnel
+            src_addr: {}, dst_addr: {}
+            {}",
+            packet.src_ip(),
+            packet.dst_ip(),
+            pretty_hex(&buf),
+        );
+
+        let event = NetworkEvent::ReceivePacket {
+            packet,
+            tunnel_info: TunnelInfo::WireGuard {
+                src_addr: self.endpoint,
+                dst_addr: self.local_addr,
+                peer: self.peer_info.clone(),
+            },
+        };
+        if self.net_tx.try_send(event).is_err() {
+            log::warn!("Dropping incoming packet, TCP channel is full.")
+        };
+    }
+
+    /// process packets and send the encrypted WireGuard datagrams to the peer.
+    ///
+    /// If there is no active session, boringtun queues the packet and initiates a handshake.
+    async fn process_outgoing_packet(&mut self, packet: SmolPacket) {
+        let packet_bytes = packet.into_inner();
+
+        // Tunn.encapsulate panics if the packet is too big for the buffer
+        if packet_bytes.len() > MAX_PACKET_SIZE - WG_HEADER_SIZE {
+            log::error!(
+                "Unable to send packet, payload too large ({} bytes > {}).",
+                packet_bytes.len(),
+                MAX_PACKET_SIZE - WG_HEADER_SIZE,
+            );
+            return;
+        }
+
+        match self.tunnel.encapsulate(&packet_bytes, &mut self.wg_buf) {
+            TunnResult::Done => {
+                log::trace!("WG::process_outgoing_packet: Done");
+            }
+            TunnResult::Err(error) => {
+                log::error!("WG::process_outgoing_packet: Err: {error:?}");
+            }
+            TunnResult::WriteToNetwork(buf) => {
+                log::trace!("WG::process_outgoing_packet: WriteToNetwork");
+                send_datagram(&self.socket, buf, self.endpoint).await;
+            }
+            TunnResult::WriteToTunnelV4(_, _) | TunnResult::WriteToTunnelV6(_, _) => {
+                log::warn!("WG::process_outgoing_packet: WriteToTunnel: unexpected event");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{IpProtocol, Ipv4Packet, Ipv4Repr, UdpPacket, UdpRepr};
+    use std::time::Duration;
+
+    fn build_ipv4_udp_packet(payload: &[u8]) -> Vec<u8> {
+        let udp_repr = UdpRepr {
+            src_port: 1234,
+            dst_port: 5353,
+        };
+        let ip_repr = Ipv4Repr {
+            src_addr: Ipv4Addr::new(10, 0, 0, 1),
+            dst_addr: Ipv4Addr::new(10, 0, 0, 2),
+            next_header: IpProtocol::Udp,
+            payload_len: udp_repr.header_len() + payload.len(),
+            hop_limit: 64,
+        };
+        let buf = vec![0u8; ip_repr.buffer_len() + ip_repr.payload_len];
+        let mut ip_packet = Ipv4Packet::new_unchecked(buf);
+        ip_repr.emit(&mut ip_packet, &ChecksumCapabilities::default());
+        udp_repr.emit(
+            &mut UdpPacket::new_unchecked(ip_packet.payload_mut()),
+            &ip_repr.src_addr.into(),
+            &ip_repr.dst_addr.into(),
+            payload.len(),
+            |buf| buf.copy_from_slice(payload),
+            &ChecksumCapabilities::default(),
+        );
+        ip_packet.into_inner()
+    }
+
+    async fn recv(socket: &UdpSocket, buf: &mut [u8]) -> Result<(usize, SocketAddr)> {
+        Ok(tokio::time::timeout(Duration::from_secs(5), socket.recv_from(buf)).await??)
+    }
+
+    #[tokio::test]
+    async fn test_client() -> Result<()> {
+        let server_private_key = StaticSecret::from([1; 32]);
+        let client_private_key = StaticSecret::from([2; 32]);
+
+        let server_socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let mut server = Tunn::new(
+            server_private_key.clone(),
+            PublicKey::from(&client_private_key),
+            None,
+            None,
+            0,
+            None,
+        );
+
+        let (events_tx, mut events_rx) = tokio::sync::mpsc::channel(1);
+        let (_commands_tx, commands_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (task, _) = WireGuardClientConf {
+            private_key: client_private_key,
+            peer_public_key: PublicKey::from(&server_private_key),
+            preshared_key: None,
+            endpoint: server_socket.local_addr()?.to_string(),
+            persistent_keepalive: None,
+        }
+        .build(events_tx, commands_rx, shutdown_rx)
+        .await?;
+        let handle = tokio::spawn(task.run());
+
+        let mut buf = vec![0u8; MAX_PACKET_SIZE];
+        let mut recv_buf = vec![0u8; MAX_PACKET_SIZE];
+        // the client initiates the handshake, and confirms the session with a keepalive.
+        let (n, client_addr) = recv(&server_socket, &mut recv_buf).await?;
+        let TunnResult::WriteToNetwork(response) =
+            server.decapsulate(Some(client_addr.ip()), &recv_buf[..n], &mut buf)
+        else {
+            panic!("expected handshake response");
+        };
+        server_socket.send_to(response, client_addr).await?;
+        let (n, _) = recv(&server_socket, &mut recv_buf).await?;
+        assert!(matches!(
+            server.decapsulate(Some(client_addr.ip()), &recv_buf[..n], &mut buf),
+            TunnResult::Done
+        ));
+
+        // packets routed to the client are intercepted.
+        let packet = build_ipv4_udp_packet(b"hello world!");
+        let TunnResult::WriteToNetwork(datagram) = server.encapsulate(&packet, &mut buf) else {
+            panic!("expected data packet");
+        };
+        server_socket.send_to(datagram, client_addr).await?;
+
+        let event = tokio::time::timeout(Duration::from_secs(5), events_rx.recv())
+            .await?
+            .unwrap();
+        let TransportEvent::ConnectionEstablished {
+            src_addr, dst_addr, ..
+        } = event
+        else {
+            panic!("expected ConnectionEstablished event");
+        };
+        assert_eq!(src_addr, "10.0.0.1:1234".parse()?);
+        assert_eq!(dst_addr, "10.0.0.2:5353".parse()?);
+
+        shutdown_tx.send()?;
+        handle.await??;
+        Ok(())
+    }
+}
@@ -1,17 +1,21 @@
 pub use image;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+use crate::intercept_conf;
+use crate::intercept_conf::PID;
+
 #[cfg(any(target_os = "linux", target_os = "macos"))]
 mod nix_list;
 #[cfg(any(target_os = "linux", target_os = "macos"))]
-pub use self::nix_list::active_executables;
+pub use self::nix_list::{active_executables, parent_pids, process_executable};
 
 #[cfg(windows)]
 mod windows_list;
 #[cfg(windows)]
-pub use self::windows_list::active_executables;
-#[cfg(windows)]
 pub use self::windows_list::get_process_name;
+#[cfg(windows)]
+pub use self::windows_list::{active_executables, parent_pids, process_executable};
 
 #[cfg(target_os = "macos")]
 mod macos_icons;
@@ -33,6 +37,40 @@ pub struct ProcessInfo {
 
 pub type ProcessList = Vec<ProcessInfo>;
 
+/// Walk a PID -> parent PID table (see `parent_pids`) up from a process,
+/// returning the PIDs of its parent, grandparent, and so on.
+pub fn ancestors(parents: &HashMap<PID, PID>, pid: PID) -> Vec<PID> {
+    let mut ancestors = Vec::new();
+    let mut current = pid;
+    while let Some(&parent) = parents.get(&current) {
+        // PID 0 is not a real process. Parent PIDs can also be stale and form cycles,
+        // because Windows does not reparent orphans and PIDs get reused.
+        if parent == 0 || parent == pid || ancestors.contains(&parent) {
+            break;
+        }
+        ancestors.push(parent);
+        current = parent;
+    }
+    ancestors
+}
+
+/// Look up a running process to match it against an intercept spec.
+/// `parents` is only needed if the spec uses process trees, see [parent_pids].
+#[cfg(any(windows, target_os = "linux", target_os = "macos"))]
+pub fn process_info(pid: PID, parents: Option<&HashMap<PID, PID>>) -> intercept_conf::ProcessInfo {
+    let executable = process_executable(pid);
+    intercept_conf::ProcessInfo {
+        pid,
+        process_name: executable
+            .as_ref()
+            .map(|e| e.to_string_lossy().into_owned()),
+        executable,
+        ancestors: parents
+            .map(|parents| ancestors(parents, pid))
+            .unwrap_or_default(),
+    }
+}
+
 #[cfg(any(windows, target_os = "macos"))]
 pub static ICON_CACHE: std::sync::LazyLock<std::sync::Mutex<IconCache>> =
     std::sync::LazyLock::new(|| std::sync::Mutex::new(IconCache::default()));
@@ -56,3 +94,33 @@ pub mod bench {
         enumerate_pids, get_display_name, get_is_critical, get_process_name,
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ancestors() {
+        let parents = HashMap::from([(4, 3), (3, 2), (2, 1), (1, 0), (7, 8), (8, 7)]);
+        assert_eq!(ancestors(&parents, 4), vec![3, 2, 1]);
+        assert_eq!(ancestors(&parents, 1), vec![]);
+        assert_eq!(ancestors(&parents, 5), vec![]);
+        assert_eq!(ancestors(&parents, 7), vec![8]);
+    }
+
+    #[test]
+    fn test_process_info() {
+        let pid = std::process::id();
+        let process_info = process_info(pid, None);
+        assert_eq!(process_info.pid, pid);
+        assert_eq!(process_info.executable, std::env::current_exe().ok());
+        assert!(process_info.ancestors.is_empty());
+
+        let parents = parent_pids().unwrap();
+        let conf =
+            intercept_conf::InterceptConf::try_from(format!("tree:{}", parents[&pid]).as_str())
+                .unwrap();
+        assert!(!conf.should_intercept(&process_info, None));
+        assert!(conf.should_intercept(&super::process_info(pid, Some(&parents)), None));
+    }
+}
@@ -3,8 +3,8 @@ use crate::processes::{ProcessInfo, ProcessList};
 use anyhow::Result;
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-use sysinfo::{Process, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
+use std::path::{Path, PathBuf};
+use sysinfo::{Pid, Process, ProcessRefreshKind, ProcessesToUpdate, System, UpdateKind};
 
 #[cfg(target_os = "linux")]
 use std::ops::Deref;
@@ -24,8 +24,7 @@ pub fn active_executables() -> Result<ProcessList> {
             .with_user(UpdateKind::OnlyIfNotSet),
     );
     for (pid, process) in sys.processes() {
-        // process.exe() will return empty path if there was an error while trying to read /proc/<pid>/exe.
-        if let Some(path) = process.exe() {
+        if let Some(path) = executable(process) {
             let pid = pid.as_u32();
             let executable = path.to_path_buf();
             match executables.entry(executable) {
@@ -59,6 +58,34 @@ pub fn active_executables() -> Result<ProcessList> {
     Ok(executables.into_values().collect())
 }
 
+/// The executable of a running process.
+pub fn process_executable(pid: PID) -> Option<PathBuf> {
+    let pid = Pid::from_u32(pid);
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(
+        ProcessesToUpdate::Some(&[pid]),
+        true,
+        ProcessRefreshKind::nothing().with_exe(UpdateKind::OnlyIfNotSet),
+    );
+    executable(sys.process(pid)?).map(Path::to_path_buf)
+}
+
+fn executable(process: &Process) -> Option<&Path> {
+    // process.exe() will return empty path if there was an error while trying to read /proc/<pid>/exe.
+    process.exe().filter(|path| !path.as_os_str().is_empty())
+}
+
+/// PID -> parent PID for all running processes.
+pub fn parent_pids() -> Result<HashMap<PID, PID>> {
+    let mut sys = System::new();
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, ProcessRefreshKind::nothing());
+    Ok(sys
+        .processes()
+        .iter()
+        .filter_map(|(pid, process)| Some((pid.as_u32(), process.parent()?.as_u32())))
+        .collect())
+}
+
 pub fn visible_windows() -> Result<HashSet<PID>> {
     #[cfg(target_os = "macos")]
     return macos_visible_windows();
@@ -153,6 +180,23 @@ mod tests {
         dbg!(lst.len());
     }
 
+    #[test]
+    fn executable() {
+        assert_eq!(
+            process_executable(std::process::id()),
+            std::env::current_exe().ok()
+        );
+    }
+
+    #[test]
+    fn parent_pid() {
+        let parents = parent_pids().unwrap();
+        assert_eq!(
+            parents.get(&std::process::id()).copied(),
+            Some(std::os::unix::process::parent_id())
+        );
+    }
+
     #[cfg(target_os = "macos")]
     #[test]
     fn visible_windows_list() {
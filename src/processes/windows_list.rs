@@ -15,6 +15,9 @@ use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED};
 use windows::Win32::Storage::FileSystem::{
     GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW,
 };
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+};
 use windows::Win32::System::ProcessStatus::EnumProcesses;
 use windows::Win32::System::Threading::{
     IsProcessCritical, OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_NATIVE,
@@ -52,6 +55,11 @@ unsafe fn process_name(handle: HANDLE) -> Result<PathBuf> {
     Ok(PathBuf::from(OsString::from_wide(path.as_wide())))
 }
 
+/// The executable of a running process.
+pub fn process_executable(pid: PID) -> Option<PathBuf> {
+    get_process_name(pid).ok()
+}
+
 pub fn get_is_critical(pid: PID) -> Result<bool> {
     unsafe {
         let handle = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid)?;
@@ -84,6 +92,28 @@ pub fn enumerate_pids() -> Result<Vec<PID>> {
     Ok(pids)
 }
 
+/// PID -> parent PID for all running processes.
+///
+/// Windows does not reparent orphaned processes, so the parent PID may refer to a process that
+/// has exited, or to an unrelated process that reused the PID.
+pub fn parent_pids() -> Result<HashMap<PID, PID>> {
+    let mut parents = HashMap::new();
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
+        let mut entry = PROCESSENTRY32W {
+            dwSize: size_of::<PROCESSENTRY32W>() as u32,
+            ..Default::default()
+        };
+        let mut next = Process32FirstW(snapshot, &mut entry);
+        while next.is_ok() {
+            parents.insert(entry.th32ProcessID, entry.th32ParentProcessID);
+            next = Process32NextW(snapshot, &mut entry);
+        }
+        CloseHandle(snapshot)?;
+    }
+    Ok(parents)
+}
+
 pub static DISPLAY_NAME_CACHE: LazyLock<Mutex<DisplayNameCache>> =
     LazyLock::new(|| Mutex::new(DisplayNameCache::default()));
 
@@ -310,6 +340,12 @@ mod tests {
         assert!(name.as_os_str().to_string_lossy().contains("mitmproxy"));
     }
 
+    #[test]
+    fn parent_pids() {
+        let parents = super::parent_pids().unwrap();
+        assert!(parents.contains_key(&std::process::id()));
+    }
+
     #[test]
     fn get_executable_name() {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
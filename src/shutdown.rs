@@ -1,11 +1,31 @@
 use anyhow::Result;
 use std::fmt::{Debug, Formatter};
+use std::time::Duration;
 
 use tokio::sync::watch;
 use tokio::task::JoinSet;
 
+#[derive(Debug)]
+pub struct Sender(watch::Sender<Duration>);
+
+impl Sender {
+    /// Request an immediate shutdown.
+    pub fn send(&self) -> Result<(), watch::error::SendError<Duration>> {
+        self.send_with_drain_timeout(Duration::ZERO)
+    }
+
+    /// Request a graceful shutdown. Tasks may spend up to `drain_timeout` flushing
+    /// pending data of existing connections before they exit.
+    pub fn send_with_drain_timeout(
+        &self,
+        drain_timeout: Duration,
+    ) -> Result<(), watch::error::SendError<Duration>> {
+        self.0.send(drain_timeout)
+    }
+}
+
 #[derive(Clone)]
-pub struct Receiver(watch::Receiver<()>);
+pub struct Receiver(watch::Receiver<Duration>);
 
 impl Receiver {
     pub async fn recv(&mut self) {
@@ -16,6 +36,11 @@ impl Receiver {
     pub fn is_shutting_down(&self) -> bool {
         self.0.has_changed().unwrap_or(true)
     }
+
+    /// The time tasks may spend draining pending data once shutdown has been requested.
+    pub fn drain_timeout(&self) -> Duration {
+        *self.0.borrow()
+    }
 }
 
 impl Debug for Receiver {
@@ -26,12 +51,12 @@ impl Debug for Receiver {
     }
 }
 
-pub fn channel() -> (watch::Sender<()>, Receiver) {
-    let (tx, rx) = watch::channel(());
-    (tx, Receiver(rx))
+pub fn channel() -> (Sender, Receiver) {
+    let (tx, rx) = watch::channel(Duration::ZERO);
+    (Sender(tx), Receiver(rx))
 }
 
-pub async fn shutdown_task(mut tasks: JoinSet<Result<()>>, shutdown_done: watch::Sender<()>) {
+pub async fn shutdown_task(mut tasks: JoinSet<Result<()>>, shutdown_done: Sender) {
     while let Some(task) = tasks.join_next().await {
         match task {
             Ok(Ok(())) => (),
@@ -49,7 +74,7 @@ pub async fn shutdown_task(mut tasks: JoinSet<Result<()>>, shutdown_done: watch:
             }
         }
     }
-    shutdown_done.send(()).ok();
+    shutdown_done.send().ok();
 }
 
 #[cfg(test)]
@@ -62,7 +87,7 @@ mod tests {
         let rx2 = rx1.clone();
         assert!(!rx1.is_shutting_down());
         assert!(!rx2.is_shutting_down());
-        tx.send(()).unwrap();
+        tx.send().unwrap();
         rx1.recv().await;
         assert!(rx1.is_shutting_down());
         assert!(rx2.is_shutting_down());
@@ -70,5 +95,15 @@ mod tests {
         assert!(rx2.is_shutting_down());
         rx1.recv().await;
         assert!(rx1.is_shutting_down());
+        assert_eq!(rx1.drain_timeout(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn shutdown_channel_drain_timeout() {
+        let (tx, mut rx) = channel();
+        tx.send_with_drain_timeout(Duration::from_secs(3)).unwrap();
+        rx.recv().await;
+        assert!(rx.is_shutting_down());
+        assert_eq!(rx.drain_timeout(), Duration::from_secs(3));
     }
 }
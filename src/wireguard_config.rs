@@ -0,0 +1,431 @@
+//! Parsing and generation of `wg-quick` configuration files.
+//!
+//! Only the keys relevant for mitmproxy are interpreted. Other keys (e.g. `PostUp` or `Table`)
+//! are accepted, but ignored.
+
+use std::fmt;
+use std::fmt::Write as _;
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use data_encoding::BASE64;
+use smoltcp::wire::IpCidr;
+
+use crate::packet_sources::wireguard::{parse_allowed_ip, WireGuardConf, WireGuardPeerConf};
+use crate::packet_sources::wireguard_client::WireGuardClientConf;
+
+/// The default listen port for WireGuard.
+pub const DEFAULT_LISTEN_PORT: u16 = 51820;
+
+/// A `wg-quick` configuration file with an `[Interface]` section and any number of `[Peer]`s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WgQuickConfig {
+    pub interface: InterfaceConfig,
+    pub peers: Vec<PeerConfig>,
+}
+
+/// The `[Interface]` section of a `wg-quick` configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceConfig {
+    pub private_key: [u8; 32],
+    pub address: Vec<IpCidr>,
+    pub listen_port: Option<u16>,
+    /// DNS servers and search domains.
+    pub dns: Vec<String>,
+    pub mtu: Option<u16>,
+}
+
+/// A `[Peer]` section of a `wg-quick` configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerConfig {
+    pub public_key: [u8; 32],
+    pub preshared_key: Option<[u8; 32]>,
+    pub allowed_ips: Vec<IpCidr>,
+    /// The peer's address as `host:port`. The host may be a domain name.
+    pub endpoint: Option<String>,
+    pub persistent_keepalive: Option<u16>,
+}
+
+impl PeerConfig {
+    pub fn new(public_key: [u8; 32]) -> Self {
+        Self {
+            public_key,
+            preshared_key: None,
+            allowed_ips: Vec::new(),
+            endpoint: None,
+            persistent_keepalive: None,
+        }
+    }
+}
+
+enum Section {
+    Interface,
+    Peer(PeerConfig),
+}
+
+impl WgQuickConfig {
+    pub fn parse(config: &str) -> Result<Self> {
+        let mut private_key = None;
+        let mut interface = InterfaceConfig {
+            private_key: [0; 32],
+            address: Vec::new(),
+            listen_port: None,
+            dns: Vec::new(),
+            mtu: None,
+        };
+        let mut peers = Vec::new();
+        let mut seen_interface = false;
+        let mut section = None;
+
+        for (lineno, line) in config.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = || format!("invalid WireGuard config (line {}): {line}", lineno + 1);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if let Some(Section::Peer(peer)) = section.take() {
+                    peers.push(peer);
+                }
+                section = match name.trim().to_ascii_lowercase().as_str() {
+                    "interface" => {
+                        ensure!(!seen_interface, "duplicate [Interface] section");
+                        seen_interface = true;
+                        Some(Section::Interface)
+                    }
+                    "peer" => Some(Section::Peer(PeerConfig::new([0; 32]))),
+                    _ => bail!("unknown section: [{name}]"),
+                };
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').with_context(err)?;
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim();
+
+            match section.as_mut().with_context(err)? {
+                Section::Interface => match key.as_str() {
+                    "privatekey" => private_key = Some(parse_key(value).with_context(err)?),
+                    "address" => interface
+                        .address
+                        .extend(parse_ip_list(value).with_context(err)?),
+                    "listenport" => {
+                        interface.listen_port = Some(value.parse().ok().with_context(err)?)
+                    }
+                    "dns" => interface.dns.extend(
+                        value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string),
+                    ),
+                    "mtu" => interface.mtu = Some(value.parse().ok().with_context(err)?),
+                    _ => log::debug!("Ignoring unsupported WireGuard interface option: {key}"),
+                },
+                Section::Peer(peer) => match key.as_str() {
+                    "publickey" => peer.public_key = parse_key(value).with_context(err)?,
+                    "presharedkey" => {
+                        peer.preshared_key = Some(parse_key(value).with_context(err)?)
+                    }
+                    "allowedips" => peer
+                        .allowed_ips
+                        .extend(parse_ip_list(value).with_context(err)?),
+                    "endpoint" => {
+                        value
+                            .rsplit_once(':')
+                            .and_then(|(_, port)| port.parse::<u16>().ok())
+                            .with_context(err)?;
+                        peer.endpoint = Some(value.to_string());
+                    }
+                    "persistentkeepalive" => {
+                        peer.persistent_keepalive = if value.eq_ignore_ascii_case("off") {
+                            None
+                        } else {
+                            Some(value.parse().ok().with_context(err)?).filter(|k| *k > 0)
+                        }
+                    }
+                    _ => log::debug!("Ignoring unsupported WireGuard peer option: {key}"),
+                },
+            }
+        }
+        if let Some(Section::Peer(peer)) = section {
+            peers.push(peer);
+        }
+
+        interface.private_key = private_key.context("missing PrivateKey in [Interface] section")?;
+        for peer in &peers {
+            ensure!(
+                peer.public_key != [0; 32],
+                "missing PublicKey in [Peer] section"
+            );
+        }
+
+        Ok(Self { interface, peers })
+    }
+
+    /// Build the configuration for a WireGuard server that listens on `host`.
+    /// Peer endpoints need to be IP addresses.
+    pub fn to_wireguard_conf(&self, host: IpAddr) -> Result<WireGuardConf> {
+        let peers = self
+            .peers
+            .iter()
+            .map(|peer| {
+                let endpoint = peer
+                    .endpoint
+                    .as_deref()
+                    .map(|e| {
+                        e.parse::<SocketAddr>()
+                            .with_context(|| format!("peer endpoint is not an IP address: {e}"))
+                    })
+                    .transpose()?;
+                Ok(WireGuardPeerConf {
+                    public_key: PublicKey::from(peer.public_key),
+                    name: None,
+                    allowed_ips: peer.allowed_ips.clone(),
+                    preshared_key: peer.preshared_key,
+                    persistent_keepalive: peer.persistent_keepalive,
+                    endpoint,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(WireGuardConf {
+            listen_addrs: vec![SocketAddr::new(
+                host,
+                self.interface.listen_port.unwrap_or(DEFAULT_LISTEN_PORT),
+            )],
+            private_key: StaticSecret::from(self.interface.private_key),
+            peers,
+            tcp_fast_open: false,
+        })
+    }
+
+    /// Build the configuration for a WireGuard client from a client configuration file,
+    /// which must have a single peer with an endpoint.
+    pub fn to_wireguard_client_conf(&self) -> Result<WireGuardClientConf> {
+        let [peer] = self.peers.as_slice() else {
+            bail!("WireGuard client configuration must have exactly one [Peer] section");
+        };
+        let endpoint = peer
+            .endpoint
+            .clone()
+            .context("missing Endpoint in [Peer] section")?;
+        Ok(WireGuardClientConf {
+            private_key: StaticSecret::from(self.interface.private_key),
+            peer_public_key: PublicKey::from(peer.public_key),
+            preshared_key: peer.preshared_key,
+            endpoint,
+            persistent_keepalive: peer.persistent_keepalive,
+        })
+    }
+
+    /// Render the configuration for the client with the given private key,
+    /// which must belong to one of the peers in this (server) configuration.
+    ///
+    /// The client's addresses are taken from the peer's allowed IPs, which must not be empty,
+    /// and the server's DNS settings are passed on to the client. All traffic is routed through the tunnel.
+    pub fn client_config(&self, client_private_key: [u8; 32], endpoint: &str) -> Result<Self> {
+        let client_public_key = PublicKey::from(&StaticSecret::from(client_private_key));
+        let peer = self
+            .peers
+            .iter()
+            .find(|p| p.public_key == *client_public_key.as_bytes())
+            .ok_or_else(|| anyhow!("no peer with the client's public key"))?;
+
+        let server_public_key = PublicKey::from(&StaticSecret::from(self.interface.private_key));
+        ensure!(
+            !peer.allowed_ips.is_empty(),
+            "peer has no AllowedIPs to use as the client's address"
+        );
+
+        Ok(Self {
+            interface: InterfaceConfig {
+                private_key: client_private_key,
+                address: peer.allowed_ips.clone(),
+                listen_port: None,
+                dns: self.interface.dns.clone(),
+                mtu: self.interface.mtu,
+            },
+            peers: vec![PeerConfig {
+                public_key: *server_public_key.as_bytes(),
+                preshared_key: peer.preshared_key,
+                allowed_ips: vec![parse_allowed_ip("0.0.0.0/0")?, parse_allowed_ip("::/0")?],
+                endpoint: Some(endpoint.to_string()),
+                persistent_keepalive: peer.persistent_keepalive,
+            }],
+        })
+    }
+}
+
+impl fmt::Display for WgQuickConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let interface = &self.interface;
+        writeln!(f, "[Interface]")?;
+        writeln!(f, "PrivateKey = {}", BASE64.encode(&interface.private_key))?;
+        if !interface.address.is_empty() {
+            writeln!(f, "Address = {}", join(&interface.address))?;
+        }
+        if let Some(port) = interface.listen_port {
+            writeln!(f, "ListenPort = {port}")?;
+        }
+        if !interface.dns.is_empty() {
+            writeln!(f, "DNS = {}", interface.dns.join(", "))?;
+        }
+        if let Some(mtu) = interface.mtu {
+            writeln!(f, "MTU = {mtu}")?;
+        }
+        for peer in &self.peers {
+            writeln!(f)?;
+            writeln!(f, "[Peer]")?;
+            writeln!(f, "PublicKey = {}", BASE64.encode(&peer.public_key))?;
+            if let Some(psk) = &peer.preshared_key {
+                writeln!(f, "PresharedKey = {}", BASE64.encode(psk))?;
+            }
+            if !peer.allowed_ips.is_empty() {
+                writeln!(f, "AllowedIPs = {}", join(&peer.allowed_ips))?;
+            }
+            if let Some(endpoint) = &peer.endpoint {
+                writeln!(f, "Endpoint = {endpoint}")?;
+            }
+            if let Some(keepalive) = peer.persistent_keepalive {
+                writeln!(f, "PersistentKeepalive = {keepalive}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_key(value: &str) -> Result<[u8; 32]> {
+    BASE64
+        .decode(value.as_bytes())
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| anyhow!("invalid key"))
+}
+
+fn parse_ip_list(value: &str) -> Result<Vec<IpCidr>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(parse_allowed_ip)
+        .collect()
+}
+
+fn join(cidrs: &[IpCidr]) -> String {
+    let mut s = String::new();
+    for (i, cidr) in cidrs.iter().enumerate() {
+        if i > 0 {
+            s.push_str(", ");
+        }
+        write!(s, "{cidr}").unwrap();
+    }
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_KEY: &str = "qG8b7LI/s+ezngWpXqj5A7Nj988hbGL+eQ8ePki0iHk=";
+    const CLIENT_KEY: &str = "uJ3o3ghdhTvBBAPZQ8LFhiEOOzM1mPOY1D2P5/nY/Xo=";
+
+    #[test]
+    fn test_parse_and_render() -> Result<()> {
+        let client_public_key =
+            BASE64.encode(PublicKey::from(&StaticSecret::from(parse_key(CLIENT_KEY)?)).as_bytes());
+        let config = format!(
+            "# mitmproxy server
+            [Interface]
+            PrivateKey = {SERVER_KEY}
+            ListenPort = 51821
+            DNS = 10.0.0.53, example.com
+            PostUp = true
+
+            [peer]
+            publickey = {client_public_key}
+            AllowedIPs = 10.0.0.2/32,fd00::2/128
+            Endpoint = 192.0.2.1:51820
+            PersistentKeepalive = off
+            "
+        );
+        let conf = WgQuickConfig::parse(&config)?;
+        assert_eq!(conf.interface.listen_port, Some(51821));
+        assert_eq!(conf.interface.dns, vec!["10.0.0.53", "example.com"]);
+        assert_eq!(conf.peers.len(), 1);
+        assert_eq!(conf.peers[0].allowed_ips.len(), 2);
+        assert_eq!(conf.peers[0].persistent_keepalive, None);
+
+        // rendering and parsing again yields the same config.
+        assert_eq!(WgQuickConfig::parse(&conf.to_string())?, conf);
+
+        let wg_conf = conf.to_wireguard_conf("0.0.0.0".parse::<IpAddr>()?)?;
+        assert_eq!(
+            wg_conf.listen_addrs,
+            vec!["0.0.0.0:51821".parse::<SocketAddr>()?]
+        );
+        assert_eq!(
+            wg_conf.peers[0].endpoint,
+            Some("192.0.2.1:51820".parse::<SocketAddr>()?)
+        );
+
+        let client = conf.client_config(parse_key(CLIENT_KEY)?, "mitm.example:51821")?;
+        assert_eq!(
+            client.to_string(),
+            format!(
+                "[Interface]
+PrivateKey = {CLIENT_KEY}
+Address = 10.0.0.2/32, fd00::2/128
+DNS = 10.0.0.53, example.com
+
+[Peer]
+PublicKey = {}
+AllowedIPs = 0.0.0.0/0, ::/0
+Endpoint = mitm.example:51821
+",
+                BASE64.encode(
+                    PublicKey::from(&StaticSecret::from(parse_key(SERVER_KEY)?)).as_bytes()
+                )
+            )
+        );
+        assert!(conf
+            .client_config(parse_key(SERVER_KEY)?, "mitm.example:51821")
+            .is_err());
+        let mut without_address = conf.clone();
+        without_address.peers[0].allowed_ips.clear();
+        assert!(without_address
+            .client_config(parse_key(CLIENT_KEY)?, "mitm.example:51821")
+            .is_err());
+
+        let client_conf = client.to_wireguard_client_conf()?;
+        assert_eq!(client_conf.endpoint, "mitm.example:51821");
+        assert!(
+            WgQuickConfig::parse(&format!("[Interface]\nPrivateKey = {SERVER_KEY}"))?
+                .to_wireguard_client_conf()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(WgQuickConfig::parse("").is_err());
+        assert!(WgQuickConfig::parse("[Interface]\nPrivateKey = invalid").is_err());
+        assert!(WgQuickConfig::parse(&format!("[Interface]\nPrivateKey = {SERVER_KEY}")).is_ok());
+        assert!(WgQuickConfig::parse(&format!("PrivateKey = {SERVER_KEY}")).is_err());
+        assert!(
+            WgQuickConfig::parse(&format!("[Interface]\nPrivateKey = {SERVER_KEY}\n[Peer]"))
+                .is_err()
+        );
+        assert!(WgQuickConfig::parse(&format!(
+            "[Interface]\nPrivateKey = {SERVER_KEY}\n[Peer]\nPublicKey = {SERVER_KEY}\nEndpoint = foo"
+        ))
+        .is_err());
+        assert!(
+            WgQuickConfig::parse(&format!("[Interface]\nPrivateKey = {SERVER_KEY}\n[Wat]"))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,462 @@
+//! A userspace WireGuard client for testing mitmproxy_rs's WireGuard mode.
+//!
+//! The client runs its own smoltcp network stack on top of a boringtun tunnel, so tests can open
+//! real TCP connections and send UDP datagrams through a WireGuard server and assert on what comes
+//! back, instead of hand-crafting IP packets.
+
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use boringtun::noise::{Tunn, TunnResult};
+use boringtun::x25519::{PublicKey, StaticSecret};
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{Device, DeviceCapabilities, Medium, RxToken, TxToken};
+use smoltcp::socket::{tcp, udp};
+use smoltcp::time::Instant;
+use smoltcp::wire::{HardwareAddress, IpCidr, IpEndpoint};
+use tokio::net::UdpSocket;
+
+const MAX_PACKET_SIZE: usize = 65535;
+
+// boringtun expects its timers to be updated every 250ms.
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+
+const TCP_BUFFER_SIZE: usize = 64 * 1024;
+const UDP_PORT: u16 = 1234;
+
+/// Configuration for a [WireGuardClient].
+pub struct ClientConf {
+    pub private_key: StaticSecret,
+    pub peer_public_key: PublicKey,
+    pub preshared_key: Option<[u8; 32]>,
+    /// The WireGuard server to connect to.
+    pub endpoint: SocketAddr,
+    /// Our address inside the tunnel.
+    pub address: IpAddr,
+}
+
+/// A smoltcp device that buffers packets going into and out of the tunnel.
+#[derive(Default)]
+struct TunnelDevice {
+    rx_queue: VecDeque<Vec<u8>>,
+    tx_queue: VecDeque<Vec<u8>>,
+}
+
+impl Device for TunnelDevice {
+    type RxToken<'a>
+        = TunnelRxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TunnelTxToken<'a>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let buffer = self.rx_queue.pop_front()?;
+        Some((
+            TunnelRxToken { buffer },
+            TunnelTxToken {
+                queue: &mut self.tx_queue,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TunnelTxToken {
+            queue: &mut self.tx_queue,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut capabilities = DeviceCapabilities::default();
+        capabilities.medium = Medium::Ip;
+        capabilities.max_transmission_unit = 1420;
+        capabilities
+    }
+}
+
+struct TunnelRxToken {
+    buffer: Vec<u8>,
+}
+
+impl RxToken for TunnelRxToken {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        f(&self.buffer[..])
+    }
+}
+
+struct TunnelTxToken<'a> {
+    queue: &'a mut VecDeque<Vec<u8>>,
+}
+
+impl TxToken for TunnelTxToken<'_> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut buffer = vec![0; len];
+        let result = f(&mut buffer);
+        self.queue.push_back(buffer);
+        result
+    }
+}
+
+/// A WireGuard client with a userspace network stack.
+///
+/// All operations drive the tunnel and the network stack until they complete,
+/// and fail if they do not complete within [WireGuardClient::timeout].
+pub struct WireGuardClient {
+    socket: UdpSocket,
+    endpoint: SocketAddr,
+    tunnel: Tunn,
+    wg_buf: Vec<u8>,
+    udp_buf: Vec<u8>,
+    last_timer_update: std::time::Instant,
+
+    iface: Interface,
+    device: TunnelDevice,
+    sockets: SocketSet<'static>,
+    udp_handle: SocketHandle,
+    next_port: u16,
+    timeout: Duration,
+}
+
+impl WireGuardClient {
+    /// Connect to a WireGuard server and wait until the handshake has completed.
+    pub async fn connect(conf: ClientConf) -> Result<Self> {
+        let bind_addr: SocketAddr = if conf.endpoint.is_ipv4() {
+            "127.0.0.1:0".parse()?
+        } else {
+            "[::1]:0".parse()?
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(conf.endpoint).await?;
+
+        let tunnel = Tunn::new(
+            conf.private_key,
+            conf.peer_public_key,
+            conf.preshared_key,
+            None,
+            0,
+            None,
+        );
+
+        let mut device = TunnelDevice::default();
+        let mut iface = Interface::new(
+            Config::new(HardwareAddress::Ip),
+            &mut device,
+            Instant::now(),
+        );
+        iface.update_ip_addrs(|ip_addrs| {
+            let prefix_len = if conf.address.is_ipv4() { 32 } else { 128 };
+            ip_addrs
+                .push(IpCidr::new(conf.address.into(), prefix_len))
+                .unwrap();
+        });
+        // Everything is routed through the tunnel. With a layer-3 device, the gateway address
+        // is never used, so we simply use our own.
+        match conf.address {
+            IpAddr::V4(addr) => iface.routes_mut().add_default_ipv4_route(addr).unwrap(),
+            IpAddr::V6(addr) => iface.routes_mut().add_default_ipv6_route(addr).unwrap(),
+        };
+
+        let mut sockets = SocketSet::new(vec![]);
+        let mut udp_socket = udp::Socket::new(
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; 16],
+                vec![0; MAX_PACKET_SIZE],
+            ),
+            udp::PacketBuffer::new(
+                vec![udp::PacketMetadata::EMPTY; 16],
+                vec![0; MAX_PACKET_SIZE],
+            ),
+        );
+        udp_socket.bind(UDP_PORT)?;
+        let udp_handle = sockets.add(udp_socket);
+
+        let mut client = Self {
+            socket,
+            endpoint: conf.endpoint,
+            tunnel,
+            wg_buf: vec![0; MAX_PACKET_SIZE],
+            udp_buf: vec![0; MAX_PACKET_SIZE],
+            last_timer_update: std::time::Instant::now(),
+
+            iface,
+            device,
+            sockets,
+            udp_handle,
+            next_port: 50000,
+            timeout: Duration::from_secs(5),
+        };
+
+        if let TunnResult::WriteToNetwork(buf) = client
+            .tunnel
+            .format_handshake_initiation(&mut client.wg_buf, false)
+        {
+            client.socket.send(buf).await?;
+        }
+        client
+            .wait_for(|client| Ok(client.tunnel.stats().0.map(|_| ())))
+            .await
+            .context("WireGuard handshake did not complete")?;
+
+        Ok(client)
+    }
+
+    /// The time after which operations fail. Defaults to five seconds.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The address of the WireGuard server.
+    pub fn endpoint(&self) -> SocketAddr {
+        self.endpoint
+    }
+
+    /// Open a TCP connection through the tunnel and wait until it is established.
+    pub async fn connect_tcp(&mut self, dst: SocketAddr) -> Result<TcpConnection<'_>> {
+        let mut socket = tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+            tcp::SocketBuffer::new(vec![0; TCP_BUFFER_SIZE]),
+        );
+        let local_port = self.next_port;
+        self.next_port = self.next_port.checked_add(1).unwrap_or(50000);
+        socket.connect(self.iface.context(), IpEndpoint::from(dst), local_port)?;
+        let handle = self.sockets.add(socket);
+
+        let established = self
+            .wait_for(|client| {
+                let socket = client.sockets.get_mut::<tcp::Socket>(handle);
+                match socket.state() {
+                    tcp::State::Established => Ok(Some(())),
+                    tcp::State::SynSent => Ok(None),
+                    state => bail!("TCP connection to {dst} failed ({state})"),
+                }
+            })
+            .await;
+        if let Err(err) = established {
+            self.sockets.remove(handle);
+            return Err(err);
+        }
+
+        Ok(TcpConnection {
+            client: self,
+            handle,
+        })
+    }
+
+    /// Send a UDP datagram through the tunnel.
+    pub async fn send_udp(&mut self, dst: SocketAddr, data: &[u8]) -> Result<()> {
+        self.sockets
+            .get_mut::<udp::Socket>(self.udp_handle)
+            .send_slice(data, IpEndpoint::from(dst))?;
+        self.poll().await
+    }
+
+    /// Wait for a UDP datagram and return its source address and payload.
+    pub async fn recv_udp(&mut self) -> Result<(SocketAddr, Vec<u8>)> {
+        let handle = self.udp_handle;
+        self.wait_for(|client| {
+            let socket = client.sockets.get_mut::<udp::Socket>(handle);
+            if !socket.can_recv() {
+                return Ok(None);
+            }
+            let (data, meta) = socket.recv()?;
+            let src_addr = SocketAddr::new(meta.endpoint.addr.into(), meta.endpoint.port);
+            Ok(Some((src_addr, data.to_vec())))
+        })
+        .await
+        .context("no UDP datagram received")
+    }
+
+    /// Drive the network stack until `f` returns a value, or fail after the configured timeout.
+    async fn wait_for<T>(
+        &mut self,
+        mut f: impl FnMut(&mut Self) -> Result<Option<T>>,
+    ) -> Result<T> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        loop {
+            self.poll().await?;
+            if let Some(value) = f(self)? {
+                // flush any packets triggered by `f`, e.g. window updates.
+                self.poll().await?;
+                return Ok(value);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                bail!("timed out after {:?}", self.timeout);
+            }
+            self.wait(deadline).await?;
+        }
+    }
+
+    /// Process packets queued in the network stack and send them through the tunnel.
+    async fn poll(&mut self) -> Result<()> {
+        if self.last_timer_update.elapsed() >= TIMER_INTERVAL {
+            self.last_timer_update = std::time::Instant::now();
+            if let TunnResult::WriteToNetwork(buf) = self.tunnel.update_timers(&mut self.wg_buf) {
+                self.socket.send(buf).await?;
+            }
+        }
+
+        self.iface
+            .poll(Instant::now(), &mut self.device, &mut self.sockets);
+
+        while let Some(packet) = self.device.tx_queue.pop_front() {
+            match self.tunnel.encapsulate(&packet, &mut self.wg_buf) {
+                TunnResult::WriteToNetwork(buf) => {
+                    self.socket.send(buf).await?;
+                }
+                TunnResult::Done => {}
+                TunnResult::Err(error) => bail!("failed to encapsulate packet: {error:?}"),
+                result => bail!("unexpected encapsulation result: {result:?}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for a datagram from the server or until the network stack needs to be polled again.
+    async fn wait(&mut self, deadline: tokio::time::Instant) -> Result<()> {
+        let delay = self
+            .iface
+            .poll_delay(Instant::now(), &self.sockets)
+            .map(Duration::from)
+            .unwrap_or(TIMER_INTERVAL)
+            .min(TIMER_INTERVAL);
+        let wakeup = (tokio::time::Instant::now() + delay).min(deadline);
+
+        let Ok(received) =
+            tokio::time::timeout_at(wakeup, self.socket.recv(&mut self.udp_buf)).await
+        else {
+            return Ok(());
+        };
+        let len = received.context("UDP recv() failed")?;
+
+        let mut result = self.tunnel.decapsulate(
+            Some(self.endpoint.ip()),
+            &self.udp_buf[..len],
+            &mut self.wg_buf,
+        );
+        while let TunnResult::WriteToNetwork(buf) = result {
+            self.socket.send(buf).await?;
+            result = self.tunnel.decapsulate(None, &[0; 0], &mut self.wg_buf);
+        }
+        match result {
+            TunnResult::WriteToTunnelV4(buf, _) | TunnResult::WriteToTunnelV6(buf, _) => {
+                self.device.rx_queue.push_back(buf.to_vec());
+            }
+            TunnResult::Done => {}
+            TunnResult::Err(error) => bail!("failed to decapsulate datagram: {error:?}"),
+            TunnResult::WriteToNetwork(_) => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+/// A TCP connection through a [WireGuardClient].
+pub struct TcpConnection<'a> {
+    client: &'a mut WireGuardClient,
+    handle: SocketHandle,
+}
+
+impl TcpConnection<'_> {
+    /// Write all data and wait until it has been acknowledged by the server.
+    pub async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        let handle = self.handle;
+        let mut written = 0;
+        self.client
+            .wait_for(|client| {
+                let socket = client.sockets.get_mut::<tcp::Socket>(handle);
+                if !socket.may_send() {
+                    bail!("connection is closed for writing ({})", socket.state());
+                }
+                written += socket.send_slice(&data[written..])?;
+                Ok((written == data.len() && socket.send_queue() == 0).then_some(()))
+            })
+            .await
+    }
+
+    /// Wait for data from the server. Returns an empty vector once the server has closed
+    /// its side of the connection.
+    pub async fn read(&mut self) -> Result<Vec<u8>> {
+        let handle = self.handle;
+        self.client
+            .wait_for(|client| {
+                let socket = client.sockets.get_mut::<tcp::Socket>(handle);
+                if socket.can_recv() {
+                    let mut buf = vec![0; socket.recv_queue()];
+                    let n = socket.recv_slice(&mut buf)?;
+                    buf.truncate(n);
+                    Ok(Some(buf))
+                } else if !socket.may_recv() {
+                    Ok(Some(vec![]))
+                } else {
+                    Ok(None)
+                }
+            })
+            .await
+    }
+
+    /// Read exactly `n` bytes.
+    pub async fn read_exact(&mut self, n: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(n);
+        while buf.len() < n {
+            let data = self.read().await?;
+            if data.is_empty() {
+                bail!("connection closed after {} of {n} bytes", buf.len());
+            }
+            buf.extend(data);
+        }
+        if buf.len() > n {
+            bail!("received {} bytes, expected {n}", buf.len());
+        }
+        Ok(buf)
+    }
+
+    /// Read until the server has closed its side of the connection.
+    pub async fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        loop {
+            let data = self.read().await?;
+            if data.is_empty() {
+                return Ok(buf);
+            }
+            buf.extend(data);
+        }
+    }
+
+    /// Close our side of the connection (half-close). We can still read data from the server.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.client
+            .sockets
+            .get_mut::<tcp::Socket>(self.handle)
+            .close();
+        self.client.poll().await
+    }
+
+    /// See [WireGuardClient::set_timeout].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.client.set_timeout(timeout);
+    }
+
+    /// The current state of the underlying TCP socket.
+    pub fn state(&self) -> tcp::State {
+        self.client.sockets.get::<tcp::Socket>(self.handle).state()
+    }
+}
+
+impl Drop for TcpConnection<'_> {
+    fn drop(&mut self) {
+        self.client.sockets.remove(self.handle);
+    }
+}